@@ -0,0 +1,30 @@
+//! Blocking API wrapping the async [`crate::Mint`] and [`crate::Wallet`].
+//!
+//! All blocking objects drive their async counterparts on one shared single-threaded runtime,
+//! so they must not be used from within another async runtime. Sharing the runtime lets
+//! operations spanning several wallets run concurrently, e.g. [`Wallet::melt_mpp`].
+
+use std::sync::OnceLock;
+
+use tokio::runtime::Runtime;
+
+pub use mint::Mint;
+pub use wallet::Wallet;
+
+mod mint;
+mod wallet;
+
+/// Returns runtime driving wrapped async objects, built on the first use
+///
+/// # Panics
+///
+/// Panics if the runtime cannot be created
+fn runtime() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("build tokio runtime")
+    })
+}
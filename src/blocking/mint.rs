@@ -0,0 +1,112 @@
+use tokio::runtime::Runtime;
+
+use crate::{
+    MintError, MintInfo,
+    cashu::{
+        Amount, BlindSignatures, BlindedMessage, CurrencyUnit, Proof,
+        crypto::PublicKey,
+        types::{
+            AllKeysetInfos, AllKeysets, KeysetChange, MeltOptions, MeltQuote, MintQuote,
+            ProofStateInfo,
+        },
+    },
+};
+
+type Result<T, E = MintError> = std::result::Result<T, E>;
+
+/// Blocking client of remote mint. See [`crate::Mint`] for details.
+pub struct Mint {
+    inner: crate::Mint,
+    rt: &'static Runtime,
+}
+
+impl Mint {
+    pub fn new(url: &str) -> Result<Self> {
+        Ok(Self {
+            inner: crate::Mint::new(url)?,
+            rt: super::runtime(),
+        })
+    }
+
+    pub fn url(&self) -> String {
+        self.inner.url()
+    }
+
+    /// NUT-06: Mint information
+    pub fn get_info(&mut self) -> Result<&MintInfo> {
+        self.rt.block_on(self.inner.get_info())
+    }
+
+    /// NUT-01: Mint public key exchange
+    pub fn get_keys(&mut self) -> Result<&AllKeysets> {
+        self.rt.block_on(self.inner.get_keys())
+    }
+
+    /// NUT-02: Keysets and fees
+    pub fn get_keysets(&mut self) -> Result<&AllKeysetInfos> {
+        self.rt.block_on(self.inner.get_keysets())
+    }
+
+    /// See [`crate::Mint::refresh`]
+    pub fn refresh(&mut self) -> Result<Vec<KeysetChange>> {
+        self.rt.block_on(self.inner.refresh())
+    }
+
+    /// NUT-23: BOLT11
+    pub fn create_mint_quote(
+        &self,
+        amount: Amount,
+        unit: &CurrencyUnit,
+        pubkey: PublicKey,
+    ) -> Result<MintQuote> {
+        self.rt
+            .block_on(self.inner.create_mint_quote(amount, unit, pubkey))
+    }
+
+    pub fn get_mint_quote(&self, quote_id: &str) -> Result<MintQuote> {
+        self.rt.block_on(self.inner.get_mint_quote(quote_id))
+    }
+
+    // NUT-04: Mint tokens
+    pub fn do_minting(
+        &self,
+        quote_id: &str,
+        outputs: &[BlindedMessage],
+        signature: &str,
+    ) -> Result<BlindSignatures> {
+        self.rt
+            .block_on(self.inner.do_minting(quote_id, outputs, signature))
+    }
+
+    // NUT-03: Swap tokens
+    pub fn do_swap(&self, inputs: &[Proof], outputs: &[BlindedMessage]) -> Result<BlindSignatures> {
+        self.rt.block_on(self.inner.do_swap(inputs, outputs))
+    }
+
+    /// NUT-23: BOLT11
+    pub fn create_melt_quote(
+        &self,
+        invoice: &str,
+        unit: &CurrencyUnit,
+        options: Option<MeltOptions>,
+    ) -> Result<MeltQuote> {
+        self.rt
+            .block_on(self.inner.create_melt_quote(invoice, unit, options))
+    }
+
+    // NUT-05: Melt tokens
+    pub fn do_melting(
+        &self,
+        quote_id: &str,
+        proofs: &[Proof],
+        blank_outputs: &[BlindedMessage],
+    ) -> Result<MeltQuote> {
+        self.rt
+            .block_on(self.inner.do_melting(quote_id, proofs, blank_outputs))
+    }
+
+    /// NUT-07: Token state check
+    pub fn check_state(&self, ys: &[PublicKey]) -> Result<Vec<ProofStateInfo>> {
+        self.rt.block_on(self.inner.check_state(ys))
+    }
+}
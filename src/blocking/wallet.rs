@@ -0,0 +1,225 @@
+use std::collections::BTreeMap;
+
+use tokio::runtime::Runtime;
+
+use crate::{
+    MintInfo, SpendPreview, WalletError,
+    bolt11::Invoice,
+    cashu::{
+        Amount, CurrencyUnit, Proofs, TokenV4,
+        types::{AllKeysetInfos, AllKeysets, KeysetChange, MeltQuote, MintQuote},
+    },
+    coin_selection::{CoinSelection, CoinSelector},
+    store::{HistoryEntry, PendingMelt, StoreKind, WalletStore},
+};
+
+type Result<T, E = WalletError> = std::result::Result<T, E>;
+
+/// Blocking wallet. See [`crate::Wallet`] for details.
+pub struct Wallet {
+    inner: crate::Wallet,
+    rt: &'static Runtime,
+}
+
+impl Wallet {
+    pub fn names_list() -> Result<Vec<String>> {
+        crate::Wallet::names_list()
+    }
+
+    pub fn create(name: &str, mint: &str, password: &str, store_kind: StoreKind) -> Result<Self> {
+        Ok(Self {
+            inner: crate::Wallet::create(name, mint, password, store_kind)?,
+            rt: super::runtime(),
+        })
+    }
+
+    pub fn open(name: &str, password: &str) -> Result<Self> {
+        let rt = super::runtime();
+        let inner = rt.block_on(crate::Wallet::open(name, password))?;
+        Ok(Self { inner, rt })
+    }
+
+    /// See [`crate::Wallet::with_store`]
+    pub fn with_store(name: &str, store: Box<dyn WalletStore>) -> Result<Self> {
+        let rt = super::runtime();
+        let inner = rt.block_on(crate::Wallet::with_store(name, store))?;
+        Ok(Self { inner, rt })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.inner.name
+    }
+
+    pub fn mint_url(&self) -> String {
+        self.inner.mint_url()
+    }
+
+    pub fn coin_selection(&self) -> Result<CoinSelection> {
+        self.inner.coin_selection()
+    }
+
+    pub fn set_coin_selection(&mut self, strategy: CoinSelection) -> Result<()> {
+        self.inner.set_coin_selection(strategy)
+    }
+
+    pub fn proofs_per_denomination(&self) -> Result<u32> {
+        self.inner.proofs_per_denomination()
+    }
+
+    pub fn set_proofs_per_denomination(&mut self, count: u32) -> Result<()> {
+        self.inner.set_proofs_per_denomination(count)
+    }
+
+    pub fn set_coin_selector(&mut self, selector: Box<dyn CoinSelector>) -> Box<dyn CoinSelector> {
+        self.inner.set_coin_selector(selector)
+    }
+
+    pub fn mint_info(&mut self) -> Result<MintInfo> {
+        self.rt.block_on(self.inner.mint_info())
+    }
+
+    pub fn mint_keys(&mut self) -> Result<AllKeysets> {
+        self.rt.block_on(self.inner.mint_keys())
+    }
+
+    pub fn mint_keysets(&mut self, only_active: bool) -> Result<AllKeysetInfos> {
+        self.rt.block_on(self.inner.mint_keysets(only_active))
+    }
+
+    /// See [`crate::Wallet::refresh`]
+    pub fn refresh(&mut self) -> Result<Vec<KeysetChange>> {
+        self.rt.block_on(self.inner.refresh())
+    }
+
+    pub fn balance(&self, unit: &CurrencyUnit) -> Result<Amount> {
+        self.inner.balance(unit)
+    }
+
+    /// See [`crate::Wallet::balances`]
+    pub fn balances(&self) -> Result<BTreeMap<CurrencyUnit, Amount>> {
+        self.inner.balances()
+    }
+
+    /// See [`crate::Wallet::pending_balance`]
+    pub fn pending_balance(&self, unit: &CurrencyUnit) -> Result<Amount> {
+        self.inner.pending_balance(unit)
+    }
+
+    pub fn proofs(&self) -> Result<Proofs> {
+        self.inner.proofs()
+    }
+
+    /// See [`crate::Wallet::unit_proofs`]
+    pub fn unit_proofs(&self, unit: &CurrencyUnit) -> Result<Proofs> {
+        self.inner.unit_proofs(unit)
+    }
+
+    pub fn mint_quotes(&self) -> Result<Vec<MintQuote>> {
+        self.inner.mint_quotes()
+    }
+
+    pub fn pending_melts(&self) -> Result<Vec<PendingMelt>> {
+        self.inner.pending_melts()
+    }
+
+    pub fn history(&self) -> Result<Vec<HistoryEntry>> {
+        self.inner.history()
+    }
+
+    pub fn mint_tokens(&mut self, amount: Amount, unit: &CurrencyUnit) -> Result<Vec<Amount>> {
+        self.rt.block_on(self.inner.mint_tokens(amount, unit))
+    }
+
+    pub fn melt_tokens(
+        &mut self,
+        invoice: &str,
+        amount: Option<Amount>,
+        unit: &CurrencyUnit,
+    ) -> Result<MeltQuote> {
+        self.rt
+            .block_on(self.inner.melt_tokens(invoice, amount, unit))
+    }
+
+    /// See [`crate::Wallet::melt_status`]
+    pub fn melt_status(&mut self, quote_id: &str) -> Result<MeltQuote> {
+        self.rt.block_on(self.inner.melt_status(quote_id))
+    }
+
+    /// See [`crate::Wallet::melt_mpp`]
+    pub fn melt_mpp(
+        wallets: &mut [&mut Wallet],
+        invoice: &str,
+    ) -> Result<Vec<(String, Result<MeltQuote>)>> {
+        let mut inner = wallets
+            .iter_mut()
+            .map(|wallet| &mut wallet.inner)
+            .collect::<Vec<_>>();
+        super::runtime().block_on(crate::Wallet::melt_mpp(&mut inner, invoice))
+    }
+
+    /// See [`crate::Wallet::create_mpp_quotes`]
+    pub fn create_mpp_quotes(
+        wallets: &mut [&mut Wallet],
+        invoice: &str,
+    ) -> Result<Vec<Option<MeltQuote>>> {
+        let mut inner = wallets
+            .iter_mut()
+            .map(|wallet| &mut wallet.inner)
+            .collect::<Vec<_>>();
+        super::runtime().block_on(crate::Wallet::create_mpp_quotes(&mut inner, invoice))
+    }
+
+    /// See [`crate::Wallet::validate_invoice`]
+    pub fn validate_invoice(invoice: &str, amount: Option<Amount>) -> Result<Invoice> {
+        crate::Wallet::validate_invoice(invoice, amount)
+    }
+
+    /// See [`crate::Wallet::preview_melt`]
+    pub fn preview_melt(
+        &mut self,
+        invoice: &str,
+        amount: Option<Amount>,
+        unit: &CurrencyUnit,
+    ) -> Result<SpendPreview> {
+        self.rt
+            .block_on(self.inner.preview_melt(invoice, amount, unit))
+    }
+
+    /// See [`crate::Wallet::preview_send`]
+    pub fn preview_send(&mut self, amount: Amount, unit: &CurrencyUnit) -> Result<SpendPreview> {
+        self.rt.block_on(self.inner.preview_send(amount, unit))
+    }
+
+    pub fn prepare_cashu_token(
+        &mut self,
+        amount: Amount,
+        unit: &CurrencyUnit,
+    ) -> Result<(TokenV4, Amount)> {
+        self.rt
+            .block_on(self.inner.prepare_cashu_token(amount, unit))
+    }
+
+    /// See [`crate::Wallet::estimate_rotation`]
+    pub fn estimate_rotation(&mut self, unit: &CurrencyUnit) -> Result<(Amount, Amount)> {
+        self.rt.block_on(self.inner.estimate_rotation(unit))
+    }
+
+    /// See [`crate::Wallet::rotate_proofs`]
+    pub fn rotate_proofs(&mut self, unit: &CurrencyUnit) -> Result<(Amount, Amount)> {
+        self.rt.block_on(self.inner.rotate_proofs(unit))
+    }
+
+    /// See [`crate::Wallet::estimate_rebalance`]
+    pub fn estimate_rebalance(&mut self, unit: &CurrencyUnit) -> Result<(Amount, Amount)> {
+        self.rt.block_on(self.inner.estimate_rebalance(unit))
+    }
+
+    /// See [`crate::Wallet::rebalance_proofs`]
+    pub fn rebalance_proofs(&mut self, unit: &CurrencyUnit) -> Result<(Amount, Amount)> {
+        self.rt.block_on(self.inner.rebalance_proofs(unit))
+    }
+
+    pub fn receive_via_cashu_token(&mut self, token: TokenV4) -> Result<(Amount, Amount)> {
+        self.rt.block_on(self.inner.receive_via_cashu_token(token))
+    }
+}
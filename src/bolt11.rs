@@ -0,0 +1,393 @@
+//! Decoding of [BOLT11](https://github.com/lightning/bolts/blob/master/11-payment-encoding.md) Lightning invoices.
+//!
+//! Only fields needed by the wallet are extracted, the signature is not verified.
+
+use std::str::FromStr;
+
+use anyhow::{Context, Result, anyhow, bail};
+use secp256k1::hashes::{Hash, sha256};
+
+const CHARSET: &str = "qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+/// Length of timestamp in 5-bit words
+const TIMESTAMP_LEN: usize = 7;
+/// Length of signature (65 bytes) in 5-bit words
+const SIGNATURE_LEN: usize = 104;
+/// Expiry in seconds used when invoice does not specify it
+const DEFAULT_EXPIRY: u64 = 3600;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Network {
+    Bitcoin,
+    Testnet,
+    Signet,
+    Regtest,
+}
+
+impl std::fmt::Display for Network {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Bitcoin => write!(f, "bitcoin"),
+            Self::Testnet => write!(f, "testnet"),
+            Self::Signet => write!(f, "signet"),
+            Self::Regtest => write!(f, "regtest"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Invoice {
+    pub network: Network,
+    /// Amount in millisatoshis, `None` for invoices without amount
+    pub amount_msat: Option<u64>,
+    /// Unix timestamp of invoice creation
+    pub timestamp: u64,
+    pub payment_hash: [u8; 32],
+    pub description: Option<String>,
+    /// Seconds after `timestamp` when invoice expires
+    pub expiry: u64,
+}
+
+impl Invoice {
+    /// Amount in sats, rounded up
+    pub fn amount_sat(&self) -> Option<u64> {
+        self.amount_msat.map(|msat| msat.div_ceil(1000))
+    }
+
+    /// Unix timestamp of invoice expiry
+    pub fn expires_at(&self) -> u64 {
+        self.timestamp.saturating_add(self.expiry)
+    }
+
+    pub fn is_expired(&self) -> bool {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+
+        self.expires_at() <= now
+    }
+
+    pub fn payment_hash_hex(&self) -> String {
+        hex::encode(self.payment_hash)
+    }
+
+    /// Checks that SHA256 of hex encoded `preimage` is the payment hash
+    pub fn verify_preimage(&self, preimage: &str) -> bool {
+        hex::decode(preimage)
+            .is_ok_and(|p| sha256::Hash::hash(&p).to_byte_array() == self.payment_hash)
+    }
+}
+
+impl FromStr for Invoice {
+    type Err = anyhow::Error;
+
+    fn from_str(invoice: &str) -> std::result::Result<Self, Self::Err> {
+        let invoice = invoice.trim();
+        if invoice.chars().any(|c| c.is_ascii_uppercase())
+            && invoice.chars().any(|c| c.is_ascii_lowercase())
+        {
+            bail!("Invoice must not use mixed case");
+        }
+        let invoice = invoice.to_ascii_lowercase();
+        let invoice = invoice.strip_prefix("lightning:").unwrap_or(&invoice);
+
+        let (hrp, words) = bech32_decode(invoice)?;
+        let (network, amount_msat) = parse_hrp(hrp)?;
+
+        if words.len() < TIMESTAMP_LEN + SIGNATURE_LEN {
+            bail!("Invoice is too short");
+        }
+        let words = &words[..words.len() - SIGNATURE_LEN];
+
+        let timestamp = words_to_u64(&words[..TIMESTAMP_LEN])?;
+
+        let mut payment_hash = None;
+        let mut description = None;
+        let mut expiry = DEFAULT_EXPIRY;
+
+        // tagged fields: type (1 word), data length (2 words), data
+        let mut fields = &words[TIMESTAMP_LEN..];
+        while !fields.is_empty() {
+            if fields.len() < 3 {
+                bail!("Truncated tagged field");
+            }
+            let tag = CHARSET.as_bytes()[fields[0] as usize];
+            let len = ((fields[1] as usize) << 5) | fields[2] as usize;
+            let data = fields
+                .get(3..3 + len)
+                .ok_or_else(|| anyhow!("Truncated tagged field '{}'", tag as char))?;
+
+            match tag {
+                // fields with unexpected length must be skipped
+                b'p' if len == 52 => {
+                    let hash = words_to_bytes(data);
+                    payment_hash = Some(hash[..32].try_into().expect("52 words hold 32 bytes"));
+                }
+                b'd' => {
+                    description =
+                        Some(String::from_utf8(words_to_bytes(data)).context("description")?);
+                }
+                b'x' => expiry = words_to_u64(data).context("expiry")?,
+                _ => {}
+            }
+
+            fields = &fields[3 + len..];
+        }
+
+        Ok(Self {
+            network,
+            amount_msat,
+            timestamp,
+            payment_hash: payment_hash.ok_or_else(|| anyhow!("Invoice has no payment hash"))?,
+            description,
+            expiry,
+        })
+    }
+}
+
+/// Returns human readable part and data words without checksum
+fn bech32_decode(s: &str) -> Result<(&str, Vec<u8>)> {
+    let pos = s
+        .rfind('1')
+        .ok_or_else(|| anyhow!("Missing bech32 separator"))?;
+    let (hrp, data) = (&s[..pos], &s[pos + 1..]);
+    if hrp.is_empty() || data.len() < 6 {
+        bail!("Invalid bech32 string");
+    }
+
+    let mut words = data
+        .chars()
+        .map(|c| {
+            CHARSET
+                .find(c)
+                .map(|i| i as u8)
+                .ok_or_else(|| anyhow!("Invalid bech32 character '{}'", c))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut values = hrp.bytes().map(|b| b >> 5).collect::<Vec<_>>();
+    values.push(0);
+    values.extend(hrp.bytes().map(|b| b & 31));
+    values.extend(&words);
+    if polymod(&values) != 1 {
+        bail!("Invalid bech32 checksum");
+    }
+
+    words.truncate(words.len() - 6);
+    Ok((hrp, words))
+}
+
+fn polymod(values: &[u8]) -> u32 {
+    const GENERATOR: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+
+    let mut chk = 1u32;
+    for v in values {
+        let b = chk >> 25;
+        chk = ((chk & 0x1ffffff) << 5) ^ *v as u32;
+        for (i, g) in GENERATOR.iter().enumerate() {
+            if (b >> i) & 1 == 1 {
+                chk ^= g;
+            }
+        }
+    }
+    chk
+}
+
+/// Returns network and amount in msat encoded in human readable part, e.g. `lnbc2500u`
+fn parse_hrp(hrp: &str) -> Result<(Network, Option<u64>)> {
+    let rest = hrp
+        .strip_prefix("ln")
+        .ok_or_else(|| anyhow!("Not a Lightning invoice"))?;
+
+    // longer prefixes first
+    let (network, amount) = [
+        ("bcrt", Network::Regtest),
+        ("bc", Network::Bitcoin),
+        ("tbs", Network::Signet),
+        ("tb", Network::Testnet),
+    ]
+    .iter()
+    .find_map(|(prefix, network)| rest.strip_prefix(prefix).map(|amount| (*network, amount)))
+    .ok_or_else(|| anyhow!("Unknown network in '{}'", hrp))?;
+
+    if amount.is_empty() {
+        return Ok((network, None));
+    }
+
+    // amount is in BTC, multiplier is in tenths of msat to handle pico-bitcoin
+    let (digits, multiplier) = match amount.as_bytes()[amount.len() - 1] {
+        b'm' => (&amount[..amount.len() - 1], 1_000_000_000),
+        b'u' => (&amount[..amount.len() - 1], 1_000_000),
+        b'n' => (&amount[..amount.len() - 1], 1_000),
+        b'p' => (&amount[..amount.len() - 1], 1),
+        _ => (amount, 1_000_000_000_000),
+    };
+
+    // `parse` would accept a sign
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        bail!("invalid amount '{}'", amount);
+    }
+    let value = digits
+        .parse::<u64>()
+        .with_context(|| format!("invalid amount '{}'", amount))?;
+    let amount_tenths_msat = value
+        .checked_mul(multiplier)
+        .ok_or_else(|| anyhow!("Amount '{}' is too large", amount))?;
+    if !amount_tenths_msat.is_multiple_of(10) {
+        bail!("Amount '{}' is not a whole number of millisatoshis", amount);
+    }
+
+    Ok((network, Some(amount_tenths_msat / 10)))
+}
+
+fn words_to_u64(words: &[u8]) -> Result<u64> {
+    if words.len() > 12 {
+        bail!("Number is too large");
+    }
+    Ok(words.iter().fold(0, |acc, w| (acc << 5) | *w as u64))
+}
+
+/// Converts 5-bit words to bytes, incomplete trailing bits are padding
+fn words_to_bytes(words: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![];
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for w in words {
+        acc = (acc << 5) | *w as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((acc >> bits) as u8);
+            acc &= (1 << bits) - 1;
+        }
+    }
+    bytes
+}
+
+/// Encodes unsigned invoice with `amount` part of human readable part (e.g. `2500u`) for tests
+#[cfg(test)]
+pub(crate) fn test_invoice(
+    amount: &str,
+    timestamp: u64,
+    payment_hash: [u8; 32],
+    expiry: u64,
+) -> String {
+    let to_words =
+        |value: u64, len: usize| (0..len).rev().map(move |i| ((value >> (5 * i)) & 31) as u8);
+    let tag = |c: char| CHARSET.find(c).expect("tag is in charset") as u8;
+
+    let mut words = to_words(timestamp, TIMESTAMP_LEN).collect::<Vec<_>>();
+    // 256 bits of payment hash padded to 52 words
+    words.extend([tag('p'), 1, 20]);
+    let (mut acc, mut bits) = (0u32, 0);
+    for b in payment_hash {
+        acc = (acc << 8) | b as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            words.push(((acc >> bits) & 31) as u8);
+        }
+        acc &= (1 << bits) - 1;
+    }
+    words.push((acc << (5 - bits)) as u8);
+    words.extend([tag('x'), 0, 7]);
+    words.extend(to_words(expiry, 7));
+    words.extend([0; SIGNATURE_LEN]);
+
+    let hrp = format!("lnbc{}", amount);
+    let mut values = hrp.bytes().map(|b| b >> 5).collect::<Vec<_>>();
+    values.push(0);
+    values.extend(hrp.bytes().map(|b| b & 31));
+    values.extend(&words);
+    values.extend([0; 6]);
+    let checksum = polymod(&values) ^ 1;
+    words.extend(to_words(checksum as u64, 6));
+
+    let data = words
+        .iter()
+        .map(|w| CHARSET.as_bytes()[*w as usize] as char)
+        .collect::<String>();
+    format!("{}1{}", hrp, data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // test vectors from BOLT11 specification
+    const DONATION: &str = "lnbc1pvjluezsp5zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zygspp5qqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqypqdpl2pkx2ctnv5sxxmmwwd5kgetjypeh2ursdae8g6twvus8g6rfwvs8qun0dfjkxaq9qrsgq357wnc5r2ueh7ck6q93dj32dlqnls087fxdwk8qakdyafkq3yap9us6v52vjjsrvywa6rt52cm9r9zqt8r2t7mlcwspyetp5h2tztugp9lfyql";
+    const COFFEE: &str = "lnbc2500u1pvjluezsp5zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zygspp5qqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqypqdq5xysxxatsyp3k7enxv4jsxqzpu9qrsgquk0rl77nj30yxdy8j9vdx85fkpmdla2087ne0xh8nhedh8w27kyke0lp53ut353s06fv3qfegext0eh0ymjpf39tuven09sam30g4vgpfna3rh";
+
+    #[test]
+    fn test_decode_invoice() {
+        let invoice = Invoice::from_str(DONATION).unwrap();
+        assert_eq!(invoice.network, Network::Bitcoin);
+        assert_eq!(invoice.amount_msat, None);
+        assert_eq!(invoice.timestamp, 1496314658);
+        assert_eq!(
+            invoice.payment_hash_hex(),
+            "0001020304050607080900010203040506070809000102030405060708090102"
+        );
+        assert_eq!(
+            invoice.description.as_deref(),
+            Some("Please consider supporting this project")
+        );
+        assert_eq!(invoice.expiry, DEFAULT_EXPIRY);
+        assert!(invoice.is_expired());
+
+        let invoice = Invoice::from_str(&COFFEE.to_uppercase()).unwrap();
+        assert_eq!(invoice.amount_msat, Some(250_000_000));
+        assert_eq!(invoice.amount_sat(), Some(250_000));
+        assert_eq!(invoice.description.as_deref(), Some("1 cup coffee"));
+        assert_eq!(invoice.expiry, 60);
+        assert_eq!(invoice.expires_at(), 1496314658 + 60);
+
+        // preimage of the test vector is unknown, use hash of zero bytes instead
+        let mut invoice = invoice;
+        invoice.payment_hash = sha256::Hash::hash(&[0; 32]).to_byte_array();
+        assert!(invoice.verify_preimage(&"00".repeat(32)));
+        assert!(!invoice.verify_preimage(&"01".repeat(32)));
+        assert!(!invoice.verify_preimage("not hex"));
+
+        let hash = sha256::Hash::hash(&[1; 32]).to_byte_array();
+        let invoice = Invoice::from_str(&test_invoice("100n", 1700000000, hash, 600)).unwrap();
+        assert_eq!(invoice.amount_sat(), Some(10));
+        assert_eq!(invoice.expires_at(), 1700000600);
+        assert!(invoice.verify_preimage(&"01".repeat(32)));
+
+        // corrupted checksum
+        let mut corrupted = COFFEE.to_string();
+        corrupted.replace_range(20..21, "x");
+        assert!(Invoice::from_str(&corrupted).is_err());
+    }
+
+    #[test]
+    fn test_parse_hrp() {
+        assert_eq!(parse_hrp("lnbc").unwrap(), (Network::Bitcoin, None));
+        assert_eq!(
+            parse_hrp("lnbc20m").unwrap(),
+            (Network::Bitcoin, Some(2_000_000_000))
+        );
+        assert_eq!(
+            parse_hrp("lntb1u").unwrap(),
+            (Network::Testnet, Some(100_000))
+        );
+        assert_eq!(
+            parse_hrp("lntbs10n").unwrap(),
+            (Network::Signet, Some(1_000))
+        );
+        assert_eq!(parse_hrp("lnbcrt10p").unwrap(), (Network::Regtest, Some(1)));
+        assert_eq!(
+            parse_hrp("lnbc1").unwrap(),
+            (Network::Bitcoin, Some(100_000_000_000))
+        );
+        assert!(parse_hrp("lnbc1p").is_err()); // fraction of millisatoshi
+        assert!(parse_hrp("lnbc+10u").is_err());
+        assert!(parse_hrp("lnbc-10u").is_err());
+        assert!(parse_hrp("lnbc u").is_err());
+        assert!(parse_hrp("lnbcu").is_err());
+        assert!(parse_hrp("lnxy10u").is_err());
+        assert!(parse_hrp("bc10u").is_err());
+    }
+}
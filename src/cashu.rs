@@ -1,4 +1,4 @@
-use std::str::FromStr;
+use std::{collections::HashMap, str::FromStr};
 
 use anyhow::{Context, Result, anyhow, bail};
 use base64::{
@@ -9,44 +9,90 @@ use base64::{
 };
 use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
-use serde_bytes::ByteBuf;
 
 use crypto::{PublicKey, Secret, SecretKey, hash_to_curve};
 
 use crate::cashu::{
-    crypto::hash_e,
+    crypto::verify_dleq,
     types::{AllKeysets, AmountKeys},
 };
+pub use amount::{Amount, CurrencyUnit};
+pub use keyset_id::{KeysetId, ShortKeysetId};
 
+mod amount;
 pub mod crypto;
+mod keyset_id;
 pub mod types;
 
 pub type Proofs = Vec<Proof>;
 
+/// Smallest batch of proofs worth verifying in its own thread
+const MIN_DLEQ_BATCH: usize = 64;
+
 pub trait ProofsMethods {
     fn validate_dleq(&self, all_keysets: &AllKeysets) -> Result<bool>;
+
+    /// Returns sum of proof amounts, fails if it overflows
+    fn total_amount(&self) -> Result<Amount>;
 }
 
-impl ProofsMethods for Proofs {
+impl ProofsMethods for [Proof] {
+    /// Verifies DLEQ proofs in batches running in parallel threads, every batch negates
+    /// each mint key only once
     fn validate_dleq(&self, all_keysets: &AllKeysets) -> Result<bool> {
-        for proof in self {
-            let proof_keyset_id = &proof.keyset_id;
-
-            let keys = all_keysets
-                .clone()
-                .by_id(proof_keyset_id)
-                .ok_or_else(|| anyhow!("Mint error: keyset {} does not exist", proof_keyset_id))?
-                .keys;
+        let keysets: HashMap<_, _> = all_keysets.iter().map(|ks| (&ks.id, &ks.keys)).collect();
+
+        // unknown keysets fail before any verification
+        let proofs = self
+            .iter()
+            .map(|proof| {
+                let keys = keysets.get(&proof.keyset_id).ok_or_else(|| {
+                    anyhow!("Mint error: keyset {} does not exist", proof.keyset_id)
+                })?;
+                Ok((proof, *keys))
+            })
+            .collect::<Result<Vec<_>>>()?;
 
-            proof.validate_dleq(&keys)?;
-        }
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let batch_size = proofs.len().div_ceil(threads).max(MIN_DLEQ_BATCH);
+        verify_dleq_in_batches(&proofs, batch_size)?;
         Ok(true)
     }
+
+    fn total_amount(&self) -> Result<Amount> {
+        Amount::try_sum(self.iter().map(|p| p.amount))
+    }
+}
+
+/// Verifies DLEQ proofs of `proofs` signed by `keys` in batches of `batch_size`, each in its own thread
+fn verify_dleq_in_batches(proofs: &[(&Proof, &AmountKeys)], batch_size: usize) -> Result<()> {
+    let verify_batch = |batch: &[(&Proof, &AmountKeys)]| {
+        let mut neg_keys = NegatedKeys::default();
+        batch
+            .iter()
+            .try_for_each(|(proof, keys)| proof.verify_dleq(keys, &mut neg_keys))
+    };
+
+    if proofs.len() <= batch_size {
+        return verify_batch(proofs);
+    }
+
+    std::thread::scope(|s| {
+        let handles = proofs
+            .chunks(batch_size)
+            .map(|batch| s.spawn(move || verify_batch(batch)))
+            .collect::<Vec<_>>();
+        handles.into_iter().try_for_each(|handle| {
+            handle
+                .join()
+                .map_err(|_| anyhow!("DLEQ verification thread panicked"))?
+        })
+    })
 }
 
 /// Blinded secret message B_
 #[derive(Debug, Clone, Serialize)]
-pub struct BlindedSecret(String);
+pub struct BlindedSecret(PublicKey);
 
 impl BlindedSecret {
     pub fn from_bytes(secret_msg: &[u8]) -> Result<(Self, SecretKey)> {
@@ -61,7 +107,7 @@ impl BlindedSecret {
         // Y + rG
         let b_ = y.combine(&blinding_factor.public_key())?;
 
-        Ok(BlindedSecret(b_.to_string()))
+        Ok(BlindedSecret(b_))
     }
 }
 
@@ -73,17 +119,16 @@ impl std::fmt::Display for BlindedSecret {
 
 /// C_ blind signature (on B_)
 #[derive(Debug, Clone, Deserialize)]
-pub struct BlindedKey(String);
+pub struct BlindedKey(PublicKey);
 
 impl BlindedKey {
-    pub fn unblind(self, blinding_factor: &SecretKey, pubkey: &PublicKey) -> Result<PublicKey> {
-        let c_ = PublicKey::from_hex(self.0)?;
+    pub fn unblind(&self, blinding_factor: &SecretKey, pubkey: &PublicKey) -> Result<PublicKey> {
         let r = blinding_factor;
 
         // rK
-        let rk = pubkey.to_owned().mul_tweak(r)?;
+        let rk = pubkey.mul_tweak(r)?;
         // C = C_ - rK
-        let c = c_.combine(&rk.negate())?;
+        let c = self.0.combine(&rk.negate())?;
 
         Ok(c)
     }
@@ -99,20 +144,20 @@ impl std::fmt::Display for BlindedKey {
 /// A BlindedMessage is also called an _output_.
 #[derive(Debug, Clone, Serialize)]
 pub struct BlindedMessage {
-    amount: u64,
+    amount: Amount,
     /// requested keyset ID from which we expect a signature
     #[serde(rename = "id")]
-    keyset_id: String,
+    keyset_id: KeysetId,
     /// blinded secret message generated by Alice
     #[serde(rename = "B_")]
     b_: BlindedSecret,
 }
 
 impl BlindedMessage {
-    pub fn new(amount: u64, keyset_id: &str, b_: BlindedSecret) -> Self {
+    pub fn new(amount: Amount, keyset_id: &KeysetId, b_: BlindedSecret) -> Self {
         Self {
             amount,
-            keyset_id: keyset_id.to_owned(),
+            keyset_id: *keyset_id,
             b_,
         }
     }
@@ -122,10 +167,10 @@ impl BlindedMessage {
 /// A BlindSignature is also called a _promise_.
 #[derive(Debug, Deserialize)]
 pub struct BlindSignature {
-    pub amount: u64,
+    pub amount: Amount,
     /// keyset ID of the mint keys that signed the token
     #[serde(rename = "id")]
-    keyset_id: String,
+    keyset_id: KeysetId,
     /// blinded signature on the secret message B_ sent in the previous step
     #[serde(rename = "C_")]
     c_: BlindedKey,
@@ -140,26 +185,20 @@ impl BlindSignature {
         amount_pubkey: &PublicKey,
         secret: &Secret,
     ) -> Result<Proof> {
-        let c_ = self.c_.clone();
-
-        let proof_dleq = if let Some(promise_dleq) = self.dleq.clone() {
-            Some(DleqProof {
-                e: promise_dleq.e,
-                s: promise_dleq.s,
-                r: r.display_secret(),
-            })
-        } else {
-            None
-        };
+        let proof_dleq = self.dleq.clone().map(|promise_dleq| DleqProof {
+            e: promise_dleq.e,
+            s: promise_dleq.s,
+            r: r.clone(),
+        });
 
         Ok(Proof {
             amount: self.amount,
-            keyset_id: self.keyset_id.clone(),
-            secret: secret.to_string(),
-            c: c_
+            keyset_id: self.keyset_id,
+            secret: secret.clone(),
+            c: self
+                .c_
                 .unblind(r, amount_pubkey)
-                .context("unblind signature")?
-                .to_string(),
+                .context("unblind signature")?,
             dleq: proof_dleq,
         })
     }
@@ -168,8 +207,8 @@ impl BlindSignature {
 /// Discrete Log Equality (DLEQ) proof (NUT-12). Mint returns the DLEQ proof for a mint or swap operation.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct Dleq {
-    e: String,
-    s: String,
+    e: SecretKey,
+    s: SecretKey,
 }
 
 #[derive(Debug, Deserialize)]
@@ -191,40 +230,22 @@ impl BlindSignatures {
             bail!("Returned signatures count does not match sent blinded messages count");
         }
 
-        for (i, signature) in self.signatures.iter().enumerate() {
+        let mut neg_keys = NegatedKeys::default();
+
+        for (signature, output) in self.signatures.iter().zip(outputs) {
             if let Some(dleq) = &signature.dleq {
-                let amount_key = active_keys
+                let k = active_keys
                     .get(&signature.amount)
                     .ok_or_else(|| anyhow!("Mint error: key for amount does not exist"))?;
 
-                let k = PublicKey::from_hex(amount_key).context("K pubkey")?;
-
-                let bm = &outputs[i];
-                let b_ = PublicKey::from_hex(bm.b_.to_string()).context("B' pubkey")?;
-
-                let c_ = PublicKey::from_hex(signature.c_.to_string()).context("C' pubkey")?;
-
-                let e = SecretKey::from_hex(&dleq.e).context("'e' secret key")?;
-                let s = SecretKey::from_hex(&dleq.s).context("'s' secret key")?;
-
-                // R1 = s*G - e*K = S - e*K
-                let r1 = &s.public_key().combine(&k.mul_tweak(&e)?.negate())?.to_hex();
-
-                // R2 = s*B' - e*C'
-                let r2 = &b_
-                    .mul_tweak(&s)?
-                    .combine(&c_.mul_tweak(&e)?.negate())?
-                    .to_hex();
-
-                let computed_e = hash_e(r1, r2, &k.to_hex(), &c_.to_string()).context("hash_e")?;
-
-                if dleq.e != computed_e {
-                    bail!(
-                        "DLEQ validation mismatch! Expected {}; got {}",
-                        dleq.e,
-                        computed_e,
-                    )
-                }
+                verify_dleq(
+                    &dleq.e,
+                    &dleq.s,
+                    k,
+                    &neg_keys.get(k),
+                    &output.b_.0,
+                    &signature.c_.0,
+                )?;
             }
         }
 
@@ -232,27 +253,46 @@ impl BlindSignatures {
     }
 }
 
+/// Negated mint keys, computed only once per key when verifying DLEQ proofs in batch
+#[derive(Default)]
+struct NegatedKeys(HashMap<PublicKey, PublicKey>);
+
+impl NegatedKeys {
+    fn get(&mut self, k: &PublicKey) -> PublicKey {
+        *self.0.entry(*k).or_insert_with(|| k.negate())
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Proof {
-    pub amount: u64,
+    pub amount: Amount,
     /// keyset ID of the mint keys that signed the token
     #[serde(rename = "id")]
-    pub keyset_id: String,
+    pub keyset_id: KeysetId,
     /// secret message
-    secret: String,
+    secret: Secret,
     /// unblinded signature on secret
     #[serde(rename = "C")]
-    c: String,
+    c: PublicKey,
     #[serde(skip_serializing_if = "Option::is_none")]
     dleq: Option<DleqProof>,
 }
 
 impl Proof {
+    pub fn secret(&self) -> &Secret {
+        &self.secret
+    }
+
     pub fn remove_dleq(&mut self) {
         self.dleq = None
     }
 
     pub fn validate_dleq(&self, keys: &AmountKeys) -> Result<bool> {
+        self.verify_dleq(keys, &mut NegatedKeys::default())?;
+        Ok(true)
+    }
+
+    fn verify_dleq(&self, keys: &AmountKeys, neg_keys: &mut NegatedKeys) -> Result<()> {
         if let Some(dleq) = &self.dleq {
             // Y = hash_to_curve(x)
             // C' = C + r*K
@@ -261,57 +301,47 @@ impl Proof {
             // R2 = s*B' - e*C'
             // e == hash(R1,R2,K,C') # must be True
 
-            let amount_key = keys
+            let k = keys
                 .get(&self.amount)
                 .ok_or_else(|| anyhow!("Mint error: key for amount does not exist"))?;
 
-            let k = PublicKey::from_hex(amount_key).context("K pubkey")?;
-            let c = PublicKey::from_hex(&self.c).context("C pubkey")?;
-
-            let e = SecretKey::from_hex(&dleq.e).context("'e' secret key")?;
-            let s = SecretKey::from_hex(&dleq.s).context("'s' secret key")?;
-            let r = SecretKey::from_hex(&dleq.r).context("'r' secret key")?;
+            let (e, s, r) = (&dleq.e, &dleq.s, &dleq.r);
 
             // Y = hash_to_curve(x)
             let y = hash_to_curve(self.secret.as_bytes())?;
 
             // C' = C + r*K
-            let c_ = c.combine(&k.mul_tweak(&r)?)?;
+            let c_ = self.c.combine(&k.mul_tweak(r)?)?;
 
             // B' = Y + r*G
             let b_ = y.combine(&r.public_key())?;
 
-            // R1 = s*G - e*K = S - e*K
-            let r1 = &s.public_key().combine(&k.mul_tweak(&e)?.negate())?.to_hex();
-
-            // R2 = s*B' - e*C'
-            let r2 = &b_
-                .mul_tweak(&s)?
-                .combine(&c_.mul_tweak(&e)?.negate())?
-                .to_hex();
-
-            let computed_e = hash_e(r1, r2, &k.to_hex(), &c_.to_string()).context("hash_e")?;
-
-            if dleq.e != computed_e {
-                bail!(
-                    "DLEQ validation mismatch! Expected {}; got {}",
-                    dleq.e,
-                    computed_e,
-                )
-            }
+            verify_dleq(e, s, k, &neg_keys.get(k), &b_, &c_)?;
         }
 
-        Ok(true)
+        Ok(())
     }
 }
 
+/// Proof from keyset with hex encoded `keyset_id`, signed by a dummy key, for tests
+#[cfg(test)]
+pub(crate) fn test_proof(amount: u64, keyset_id: &str, secret: &str) -> Proof {
+    serde_json::from_value(serde_json::json!({
+        "amount": amount,
+        "id": keyset_id,
+        "secret": secret,
+        "C": "0273129c5719e599379a974a626363c333c56cafc0e6d01abe46d5808280789c63",
+    }))
+    .unwrap()
+}
+
 /// Discrete Log Equality (DLEQ) proof (NUT-12). Mint returns the DLEQ proof for a mint or swap operation.
 /// If we want to send the proof to another user we also need to include the blinding factor r for the proof to be convincing to another user.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 struct DleqProof {
-    e: String,
-    s: String,
-    r: String, // We also need to include the blinding factor r for the proof to be convincing to another user
+    e: SecretKey,
+    s: SecretKey,
+    r: SecretKey, // We also need to include the blinding factor r for the proof to be convincing to another user
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -321,7 +351,7 @@ pub struct TokenV4 {
     #[serde(rename = "m")]
     mint_url: String,
     #[serde(rename = "u")]
-    unit: String,
+    unit: CurrencyUnit,
 }
 
 impl std::fmt::Display for TokenV4 {
@@ -339,34 +369,24 @@ impl FromStr for TokenV4 {
 }
 
 impl TokenV4 {
-    pub fn new(mint_url: &str, unit: &str, proofs: &[Proof]) -> Result<Self> {
-        let mut keyset_id_proofs = IndexMap::<String, Vec<TokenProof>>::new();
+    pub fn new(mint_url: &str, unit: &CurrencyUnit, proofs: &[Proof]) -> Result<Self> {
+        let mut keyset_id_proofs = IndexMap::<ShortKeysetId, Vec<TokenProof>>::new();
 
         for proof in proofs {
-            let keyset_id = proof.keyset_id.clone();
-            let token_proof = TokenProof::try_from(proof)?;
-
             keyset_id_proofs
-                .entry(keyset_id)
-                .and_modify(|tp| tp.push(token_proof))
-                .or_insert(vec![proof.try_into()?]);
+                .entry(proof.keyset_id.short())
+                .or_default()
+                .push(proof.into());
         }
 
-        let mut tokens = vec![];
-
-        for (keyset_id, token_proofs) in keyset_id_proofs {
-            let inner_token = InnerToken {
-                keyset_id: hex::decode(keyset_id)
-                    .context("decode hex value of keyset id")?
-                    .into(),
-                proofs: token_proofs,
-            };
-            tokens.push(inner_token);
-        }
+        let tokens = keyset_id_proofs
+            .into_iter()
+            .map(|(keyset_id, proofs)| InnerToken { keyset_id, proofs })
+            .collect();
 
         Ok(Self {
             mint_url: mint_url.to_string(),
-            unit: unit.to_string(),
+            unit: unit.clone(),
             tokens,
         })
     }
@@ -397,125 +417,180 @@ impl TokenV4 {
         Ok(token)
     }
 
-    pub fn amount(&self) -> u64 {
-        self.tokens.iter().map(|t| t.amount()).sum()
+    /// Returns total amount of token proofs, fails if it overflows
+    pub fn amount(&self) -> Result<Amount> {
+        Amount::try_sum(
+            self.tokens
+                .iter()
+                .flat_map(|inner_token| inner_token.proofs.iter().map(|p| p.amount)),
+        )
     }
 
     pub fn mint_url(&self) -> &str {
         &self.mint_url
     }
 
-    pub fn proofs(&self) -> Proofs {
-        let mut proofs = vec![];
-
-        for inner_token in self.tokens.iter() {
-            let keyset_id = hex::encode(inner_token.keyset_id.clone());
-            for token_proof in inner_token.proofs.iter() {
-                let dleq = token_proof.dleq.as_ref().map(|dleq| DleqProof {
-                    e: hex::encode(&dleq.e),
-                    s: hex::encode(&dleq.s),
-                    r: hex::encode(&dleq.r),
-                });
-
-                let proof = Proof {
-                    amount: token_proof.amount,
-                    keyset_id: keyset_id.clone(),
-                    secret: token_proof.secret.clone(),
-                    c: hex::encode(token_proof.c.clone()),
-                    dleq,
-                };
-                proofs.push(proof);
-            }
-        }
+    pub fn unit(&self) -> &CurrencyUnit {
+        &self.unit
+    }
 
-        proofs
+    /// Returns token proofs with short keyset IDs expanded against `known` keyset IDs
+    pub fn proofs<'a>(
+        &self,
+        known: impl IntoIterator<Item = &'a KeysetId> + Clone,
+    ) -> Result<Proofs> {
+        let mut proofs = Proofs::new();
+        for inner_token in &self.tokens {
+            let keyset_id = inner_token.keyset_id.resolve(known.clone())?;
+            proofs.extend(inner_token.proofs.iter().map(|token_proof| Proof {
+                amount: token_proof.amount,
+                keyset_id,
+                secret: token_proof.secret.clone(),
+                c: token_proof.c,
+                dleq: token_proof.dleq.clone(),
+            }));
+        }
+        Ok(proofs)
     }
 
     pub fn validate_dleq_proofs(&self, all_keysets: &AllKeysets) -> Result<bool> {
-        self.proofs().validate_dleq(all_keysets)
+        self.proofs(all_keysets.ids())?.validate_dleq(all_keysets)
     }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 struct InnerToken {
-    #[serde(rename = "i")]
-    // keyset id represented as bytes
-    pub keyset_id: ByteBuf,
+    #[serde(rename = "i", with = "bytes")]
+    pub keyset_id: ShortKeysetId,
 
     #[serde(rename = "p")]
     proofs: Vec<TokenProof>,
 }
 
-impl InnerToken {
-    pub fn amount(&self) -> u64 {
-        self.proofs.iter().map(|p| p.amount).sum()
-    }
-}
-
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 struct TokenProof {
     #[serde(rename = "a")]
-    pub amount: u64,
+    pub amount: Amount,
     #[serde(rename = "s")]
-    secret: String,
-    // unblinded signature on secret represented as bytes
-    c: ByteBuf,
-    #[serde(rename = "d", skip_serializing_if = "Option::is_none")]
-    dleq: Option<TokenDleq>,
+    secret: Secret,
+    /// unblinded signature on secret
+    #[serde(with = "bytes")]
+    c: PublicKey,
+    #[serde(
+        rename = "d",
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "token_dleq"
+    )]
+    dleq: Option<DleqProof>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
-struct TokenDleq {
-    e: ByteBuf,
-    s: ByteBuf,
-    r: ByteBuf,
+impl From<&Proof> for TokenProof {
+    fn from(proof: &Proof) -> Self {
+        Self {
+            amount: proof.amount,
+            secret: proof.secret.clone(),
+            c: proof.c,
+            dleq: proof.dleq.clone(),
+        }
+    }
 }
 
-impl TryFrom<DleqProof> for TokenDleq {
-    type Error = anyhow::Error;
+/// Token V4 encodes keys and keyset IDs as CBOR byte strings instead of hex strings
+mod bytes {
+    use serde::{Deserialize, Deserializer, Serializer, de::Error as _};
+    use serde_bytes::{ByteBuf, Bytes};
+    use zeroize::Zeroizing;
 
-    fn try_from(value: DleqProof) -> std::result::Result<Self, Self::Error> {
-        Ok(Self {
-            e: hex::decode(value.e)
-                .context("decode hex value of 'e' in DLEQ proof")?
-                .into(),
-            s: hex::decode(value.s)
-                .context("decode hex value of 's' in DLEQ proof")?
-                .into(),
-            r: hex::decode(value.r)
-                .context("decode hex value of 'r' in DLEQ proof")?
-                .into(),
-        })
+    use super::{PublicKey, SecretKey, ShortKeysetId};
+
+    /// Byte representation of a value, wiped from memory once it is serialized
+    pub trait AsBytes: Sized {
+        fn to_vec(&self) -> Zeroizing<Vec<u8>>;
+        fn from_slice(bytes: &[u8]) -> anyhow::Result<Self>;
     }
-}
 
-impl TryFrom<Proof> for TokenProof {
-    type Error = anyhow::Error;
+    impl AsBytes for PublicKey {
+        fn to_vec(&self) -> Zeroizing<Vec<u8>> {
+            self.to_bytes().to_vec().into()
+        }
+
+        fn from_slice(bytes: &[u8]) -> anyhow::Result<Self> {
+            PublicKey::from_slice(bytes)
+        }
+    }
 
-    fn try_from(value: Proof) -> std::result::Result<Self, Self::Error> {
-        let dleq = if let Some(dleq) = value.dleq {
-            Some(dleq.try_into()?)
-        } else {
-            None
-        };
+    impl AsBytes for SecretKey {
+        fn to_vec(&self) -> Zeroizing<Vec<u8>> {
+            self.to_bytes().to_vec().into()
+        }
 
-        Ok(Self {
-            amount: value.amount,
-            secret: value.secret,
-            c: hex::decode(value.c)
-                .context("decode hex value of signature in proof")?
-                .into(),
-            dleq,
-        })
+        fn from_slice(bytes: &[u8]) -> anyhow::Result<Self> {
+            SecretKey::from_slice(bytes)
+        }
+    }
+
+    impl AsBytes for ShortKeysetId {
+        fn to_vec(&self) -> Zeroizing<Vec<u8>> {
+            self.as_bytes().to_vec().into()
+        }
+
+        fn from_slice(bytes: &[u8]) -> anyhow::Result<Self> {
+            ShortKeysetId::from_bytes(bytes)
+        }
+    }
+
+    pub fn serialize<T: AsBytes, S: Serializer>(
+        value: &T,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(Bytes::new(&value.to_vec()))
+    }
+
+    pub fn deserialize<'de, T: AsBytes, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<T, D::Error> {
+        let bytes = Zeroizing::new(ByteBuf::deserialize(deserializer)?.into_vec());
+        T::from_slice(&bytes).map_err(D::Error::custom)
     }
 }
 
-impl TryFrom<&Proof> for TokenProof {
-    type Error = anyhow::Error;
+/// DLEQ proof in token V4 with `e`, `s` and `r` as byte strings
+mod token_dleq {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::{DleqProof, SecretKey, bytes};
+
+    #[derive(Serialize, Deserialize)]
+    struct TokenDleq {
+        #[serde(with = "bytes")]
+        e: SecretKey,
+        #[serde(with = "bytes")]
+        s: SecretKey,
+        #[serde(with = "bytes")]
+        r: SecretKey,
+    }
+
+    pub fn serialize<S: Serializer>(
+        dleq: &Option<DleqProof>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        dleq.as_ref()
+            .map(|dleq| TokenDleq {
+                e: dleq.e.clone(),
+                s: dleq.s.clone(),
+                r: dleq.r.clone(),
+            })
+            .serialize(serializer)
+    }
 
-    fn try_from(value: &Proof) -> std::result::Result<Self, Self::Error> {
-        let value = value.clone();
-        value.try_into()
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<DleqProof>, D::Error> {
+        Ok(
+            Option::<TokenDleq>::deserialize(deserializer)?
+                .map(|TokenDleq { e, s, r }| DleqProof { e, s, r }),
+        )
     }
 }
 
@@ -527,35 +602,41 @@ mod tests {
 
     fn prepare_token() -> TokenV4 {
         let proof1 = Proof {
-            amount: 1,
-            keyset_id: "00ffd48b8f5ecf80".to_string(),
-            secret: "acc12435e7b8484c3cf1850149218af90f716a52bf4a5ed347e48ecc13f77388".to_string(),
-            c: "0244538319de485d55bed3b29a642bee5879375ab9e7a620e11e48ba482421f3cf".to_string(),
+            amount: Amount::new(1),
+            keyset_id: "00ffd48b8f5ecf80".parse().unwrap(),
+            secret: "acc12435e7b8484c3cf1850149218af90f716a52bf4a5ed347e48ecc13f77388".into(),
+            c: "0244538319de485d55bed3b29a642bee5879375ab9e7a620e11e48ba482421f3cf"
+                .parse()
+                .unwrap(),
             dleq: None,
         };
 
         let proof2 = Proof {
-            amount: 2,
-            keyset_id: "00ad268c4d1f5826".to_string(),
-            secret: "1323d3d4707a58ad2e23ada4e9f1f49f5a5b4ac7b708eb0d61f738f48307e8ee".to_string(),
-            c: "023456aa110d84b4ac747aebd82c3b005aca50bf457ebd5737a4414fac3ae7d94d".to_string(),
+            amount: Amount::new(2),
+            keyset_id: "00ad268c4d1f5826".parse().unwrap(),
+            secret: "1323d3d4707a58ad2e23ada4e9f1f49f5a5b4ac7b708eb0d61f738f48307e8ee".into(),
+            c: "023456aa110d84b4ac747aebd82c3b005aca50bf457ebd5737a4414fac3ae7d94d"
+                .parse()
+                .unwrap(),
             dleq: None,
         };
 
         let proof3 = Proof {
-            amount: 1,
-            keyset_id: "00ad268c4d1f5826".to_string(),
-            secret: "56bcbcbb7cc6406b3fa5d57d2174f4eff8b4402b176926d3a57d3c3dcbb59d57".to_string(),
-            c: "0273129c5719e599379a974a626363c333c56cafc0e6d01abe46d5808280789c63".to_string(),
+            amount: Amount::new(1),
+            keyset_id: "00ad268c4d1f5826".parse().unwrap(),
+            secret: "56bcbcbb7cc6406b3fa5d57d2174f4eff8b4402b176926d3a57d3c3dcbb59d57".into(),
+            c: "0273129c5719e599379a974a626363c333c56cafc0e6d01abe46d5808280789c63"
+                .parse()
+                .unwrap(),
             dleq: None,
         };
 
         let mint_url = "http://localhost:3338";
-        let unit = "sat";
+        let unit = CurrencyUnit::Sat;
 
         let proofs = vec![proof1, proof2, proof3];
 
-        TokenV4::new(mint_url, unit, &proofs).unwrap()
+        TokenV4::new(mint_url, &unit, &proofs).unwrap()
     }
 
     #[test]
@@ -585,26 +666,69 @@ mod tests {
         assert_eq!(deserialized_token, expected);
     }
 
+    #[test]
+    fn test_token_with_short_keyset_id() {
+        let v1: KeysetId = "00ad268c4d1f5826".parse().unwrap();
+        let v2: KeysetId = "015ba18a8adcd02e715a58358eb618da4a4b3791151a4bee5e968bb88406ccf76a"
+            .parse()
+            .unwrap();
+
+        let mut proofs = prepare_token()
+            .proofs([&v1, &"00ffd48b8f5ecf80".parse().unwrap()])
+            .unwrap();
+        for proof in proofs.iter_mut() {
+            proof.keyset_id = v2;
+        }
+
+        let token = TokenV4::new("http://localhost:3338", &CurrencyUnit::Sat, &proofs).unwrap();
+        assert_eq!(token.tokens.len(), 1);
+        assert_eq!(token.tokens[0].keyset_id.as_bytes(), &v2.as_bytes()[..8]);
+        assert_eq!(token.amount().unwrap(), Amount::new(4));
+
+        let token = TokenV4::from_str(&token.to_string()).unwrap();
+        let resolved = token.proofs([&v1, &v2]).unwrap();
+        assert!(resolved.iter().all(|proof| proof.keyset_id == v2));
+        assert_eq!(
+            TokenV4::new("http://localhost:3338", &CurrencyUnit::Sat, &resolved).unwrap(),
+            token
+        );
+
+        // short ID has to be expanded against known keysets
+        assert!(token.proofs([&v1]).is_err());
+        let mut other = v2.as_bytes().to_vec();
+        other[32] ^= 1;
+        let other = KeysetId::from_bytes(&other).unwrap();
+        assert!(token.proofs([&v2, &other]).is_err());
+    }
+
     #[test]
     fn test_token_with_dleq_proofs() {
         let proof1 = Proof {
-            amount: 1,
-            keyset_id: "00882760bfa2eb41".to_string(),
-            secret: "daf4dd00a2b68a0858a80450f52c8a7d2ccf87d375e43e216e0c571f089f63e9".to_string(),
-            c: "02a9acc1e48c25eeeb9289b5031cc57da9fe72f3fe2861d264bdc074209b107ba2".to_string(),
+            amount: Amount::new(1),
+            keyset_id: "00882760bfa2eb41".parse().unwrap(),
+            secret: "daf4dd00a2b68a0858a80450f52c8a7d2ccf87d375e43e216e0c571f089f63e9".into(),
+            c: "02a9acc1e48c25eeeb9289b5031cc57da9fe72f3fe2861d264bdc074209b107ba2"
+                .parse()
+                .unwrap(),
             dleq: Some(DleqProof {
-                e: "b31e58ac6527f34975ffab13e70a48b6d2b0d35abc4b03f0151f09ee1a9763d4".to_string(),
-                s: "8fbae004c59e754d71df67e392b6ae4e29293113ddc2ec86592a0431d16306d8".to_string(),
-                r: "a6d13fcd7a18442e6076f5e1e7c887ad5de40a019824bdfa9fe740d302e8d861".to_string(),
+                e: "b31e58ac6527f34975ffab13e70a48b6d2b0d35abc4b03f0151f09ee1a9763d4"
+                    .parse()
+                    .unwrap(),
+                s: "8fbae004c59e754d71df67e392b6ae4e29293113ddc2ec86592a0431d16306d8"
+                    .parse()
+                    .unwrap(),
+                r: "a6d13fcd7a18442e6076f5e1e7c887ad5de40a019824bdfa9fe740d302e8d861"
+                    .parse()
+                    .unwrap(),
             }),
         };
 
         let mint_url = "http://localhost:3338";
-        let unit = "sat";
+        let unit = CurrencyUnit::Sat;
 
         let proofs = vec![proof1];
 
-        let token = TokenV4::new(mint_url, unit, &proofs).unwrap();
+        let token = TokenV4::new(mint_url, &unit, &proofs).unwrap();
         let serialized_token = token.to_string();
 
         let expected = "cashuBo2F0gaJhaUgAiCdgv6LrQWFwgaRhYQFhc3hAZGFmNGRkMDBhMmI2OGEwODU4YTgwNDUwZjUyYzhhN2QyY2NmODdkMzc1ZTQzZTIxNmUwYzU3MWYwODlmNjNlOWFjWCECqazB5Iwl7uuSibUDHMV9qf5y8_4oYdJkvcB0IJsQe6JhZKNhZVggsx5YrGUn80l1_6sT5wpIttKw01q8SwPwFR8J7hqXY9Rhc1ggj7rgBMWedU1x32fjkrauTikpMRPdwuyGWSoEMdFjBthhclggptE_zXoYRC5gdvXh58iHrV3kCgGYJL36n-dA0wLo2GFhbXVodHRwOi8vbG9jYWxob3N0OjMzMzhhdWNzYXQ";
@@ -615,6 +739,23 @@ mod tests {
         assert_eq!(token, deserialized_token);
     }
 
+    #[test]
+    fn test_invalid_proof_rejected() {
+        let proof = |keyset_id: &str, c: &str| {
+            serde_json::from_value::<Proof>(serde_json::json!({
+                "amount": 1,
+                "id": keyset_id,
+                "secret": "s",
+                "C": c,
+            }))
+        };
+        let c = "0273129c5719e599379a974a626363c333c56cafc0e6d01abe46d5808280789c63";
+
+        assert!(proof("00ad268c4d1f5826", c).is_ok());
+        assert!(proof("00ad268c4d1f5826", "02ff").is_err());
+        assert!(proof("keyset", c).is_err());
+    }
+
     #[test]
     fn test_validate_signature_dleq() {
         // https://github.com/cashubtc/nuts/blob/main/tests/12-tests.md#dleq-verification-on-blindsignature
@@ -633,26 +774,36 @@ mod tests {
         }
         */
 
-        let k = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798".to_string();
-        let b_ = "02a9acc1e48c25eeeb9289b5031cc57da9fe72f3fe2861d264bdc074209b107ba2".to_string();
+        let k: PublicKey = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798"
+            .parse()
+            .unwrap();
+        let b_: PublicKey = "02a9acc1e48c25eeeb9289b5031cc57da9fe72f3fe2861d264bdc074209b107ba2"
+            .parse()
+            .unwrap();
 
-        let id = "00882760bfa2eb41".to_string();
+        let id: KeysetId = "00882760bfa2eb41".parse().unwrap();
 
         let signature = BlindSignature {
-            amount: 8,
-            keyset_id: id.clone(),
+            amount: Amount::new(8),
+            keyset_id: id,
             c_: BlindedKey(
-                "02a9acc1e48c25eeeb9289b5031cc57da9fe72f3fe2861d264bdc074209b107ba2".to_string(),
+                "02a9acc1e48c25eeeb9289b5031cc57da9fe72f3fe2861d264bdc074209b107ba2"
+                    .parse()
+                    .unwrap(),
             ),
             dleq: Some(Dleq {
-                e: "9818e061ee51d5c8edc3342369a554998ff7b4381c8652d724cdf46429be73d9".to_string(),
-                s: "9818e061ee51d5c8edc3342369a554998ff7b4381c8652d724cdf46429be73da".to_string(),
+                e: "9818e061ee51d5c8edc3342369a554998ff7b4381c8652d724cdf46429be73d9"
+                    .parse()
+                    .unwrap(),
+                s: "9818e061ee51d5c8edc3342369a554998ff7b4381c8652d724cdf46429be73da"
+                    .parse()
+                    .unwrap(),
             }),
         };
 
-        let active_keys = AmountKeys::from([(8, k)]);
+        let active_keys = AmountKeys::from([(Amount::new(8), k)]);
         let outputs = vec![BlindedMessage {
-            amount: 8,
+            amount: Amount::new(8),
             keyset_id: id,
             b_: BlindedSecret(b_),
         }];
@@ -683,25 +834,36 @@ mod tests {
         }
         */
 
-        let k = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798".to_string();
+        let k: PublicKey = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798"
+            .parse()
+            .unwrap();
 
-        let id = "00882760bfa2eb41".to_string();
-        let secret = "daf4dd00a2b68a0858a80450f52c8a7d2ccf87d375e43e216e0c571f089f63e9".to_string();
-        let c = "024369d2d22a80ecf78f3937da9d5f30c1b9f74f0c32684d583cca0fa6a61cdcfc".to_string();
+        let id: KeysetId = "00882760bfa2eb41".parse().unwrap();
+        let secret: Secret =
+            "daf4dd00a2b68a0858a80450f52c8a7d2ccf87d375e43e216e0c571f089f63e9".into();
+        let c: PublicKey = "024369d2d22a80ecf78f3937da9d5f30c1b9f74f0c32684d583cca0fa6a61cdcfc"
+            .parse()
+            .unwrap();
 
         let proof = Proof {
-            amount: 1,
+            amount: Amount::new(1),
             keyset_id: id,
             secret,
             c,
             dleq: Some(DleqProof {
-                e: "b31e58ac6527f34975ffab13e70a48b6d2b0d35abc4b03f0151f09ee1a9763d4".to_string(),
-                s: "8fbae004c59e754d71df67e392b6ae4e29293113ddc2ec86592a0431d16306d8".to_string(),
-                r: "a6d13fcd7a18442e6076f5e1e7c887ad5de40a019824bdfa9fe740d302e8d861".to_string(),
+                e: "b31e58ac6527f34975ffab13e70a48b6d2b0d35abc4b03f0151f09ee1a9763d4"
+                    .parse()
+                    .unwrap(),
+                s: "8fbae004c59e754d71df67e392b6ae4e29293113ddc2ec86592a0431d16306d8"
+                    .parse()
+                    .unwrap(),
+                r: "a6d13fcd7a18442e6076f5e1e7c887ad5de40a019824bdfa9fe740d302e8d861"
+                    .parse()
+                    .unwrap(),
             }),
         };
 
-        let keys = AmountKeys::from([(1, k)]);
+        let keys = AmountKeys::from([(Amount::new(1), k)]);
 
         assert!(proof.validate_dleq(&keys).unwrap());
 
@@ -711,4 +873,91 @@ mod tests {
 
         assert!(proof_no_dleq.validate_dleq(&keys).unwrap());
     }
+
+    /// Signs `count` random proofs of amount 1 with DLEQ proofs by mint private key `a`
+    fn sign_proofs(a: &SecretKey, keyset_id: KeysetId, count: usize) -> Proofs {
+        let k = a.public_key();
+
+        (0..count)
+            .map(|_| {
+                let secret = Secret::generate();
+                let (b_, r) = BlindedSecret::from_bytes(secret.as_bytes()).unwrap();
+
+                // C' = a*B'
+                let c_ = b_.0.mul_tweak(a).unwrap();
+
+                // e = hash(p*G, p*B', K, C'); s = p + e*a
+                let p = SecretKey::generate();
+                let r1 = p.public_key();
+                let r2 = b_.0.mul_tweak(&p).unwrap();
+                let e = SecretKey::from_slice(&crypto::hash_e(&r1, &r2, &k, &c_)).unwrap();
+                let s = p.add(&e.mul(a).unwrap()).unwrap();
+
+                let signature = BlindSignature {
+                    amount: Amount::new(1),
+                    keyset_id,
+                    c_: BlindedKey(c_),
+                    dleq: Some(Dleq { e, s }),
+                };
+                signature.construct_proof(&r, &k, &secret).unwrap()
+            })
+            .collect()
+    }
+
+    /// Mint keyset with a single key `a` for amount 1 and `count` proofs signed by it
+    fn signed_token(count: usize) -> (AllKeysets, Proofs) {
+        let a = SecretKey::generate();
+        let keyset_id: KeysetId = "00882760bfa2eb41".parse().unwrap();
+        let all_keysets: AllKeysets = serde_json::from_value(serde_json::json!({
+            "keysets": [{ "id": keyset_id, "unit": "sat", "keys": { "1": a.public_key() } }]
+        }))
+        .unwrap();
+        (all_keysets, sign_proofs(&a, keyset_id, count))
+    }
+
+    #[test]
+    fn test_validate_dleq_of_many_proofs() {
+        let (all_keysets, mut proofs) = signed_token(20);
+
+        // batches verified in parallel, the last one holds an invalid proof
+        let keys = &all_keysets.iter().next().unwrap().keys;
+        let mut invalid = proofs.clone();
+        invalid[19].c = proofs[0].c;
+        for (proofs, valid) in [(&proofs, true), (&invalid, false)] {
+            let batch = proofs.iter().map(|p| (p, keys)).collect::<Vec<_>>();
+            assert_eq!(verify_dleq_in_batches(&batch, 6).is_ok(), valid);
+        }
+
+        let token = TokenV4::new("http://localhost:3338", &CurrencyUnit::Sat, &proofs).unwrap();
+        let token = TokenV4::from_str(&token.to_string()).unwrap();
+        assert!(token.validate_dleq_proofs(&all_keysets).unwrap());
+
+        // a single invalid proof fails validation of all of them
+        proofs[10].c = proofs[0].c;
+        assert!(proofs.validate_dleq(&all_keysets).is_err());
+    }
+
+    /// Measures decoding and DLEQ validation of a large token,
+    /// run with `cargo test --release -- --ignored --nocapture`
+    #[test]
+    #[ignore = "benchmark"]
+    fn bench_validate_dleq_of_large_token() {
+        const PROOFS: usize = 1000;
+
+        let (all_keysets, proofs) = signed_token(PROOFS);
+        let encoded = TokenV4::new("http://localhost:3338", &CurrencyUnit::Sat, &proofs)
+            .unwrap()
+            .to_string();
+
+        let start = std::time::Instant::now();
+        let token = TokenV4::from_str(&encoded).unwrap();
+        assert!(token.validate_dleq_proofs(&all_keysets).unwrap());
+        let elapsed = start.elapsed();
+
+        assert_eq!(token.amount().unwrap(), PROOFS as u64);
+        println!(
+            "decoded and validated token of {} proofs in {:?}",
+            PROOFS, elapsed
+        );
+    }
 }
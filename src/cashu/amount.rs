@@ -0,0 +1,185 @@
+use std::str::FromStr;
+
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Amount of proofs, outputs, quotes and tokens in the unit of their keyset.
+///
+/// Amounts come from mint responses and tokens of other users, so arithmetic is checked:
+/// overflow or underflow is an error instead of a wrapped value or panic.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct Amount(u64);
+
+impl Amount {
+    pub const ZERO: Amount = Amount(0);
+
+    pub const fn new(value: u64) -> Self {
+        Self(value)
+    }
+
+    pub const fn value(self) -> u64 {
+        self.0
+    }
+
+    pub fn checked_add(self, other: Amount) -> Option<Amount> {
+        self.0.checked_add(other.0).map(Self)
+    }
+
+    pub fn checked_sub(self, other: Amount) -> Option<Amount> {
+        self.0.checked_sub(other.0).map(Self)
+    }
+
+    /// Adds amounts, fails on overflow
+    pub fn try_add(self, other: Amount) -> Result<Amount> {
+        self.checked_add(other)
+            .ok_or_else(|| anyhow!("Amount overflow: {} + {}", self, other))
+    }
+
+    /// Subtracts amounts, fails if `other` is larger
+    pub fn try_sub(self, other: Amount) -> Result<Amount> {
+        self.checked_sub(other)
+            .ok_or_else(|| anyhow!("Amount underflow: {} - {}", self, other))
+    }
+
+    /// Sums amounts, fails on overflow
+    pub fn try_sum(amounts: impl IntoIterator<Item = Amount>) -> Result<Amount> {
+        amounts
+            .into_iter()
+            .try_fold(Amount::ZERO, |acc, amount| acc.try_add(amount))
+    }
+}
+
+impl From<u64> for Amount {
+    fn from(value: u64) -> Self {
+        Self(value)
+    }
+}
+
+impl From<Amount> for u64 {
+    fn from(amount: Amount) -> Self {
+        amount.0
+    }
+}
+
+impl PartialEq<u64> for Amount {
+    fn eq(&self, other: &u64) -> bool {
+        self.0 == *other
+    }
+}
+
+impl FromStr for Amount {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        s.parse().map(Self)
+    }
+}
+
+impl std::fmt::Display for Amount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Unit of keysets, quotes and tokens
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CurrencyUnit {
+    #[default]
+    Sat,
+    Msat,
+    Usd,
+    Eur,
+    /// Unit not known to this wallet
+    Custom(String),
+}
+
+impl std::fmt::Display for CurrencyUnit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Sat => write!(f, "sat"),
+            Self::Msat => write!(f, "msat"),
+            Self::Usd => write!(f, "usd"),
+            Self::Eur => write!(f, "eur"),
+            Self::Custom(unit) => write!(f, "{}", unit),
+        }
+    }
+}
+
+impl FromStr for CurrencyUnit {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(match s.to_lowercase().as_str() {
+            "sat" => Self::Sat,
+            "msat" => Self::Msat,
+            "usd" => Self::Usd,
+            "eur" => Self::Eur,
+            _ => Self::Custom(s.to_string()),
+        })
+    }
+}
+
+impl Serialize for CurrencyUnit {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for CurrencyUnit {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let unit = String::deserialize(deserializer)?;
+        Ok(unit.parse().unwrap_or_else(|e| match e {}))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_amount_arithmetic() {
+        let max = Amount::new(u64::MAX);
+
+        assert_eq!(Amount::new(2).try_add(Amount::new(3)).unwrap(), 5);
+        assert!(max.try_add(Amount::new(1)).is_err());
+        assert_eq!(
+            Amount::new(3).try_sub(Amount::new(3)).unwrap(),
+            Amount::ZERO
+        );
+        assert!(Amount::new(2).try_sub(Amount::new(3)).is_err());
+
+        assert_eq!(Amount::try_sum([1, 2, 4].map(Amount::new)).unwrap(), 7);
+        assert_eq!(Amount::try_sum([]).unwrap(), Amount::ZERO);
+        assert!(Amount::try_sum([max, Amount::new(1)]).is_err());
+
+        assert_eq!("21".parse::<Amount>().unwrap(), 21);
+        assert!("-1".parse::<Amount>().is_err());
+
+        assert_eq!(serde_json::to_string(&Amount::new(8)).unwrap(), "8");
+        let keys: std::collections::BTreeMap<Amount, String> =
+            serde_json::from_str(r#"{"1": "a", "2": "b"}"#).unwrap();
+        assert_eq!(
+            keys.keys().copied().collect::<Vec<_>>(),
+            [1, 2].map(Amount::new)
+        );
+    }
+
+    #[test]
+    fn test_currency_unit() {
+        for unit in [
+            CurrencyUnit::Sat,
+            CurrencyUnit::Msat,
+            CurrencyUnit::Usd,
+            CurrencyUnit::Eur,
+            CurrencyUnit::Custom("btc".to_string()),
+        ] {
+            let json = serde_json::to_string(&unit).unwrap();
+            assert_eq!(json, format!("\"{}\"", unit));
+            assert_eq!(serde_json::from_str::<CurrencyUnit>(&json).unwrap(), unit);
+        }
+        assert_eq!("SAT".parse::<CurrencyUnit>().unwrap(), CurrencyUnit::Sat);
+    }
+}
@@ -1,15 +1,22 @@
 use std::str::FromStr;
+use std::sync::LazyLock;
 
 use anyhow::{Context, Result, bail};
-use secp256k1::Secp256k1;
-use secp256k1::hashes::Hash;
 use secp256k1::hashes::sha256::Hash as Sha256Hash;
+use secp256k1::hashes::{Hash, HashEngine};
 use secp256k1::rand::prelude::RngCore;
+use secp256k1::{All, Secp256k1};
 use secp256k1::{Parity, PublicKey as UncompressedPublicKey, XOnlyPublicKey};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de::Error as _};
+use zeroize::{Zeroize, Zeroizing};
 
-/// Secret message: 32 random hex encoded bytes
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Context shared by all curve operations, creating a new one for every operation is expensive
+static SECP: LazyLock<Secp256k1<All>> = LazyLock::new(Secp256k1::new);
+
+/// Secret message: 32 random hex encoded bytes, wiped from memory on drop.
+/// Proofs with known secrets can be spent, so it is never formatted.
+#[derive(Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
 pub struct Secret(String);
 
 impl Secret {
@@ -17,11 +24,12 @@ impl Secret {
     pub fn generate() -> Self {
         let mut rng = secp256k1::rand::rng();
 
-        let mut random_bytes = [0u8; 32];
+        let mut random_bytes = Zeroizing::new([0u8; 32]);
+        rng.fill_bytes(random_bytes.as_mut());
 
-        rng.fill_bytes(&mut random_bytes);
-        let secret = hex::encode(random_bytes);
-        Self(secret)
+        let mut secret = Zeroizing::new([0u8; 64]);
+        hex::encode_to_slice(random_bytes, secret.as_mut()).expect("buffer fits hex of 32 bytes");
+        Self(String::from_utf8(secret.to_vec()).expect("hex is valid UTF-8"))
     }
 
     pub fn as_bytes(&self) -> &[u8] {
@@ -29,14 +37,26 @@ impl Secret {
     }
 }
 
-impl std::fmt::Display for Secret {
+impl From<&str> for Secret {
+    fn from(secret: &str) -> Self {
+        Self(secret.to_owned())
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl std::fmt::Debug for Secret {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str(&self.0)
+        write!(f, "Secret(XXXX)")
     }
 }
 
-/// PublicKey
-#[derive(PartialEq)]
+/// PublicKey, serialized as hex string of its compressed form
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
 pub struct PublicKey {
     inner: secp256k1::PublicKey,
 }
@@ -67,6 +87,19 @@ impl std::fmt::Debug for PublicKey {
     }
 }
 
+impl Serialize for PublicKey {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_hex())
+    }
+}
+
+impl<'de> Deserialize<'de> for PublicKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        Self::from_hex(hex).map_err(D::Error::custom)
+    }
+}
+
 impl PublicKey {
     /// Parse from `hex` string
     pub fn from_hex<S>(hex: S) -> Result<Self>
@@ -88,33 +121,51 @@ impl PublicKey {
         })
     }
 
+    /// Parse from 33 bytes of compressed public key
+    pub fn from_slice(bytes: &[u8]) -> Result<Self> {
+        let bytes = bytes.try_into().with_context(|| {
+            format!("Invalid public key size: expected 33, got {}", bytes.len())
+        })?;
+        Ok(Self {
+            inner: secp256k1::PublicKey::from_byte_array_compressed(bytes)?,
+        })
+    }
+
     pub fn to_hex(&self) -> String {
         self.inner.to_string()
     }
 
+    /// Returns 33 bytes of compressed public key
+    pub fn to_bytes(&self) -> [u8; 33] {
+        self.inner.serialize()
+    }
+
     /// Adds a second key to this one, returning the sum
     pub fn combine(&self, other: &Self) -> Result<Self> {
         Ok(self.inner.combine(&other.inner)?.into())
     }
 
+    /// Adds all `keys` together, returning the sum
+    pub fn combine_keys(keys: &[&Self]) -> Result<Self> {
+        let keys: Vec<_> = keys.iter().map(|key| &key.inner).collect();
+        Ok(secp256k1::PublicKey::combine_keys(&keys)?.into())
+    }
+
     /// Tweaks a [`PublicKey`] by multiplying by tweak modulo the curve order
     pub fn mul_tweak(&self, other: &SecretKey) -> Result<Self> {
-        let secp = Secp256k1::new();
         let other = secp256k1::Scalar::from(other.inner);
 
-        Ok(self.inner.mul_tweak(&secp, &other)?.into())
+        Ok(self.inner.mul_tweak(&SECP, &other)?.into())
     }
 
     /// Negates the public key
     pub fn negate(&self) -> Self {
-        let secp = Secp256k1::new();
-
-        self.inner.negate(&secp).into()
+        self.inner.negate(&SECP).into()
     }
 }
 
-/// SecretKey
-#[derive(Clone)]
+/// SecretKey, serialized as hex string
+#[derive(Clone, PartialEq)]
 pub struct SecretKey {
     inner: secp256k1::SecretKey,
 }
@@ -131,6 +182,29 @@ impl From<secp256k1::SecretKey> for SecretKey {
     }
 }
 
+impl FromStr for SecretKey {
+    type Err = anyhow::Error;
+
+    fn from_str(hex: &str) -> Result<Self, Self::Err> {
+        Self::from_hex(hex)
+    }
+}
+
+impl Serialize for SecretKey {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut hex = Zeroizing::new([0u8; 64]);
+        hex::encode_to_slice(self.to_bytes(), hex.as_mut()).expect("buffer fits hex of 32 bytes");
+        serializer.serialize_str(std::str::from_utf8(hex.as_ref()).expect("hex is valid UTF-8"))
+    }
+}
+
+impl<'de> Deserialize<'de> for SecretKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let hex = Zeroizing::new(String::deserialize(deserializer)?);
+        Self::from_hex(hex.as_str()).map_err(D::Error::custom)
+    }
+}
+
 impl SecretKey {
     /// Generates a new random secret key
     pub fn generate() -> Self {
@@ -149,27 +223,48 @@ impl SecretKey {
         })
     }
 
+    /// Parse from 32 bytes
+    pub fn from_slice(bytes: &[u8]) -> Result<Self> {
+        let bytes = bytes.try_into().with_context(|| {
+            format!("Invalid secret key size: expected 32, got {}", bytes.len())
+        })?;
+        Ok(Self {
+            inner: secp256k1::SecretKey::from_byte_array(bytes)?,
+        })
+    }
+
+    /// Returns 32 bytes of the secret key, wiped from memory on drop
+    pub fn to_bytes(&self) -> Zeroizing<[u8; 32]> {
+        Zeroizing::new(self.inner.secret_bytes())
+    }
+
     /// Returns the PublicKey for this SecretKey.
     pub fn public_key(&self) -> PublicKey {
-        let secp = secp256k1::Secp256k1::new();
-        self.inner.public_key(&secp).into()
+        self.inner.public_key(&SECP).into()
+    }
+
+    /// Multiplies two secret keys modulo the curve order
+    pub fn mul(&self, other: &SecretKey) -> Result<Self> {
+        let other = secp256k1::Scalar::from(other.inner);
+        Ok(self.inner.mul_tweak(&other)?.into())
+    }
+
+    /// Adds two secret keys modulo the curve order
+    pub fn add(&self, other: &SecretKey) -> Result<Self> {
+        let other = secp256k1::Scalar::from(other.inner);
+        Ok(self.inner.add_tweak(&other)?.into())
     }
 
     /// Returns Schnorr signature of the `msg` according to NUT-20
     pub fn sign_mint_quote(&self, msg: &str) -> [u8; 64] {
         let msg_hash = Sha256Hash::hash(msg.as_bytes());
 
-        let secp = Secp256k1::new();
-        let keypair = self.inner.keypair(&secp);
+        let mut keypair = self.inner.keypair(&SECP);
 
-        let signature = secp.sign_schnorr_no_aux_rand(msg_hash.as_byte_array(), &keypair);
+        let signature = SECP.sign_schnorr_no_aux_rand(msg_hash.as_byte_array(), &keypair);
+        keypair.non_secure_erase();
         signature.to_byte_array()
     }
-
-    /// Displays the secret key as a hexadecimal string. This method should be used with extreme caution.
-    pub fn display_secret(&self) -> String {
-        self.inner.display_secret().to_string()
-    }
 }
 
 impl Drop for SecretKey {
@@ -185,7 +280,7 @@ impl Drop for SecretKey {
 pub fn hash_to_curve(message: &[u8]) -> Result<PublicKey> {
     const DOMAIN_SEPARATOR: &[u8; 28] = b"Secp256k1_HashToCurve_Cashu_";
 
-    let msg_to_hash: Vec<u8> = [DOMAIN_SEPARATOR, message].concat();
+    let msg_to_hash = Zeroizing::new([DOMAIN_SEPARATOR, message].concat());
 
     let msg_hash: [u8; 32] = Sha256Hash::hash(&msg_to_hash).to_byte_array();
 
@@ -215,40 +310,47 @@ pub fn hash_to_curve(message: &[u8]) -> Result<PublicKey> {
 /// Generates deterministic SHA256 hash for a given input list of public keys for DLEQ proof validation.
 ///
 /// For definition in NUT see [NUT-12](https://github.com/cashubtc/nuts/blob/main/12.md)
-pub fn hash_e(r1: &str, r2: &str, k: &str, c_: &str) -> Result<String> {
-    let r1 = hex::encode(
-        PublicKey::from_hex(r1)
-            .context("pubkey from 'r1'")?
-            .inner
-            .serialize_uncompressed(),
-    );
-
-    let r2 = hex::encode(
-        PublicKey::from_hex(r2)
-            .context("pubkey from 'r2'")?
-            .inner
-            .serialize_uncompressed(),
-    );
-
-    let k = hex::encode(
-        PublicKey::from_hex(k)
-            .context("pubkey from 'k'")?
-            .inner
-            .serialize_uncompressed(),
-    );
-
-    let c_ = hex::encode(
-        PublicKey::from_hex(c_)
-            .context("pubkey from 'c_'")?
-            .inner
-            .serialize_uncompressed(),
-    );
-
-    let data = [r1, r2, k, c_].concat();
-
-    let hash = Sha256Hash::hash(data.as_bytes());
-
-    Ok(hash.to_string())
+pub fn hash_e(r1: &PublicKey, r2: &PublicKey, k: &PublicKey, c_: &PublicKey) -> [u8; 32] {
+    let mut engine = Sha256Hash::engine();
+    let mut key_hex = [0u8; 65 * 2];
+    for key in [r1, r2, k, c_] {
+        hex::encode_to_slice(key.inner.serialize_uncompressed(), &mut key_hex)
+            .expect("buffer fits hex of uncompressed key");
+        engine.input(&key_hex);
+    }
+
+    Sha256Hash::from_engine(engine).to_byte_array()
+}
+
+/// Verifies DLEQ proof (`e`, `s`) that blind signature `c_` on `b_` was made by the private key of `k`.
+/// Negated `k` is passed in, so it can be computed only once for many proofs.
+///
+/// For definition in NUT see [NUT-12](https://github.com/cashubtc/nuts/blob/main/12.md)
+pub fn verify_dleq(
+    e: &SecretKey,
+    s: &SecretKey,
+    k: &PublicKey,
+    neg_k: &PublicKey,
+    b_: &PublicKey,
+    c_: &PublicKey,
+) -> Result<()> {
+    // R1 = s*G - e*K
+    let r1 = s.public_key().combine(&neg_k.mul_tweak(e)?)?;
+
+    // R2 = s*B' - e*C'
+    let r2 = b_.mul_tweak(s)?.combine(&c_.negate().mul_tweak(e)?)?;
+
+    let computed_e = hash_e(&r1, &r2, k, c_);
+
+    if *e.to_bytes() != computed_e {
+        bail!(
+            "DLEQ validation mismatch! Expected {}; got {}",
+            hex::encode(e.to_bytes()),
+            hex::encode(computed_e),
+        )
+    }
+
+    Ok(())
 }
 
 #[cfg(test)]
@@ -289,15 +391,57 @@ mod tests {
 
     #[test]
     fn test_hash_e() {
-        let r1 = "020000000000000000000000000000000000000000000000000000000000000001";
-        let r2 = "020000000000000000000000000000000000000000000000000000000000000001";
-        let k = "020000000000000000000000000000000000000000000000000000000000000001";
-        let c_ = "02a9acc1e48c25eeeb9289b5031cc57da9fe72f3fe2861d264bdc074209b107ba2";
+        let r1: PublicKey = "020000000000000000000000000000000000000000000000000000000000000001"
+            .parse()
+            .unwrap();
+        let r2 = r1;
+        let k = r1;
+        let c_: PublicKey = "02a9acc1e48c25eeeb9289b5031cc57da9fe72f3fe2861d264bdc074209b107ba2"
+            .parse()
+            .unwrap();
 
         let expected_e = "a4dc034b74338c28c6bc3ea49731f2a24440fc7c4affc08b31a93fc9fbe6401e";
 
-        let e = hash_e(r1, r2, k, c_).unwrap();
+        let e = hash_e(&r1, &r2, &k, &c_);
+
+        assert_eq!(expected_e, hex::encode(e));
+    }
+
+    #[test]
+    fn test_keys_serde() {
+        let hex = "02a9acc1e48c25eeeb9289b5031cc57da9fe72f3fe2861d264bdc074209b107ba2";
+        let pubkey: PublicKey = serde_json::from_value(serde_json::json!(hex)).unwrap();
+        assert_eq!(
+            serde_json::to_value(pubkey).unwrap(),
+            serde_json::json!(hex)
+        );
+        assert_eq!(PublicKey::from_slice(&pubkey.to_bytes()).unwrap(), pubkey);
+
+        // invalid points and sizes are rejected when parsed
+        assert!(serde_json::from_value::<PublicKey>(serde_json::json!("02ff")).is_err());
+        assert!(serde_json::from_value::<PublicKey>(serde_json::json!("05".repeat(33))).is_err());
+        assert!(PublicKey::from_slice(&[2; 32]).is_err());
+
+        let hex = "01".repeat(32);
+        let secret_key: SecretKey = serde_json::from_value(serde_json::json!(hex)).unwrap();
+        assert_eq!(
+            serde_json::to_value(&secret_key).unwrap(),
+            serde_json::json!(hex)
+        );
+        assert!(SecretKey::from_slice(secret_key.to_bytes().as_ref()).unwrap() == secret_key);
+        assert!(serde_json::from_value::<SecretKey>(serde_json::json!("00".repeat(32))).is_err());
+    }
 
-        assert_eq!(expected_e, e);
+    #[test]
+    fn test_generate_secret() {
+        let secret = Secret::generate();
+        assert_eq!(secret.as_bytes().len(), 64);
+        assert!(hex::decode(secret.as_bytes()).is_ok());
+        assert_ne!(secret, Secret::generate());
+
+        // secret is not revealed by formatting, not even of proofs holding it
+        assert_eq!(format!("{:?}", secret), "Secret(XXXX)");
+        let proof = crate::cashu::test_proof(1, "00ad268c4d1f5826", "very-secret");
+        assert!(!format!("{:?}", proof).contains("very-secret"));
     }
 }
@@ -0,0 +1,163 @@
+use std::str::FromStr;
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de::Error as _};
+
+/// ID of mint keyset (NUT-02), serialized as hex string of its bytes.
+/// The first byte is the version of the ID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum KeysetId {
+    /// `00` prefix followed by 7 bytes of hash of the keys
+    V1([u8; 8]),
+    /// `01` prefix followed by 32 bytes of hash of the keys, unit and expiry
+    V2([u8; 33]),
+}
+
+impl KeysetId {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        match bytes.first() {
+            Some(0x00) => Ok(Self::V1(bytes.try_into().with_context(|| {
+                format!(
+                    "Invalid size of keyset ID v1: expected 8, got {}",
+                    bytes.len()
+                )
+            })?)),
+            Some(0x01) => Ok(Self::V2(bytes.try_into().with_context(|| {
+                format!(
+                    "Invalid size of keyset ID v2: expected 33, got {}",
+                    bytes.len()
+                )
+            })?)),
+            Some(version) => bail!("Unknown keyset ID version {:02x}", version),
+            None => bail!("Keyset ID must not be empty"),
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Self::V1(bytes) => bytes,
+            Self::V2(bytes) => bytes,
+        }
+    }
+
+    /// Returns the first 8 bytes of the ID used in tokens. For v1 IDs it is the whole ID.
+    pub fn short(&self) -> ShortKeysetId {
+        let mut short = [0; 8];
+        short.copy_from_slice(&self.as_bytes()[..8]);
+        ShortKeysetId(short)
+    }
+}
+
+impl std::fmt::Display for KeysetId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", hex::encode(self.as_bytes()))
+    }
+}
+
+impl FromStr for KeysetId {
+    type Err = anyhow::Error;
+
+    fn from_str(hex: &str) -> Result<Self> {
+        Self::from_bytes(&hex::decode(hex).context("decode hex value of keyset ID")?)
+    }
+}
+
+impl Serialize for KeysetId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for KeysetId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        hex.parse().map_err(D::Error::custom)
+    }
+}
+
+/// Keyset ID carried by tokens: the first 8 bytes of [`KeysetId`], which have to be expanded
+/// against keysets known to the wallet
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ShortKeysetId([u8; 8]);
+
+impl ShortKeysetId {
+    /// Parses short ID or full ID of any version, which is shortened
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        match bytes.len() {
+            8 if matches!(bytes[0], 0x00 | 0x01) => Ok(Self(bytes.try_into()?)),
+            _ => Ok(KeysetId::from_bytes(bytes)?.short()),
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Returns the only keyset ID from `known` IDs starting with this short ID
+    pub fn resolve<'a>(&self, known: impl IntoIterator<Item = &'a KeysetId>) -> Result<KeysetId> {
+        let mut matching = known.into_iter().filter(|id| id.short() == *self);
+        match (matching.next(), matching.next()) {
+            (Some(id), None) => Ok(*id),
+            (Some(_), Some(_)) => bail!("Short keyset ID {} is ambiguous", self),
+            (None, _) => bail!("Unknown keyset ID {}", self),
+        }
+    }
+}
+
+impl std::fmt::Display for ShortKeysetId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const V1: &str = "00ad268c4d1f5826";
+    const V2: &str = "015ba18a8adcd02e715a58358eb618da4a4b3791151a4bee5e968bb88406ccf76a";
+
+    #[test]
+    fn test_keyset_id() {
+        let id: KeysetId = serde_json::from_str(&format!("\"{}\"", V1)).unwrap();
+        assert!(matches!(id, KeysetId::V1(_)));
+        assert_eq!(id.as_bytes(), hex::decode(V1).unwrap());
+        assert_eq!(serde_json::to_string(&id).unwrap(), format!("\"{}\"", V1));
+
+        let id: KeysetId = V2.parse().unwrap();
+        assert!(matches!(id, KeysetId::V2(_)));
+        assert_eq!(id.to_string(), V2);
+
+        // sizes must match version
+        assert!("".parse::<KeysetId>().is_err());
+        assert!("00ad268c4d1f58".parse::<KeysetId>().is_err());
+        assert!(V2[..16].parse::<KeysetId>().is_err());
+        assert!(format!("00{}", &V2[2..]).parse::<KeysetId>().is_err());
+        assert!("02ad268c4d1f5826".parse::<KeysetId>().is_err());
+        assert!("00xx".parse::<KeysetId>().is_err());
+    }
+
+    #[test]
+    fn test_short_keyset_id() {
+        let v1: KeysetId = V1.parse().unwrap();
+        let v2: KeysetId = V2.parse().unwrap();
+        assert_eq!(v1.short().as_bytes(), v1.as_bytes());
+        assert_eq!(v2.short().to_string(), &V2[..16]);
+
+        let short = ShortKeysetId::from_bytes(&hex::decode(&V2[..16]).unwrap()).unwrap();
+        assert_eq!(short, v2.short());
+        // full IDs are shortened
+        assert_eq!(ShortKeysetId::from_bytes(v2.as_bytes()).unwrap(), short);
+        assert!(ShortKeysetId::from_bytes(&[0x02; 8]).is_err());
+        assert!(ShortKeysetId::from_bytes(&[0x01; 7]).is_err());
+
+        assert_eq!(short.resolve([&v1, &v2]).unwrap(), v2);
+        assert_eq!(v1.short().resolve([&v1, &v2]).unwrap(), v1);
+        assert!(short.resolve([&v1]).is_err());
+
+        let mut other = v2.as_bytes().to_vec();
+        other[32] ^= 1;
+        let other = KeysetId::from_bytes(&other).unwrap();
+        assert!(short.resolve([&v2, &other]).is_err());
+    }
+}
@@ -2,27 +2,38 @@ use std::collections::BTreeMap;
 
 use serde::{Deserialize, Serialize};
 
-use crate::cashu::{BlindSignature, BlindedMessage, crypto::SecretKey};
+use crate::cashu::{
+    Amount, BlindSignature, BlindedMessage, CurrencyUnit, KeysetId,
+    crypto::{PublicKey, SecretKey},
+};
 
 /// Public keys for a set of amounts
-pub type AmountKeys = BTreeMap<u64, String>;
+pub type AmountKeys = BTreeMap<Amount, PublicKey>;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MintQuote {
     pub quote: String,
     pub request: String,
-    pub amount: u64,
-    pub unit: String,
+    pub amount: Amount,
+    pub unit: CurrencyUnit,
     pub state: QuoteState,
+    /// Unix timestamp until which the quote can be paid
+    #[serde(default)]
+    pub expiry: Option<u64>,
     pub pubkey: Option<String>,
 }
 
 impl MintQuote {
+    /// Unpaid quote can not be paid after its expiry
+    pub fn is_expired(&self) -> bool {
+        self.state == QuoteState::Unpaid && is_expired(self.expiry)
+    }
+
     /// Returns Schnorr signature according to NUT-20 using `secret_key`
     pub fn sign(&self, outputs: &[BlindedMessage], secret_key: SecretKey) -> String {
         let mut msg = String::from(&self.quote);
 
-        let bs: Vec<String> = outputs.iter().map(|m| m.b_.0.clone()).collect();
+        let bs: Vec<String> = outputs.iter().map(|m| m.b_.to_string()).collect();
 
         msg.push_str(&bs.join(""));
 
@@ -35,62 +46,332 @@ impl MintQuote {
 pub struct MeltQuote {
     pub quote: String,
     pub request: String,
-    pub amount: u64,
-    pub unit: String,
+    pub amount: Amount,
+    pub unit: CurrencyUnit,
     pub state: QuoteState,
-    pub fee_reserve: u64,
+    pub fee_reserve: Amount,
+    /// Unix timestamp until which the quote can be paid
+    #[serde(default)]
+    pub expiry: Option<u64>,
     pub payment_preimage: Option<String>,
     #[serde(skip_serializing)]
     pub change: Option<Vec<BlindSignature>>,
 }
 
+impl MeltQuote {
+    /// Unpaid quote can not be paid after its expiry
+    pub fn is_expired(&self) -> bool {
+        self.state == QuoteState::Unpaid && is_expired(self.expiry)
+    }
+}
+
+/// Options of melt quote request
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MeltOptions {
+    /// NUT-23: amount to pay for invoice without amount
+    Amountless { amount_msat: u64 },
+    /// NUT-15: part of invoice amount (in msat) paid by this mint in multi-path payment
+    Mpp { amount: u64 },
+}
+
+fn is_expired(expiry: Option<u64>) -> bool {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+
+    expiry.is_some_and(|expiry| expiry <= now)
+}
+
+/// State of mint quote (NUT-04) or melt quote (NUT-05)
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "UPPERCASE")]
 pub enum QuoteState {
     Unpaid,
+    /// Melt quote is being paid
+    Pending,
     Paid,
+    /// Tokens of mint quote were issued
     Issued,
 }
 
+/// State of proof reported by mint (NUT-07)
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum SpendingState {
+    Unspent,
+    /// Proof is used in a payment which is not settled yet
+    Pending,
+    Spent,
+}
+
+/// State of proof with `Y = hash_to_curve(secret)` reported by mint (NUT-07)
 #[derive(Debug, Clone, Deserialize)]
+pub struct ProofStateInfo {
+    #[serde(rename = "Y")]
+    pub y: PublicKey,
+    pub state: SpendingState,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AllKeysets {
     keysets: Vec<Keyset>,
 }
 
 impl AllKeysets {
-    pub fn by_id(self, id: &str) -> Option<Keyset> {
-        self.keysets.into_iter().find(|ks| ks.id == id)
+    pub fn by_id(self, id: &KeysetId) -> Option<Keyset> {
+        self.keysets.into_iter().find(|ks| &ks.id == id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Keyset> + Clone {
+        self.keysets.iter()
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = &KeysetId> + Clone {
+        self.iter().map(|ks| &ks.id)
+    }
+
+    /// Returns keysets whose keys differ in `new` (keysets present only in one of them are ignored)
+    pub fn changes(&self, new: &AllKeysets) -> Vec<KeysetChange> {
+        self.keysets
+            .iter()
+            .filter(|old| {
+                new.keysets
+                    .iter()
+                    .any(|ks| ks.id == old.id && ks.keys != old.keys)
+            })
+            .map(|old| KeysetChange::KeysChanged(old.id))
+            .collect()
     }
 }
 
-#[expect(dead_code)]
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Keyset {
-    pub id: String,
-    pub unit: String,
+    pub id: KeysetId,
+    pub unit: CurrencyUnit,
     pub keys: AmountKeys,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AllKeysetInfos {
     pub keysets: Vec<KeysetInfo>,
 }
 
 impl AllKeysetInfos {
-    pub fn for_unit(self, unit: &str) -> Option<KeysetInfo> {
-        self.keysets.into_iter().find(|s| s.unit == unit)
+    pub fn for_unit(self, unit: &CurrencyUnit) -> Option<KeysetInfo> {
+        self.keysets.into_iter().find(|s| &s.unit == unit)
     }
 
-    pub fn by_id(self, id: &str) -> Option<KeysetInfo> {
-        self.keysets.into_iter().find(|ks| ks.id == id)
+    pub fn by_id(self, id: &KeysetId) -> Option<KeysetInfo> {
+        self.keysets.into_iter().find(|ks| &ks.id == id)
+    }
+
+    /// Returns differences between cached keyset infos (`self`) and `new` ones advertised by mint
+    pub fn changes(&self, new: &AllKeysetInfos) -> Vec<KeysetChange> {
+        let mut changes = vec![];
+
+        for old in self.keysets.iter() {
+            match new.keysets.iter().find(|ks| ks.id == old.id) {
+                Some(ks) => {
+                    if ks.input_fee_ppk != old.input_fee_ppk {
+                        changes.push(KeysetChange::FeeChanged {
+                            id: old.id,
+                            old: old.input_fee_ppk,
+                            new: ks.input_fee_ppk,
+                        });
+                    }
+                    if ks.active != old.active {
+                        changes.push(KeysetChange::ActiveChanged {
+                            id: old.id,
+                            active: ks.active,
+                        });
+                    }
+                }
+                None => changes.push(KeysetChange::Removed(old.id)),
+            }
+        }
+
+        for ks in new.keysets.iter() {
+            if !self.keysets.iter().any(|old| old.id == ks.id) {
+                changes.push(KeysetChange::Added(ks.id));
+            }
+        }
+
+        changes
     }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct KeysetInfo {
-    pub id: String,
-    pub unit: String,
+    pub id: KeysetId,
+    pub unit: CurrencyUnit,
     pub active: bool,
     #[serde(default)]
     pub input_fee_ppk: u64,
 }
+
+/// Change of mint keysets detected by comparing cached keysets with those advertised by mint
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeysetChange {
+    /// Mint advertises a new keyset
+    Added(KeysetId),
+    /// Keyset is no longer advertised by mint
+    Removed(KeysetId),
+    /// Keys of already known keyset ID are different
+    KeysChanged(KeysetId),
+    /// Input fee of keyset has changed
+    FeeChanged { id: KeysetId, old: u64, new: u64 },
+    /// Keyset was activated or deactivated
+    ActiveChanged { id: KeysetId, active: bool },
+}
+
+impl std::fmt::Display for KeysetChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Added(id) => write!(f, "new keyset {}", id),
+            Self::Removed(id) => write!(f, "keyset {} disappeared", id),
+            Self::KeysChanged(id) => write!(f, "keys of keyset {} have changed!", id),
+            Self::FeeChanged { id, old, new } => {
+                write!(
+                    f,
+                    "input fee of keyset {} changed: {} -> {} ppk",
+                    id, old, new
+                )
+            }
+            Self::ActiveChanged { id, active } => {
+                let state = if *active { "active" } else { "inactive" };
+                write!(f, "keyset {} is now {}", id, state)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyset_info(id: &str, active: bool, input_fee_ppk: u64) -> KeysetInfo {
+        KeysetInfo {
+            id: id.parse().unwrap(),
+            unit: CurrencyUnit::Sat,
+            active,
+            input_fee_ppk,
+        }
+    }
+
+    #[test]
+    fn test_melt_options() {
+        let amountless = MeltOptions::Amountless { amount_msat: 5000 };
+        assert_eq!(
+            serde_json::to_value(amountless).unwrap(),
+            serde_json::json!({ "amountless": { "amount_msat": 5000 } })
+        );
+
+        let mpp = MeltOptions::Mpp { amount: 2000 };
+        assert_eq!(
+            serde_json::to_value(mpp).unwrap(),
+            serde_json::json!({ "mpp": { "amount": 2000 } })
+        );
+    }
+
+    #[test]
+    fn test_quote_states_and_expiry() {
+        let quote: MeltQuote = serde_json::from_value(serde_json::json!({
+            "quote": "q1",
+            "request": "lnbc",
+            "amount": 10,
+            "unit": "sat",
+            "state": "PENDING",
+            "fee_reserve": 2,
+            "expiry": 1700000000,
+            "payment_preimage": null,
+        }))
+        .unwrap();
+        assert_eq!(quote.state, QuoteState::Pending);
+        assert!(!quote.is_expired()); // pending quote is being paid
+
+        let mut quote: MintQuote = serde_json::from_value(serde_json::json!({
+            "quote": "q2",
+            "request": "lnbc",
+            "amount": 10,
+            "unit": "sat",
+            "state": "UNPAID",
+            "expiry": 1700000000,
+            "pubkey": null,
+        }))
+        .unwrap();
+        assert!(quote.is_expired());
+        quote.expiry = None;
+        assert!(!quote.is_expired());
+        quote.expiry = Some(u64::MAX);
+        assert!(!quote.is_expired());
+    }
+
+    #[test]
+    fn test_keyset_infos_changes() {
+        let old = AllKeysetInfos {
+            keysets: vec![
+                keyset_info("00aaaaaaaaaaaaaa", true, 100),
+                keyset_info("00bbbbbbbbbbbbbb", true, 0),
+                keyset_info("00cccccccccccccc", false, 0),
+            ],
+        };
+        assert!(old.changes(&old.clone()).is_empty());
+
+        let new = AllKeysetInfos {
+            keysets: vec![
+                keyset_info("00aaaaaaaaaaaaaa", false, 200),
+                keyset_info("00cccccccccccccc", false, 0),
+                keyset_info("00dddddddddddddd", true, 0),
+            ],
+        };
+
+        assert_eq!(
+            old.changes(&new),
+            vec![
+                KeysetChange::FeeChanged {
+                    id: "00aaaaaaaaaaaaaa".parse().unwrap(),
+                    old: 100,
+                    new: 200
+                },
+                KeysetChange::ActiveChanged {
+                    id: "00aaaaaaaaaaaaaa".parse().unwrap(),
+                    active: false
+                },
+                KeysetChange::Removed("00bbbbbbbbbbbbbb".parse().unwrap()),
+                KeysetChange::Added("00dddddddddddddd".parse().unwrap()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_keysets_changes() {
+        let keyset = |id: &str, key: u8| Keyset {
+            id: id.parse().unwrap(),
+            unit: CurrencyUnit::Sat,
+            keys: AmountKeys::from([(
+                Amount::new(1),
+                SecretKey::from_slice(&[key; 32]).unwrap().public_key(),
+            )]),
+        };
+
+        let old = AllKeysets {
+            keysets: vec![keyset("00aaaaaaaaaaaaaa", 1), keyset("00bbbbbbbbbbbbbb", 2)],
+        };
+        let new = AllKeysets {
+            keysets: vec![
+                keyset("00aaaaaaaaaaaaaa", 1),
+                keyset("00bbbbbbbbbbbbbb", 3),
+                keyset("00cccccccccccccc", 4),
+            ],
+        };
+
+        assert_eq!(
+            old.changes(&new),
+            vec![KeysetChange::KeysChanged(
+                "00bbbbbbbbbbbbbb".parse().unwrap()
+            )]
+        );
+    }
+}
@@ -1,5 +1,7 @@
 use clap::{Parser, Subcommand};
 
+use toy_cashu::store::StoreKind;
+
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 pub struct Cli {
@@ -22,6 +24,9 @@ pub enum Command {
         wallet_name: String,
         /// Mint URL
         mint: reqwest::Url,
+        /// Wallet store: 'file' (encrypted) or 'sqlite'
+        #[arg(long, default_value = "file")]
+        store: StoreKind,
     },
 }
 
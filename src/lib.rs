@@ -0,0 +1,18 @@
+//! Bitcoin Chaumian E-Cash wallet using [Cashu](https://cashu.space/) protocol.
+//!
+//! Only for educational and testing purposes!
+
+#[cfg(feature = "blocking")]
+pub mod blocking;
+pub mod bolt11;
+pub mod cashu;
+#[cfg(feature = "async")]
+mod mint;
+pub mod store;
+#[cfg(feature = "async")]
+mod wallet;
+
+#[cfg(feature = "async")]
+pub use mint::{Mint, MintError, MintInfo, Nut};
+#[cfg(feature = "async")]
+pub use wallet::{SpendPreview, Wallet, WalletError, coin_selection};
@@ -1,18 +1,46 @@
+use std::process::ExitCode;
+
 use anyhow::bail;
 use rpassword::prompt_password;
+use zeroize::Zeroizing;
+
+use toy_cashu::{WalletError, blocking::Wallet};
 
 use crate::cli::Command;
-use crate::wallet::Wallet;
 
-mod cashu;
 mod cli;
-mod file;
-mod helpers;
-mod mint;
 mod repl;
-mod wallet;
 
-fn main() -> anyhow::Result<()> {
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {err:?}");
+            exit_code(&err)
+        }
+    }
+}
+
+/// Maps wallet errors to distinct process exit codes. Only errors of opening or creating
+/// the wallet end the process, errors of REPL commands are printed and the session goes on.
+fn exit_code(err: &anyhow::Error) -> ExitCode {
+    let code = match err.downcast_ref::<WalletError>() {
+        Some(WalletError::InsufficientFunds { .. }) => 3,
+        Some(WalletError::QuoteNotPaid(_)) => 4,
+        Some(WalletError::Mint(_)) => 5,
+        Some(WalletError::Dleq(_)) => 6,
+        Some(WalletError::Storage(_)) => 7,
+        Some(WalletError::Crypto(_)) => 8,
+        Some(WalletError::InvalidPassword) => 9,
+        Some(WalletError::QuoteExpired(_)) => 10,
+        Some(WalletError::InvalidInvoice(_)) => 11,
+        Some(WalletError::QuoteMismatch { .. }) => 12,
+        Some(WalletError::Other(_)) | None => 1,
+    };
+    ExitCode::from(code)
+}
+
+fn run() -> anyhow::Result<()> {
     let cli = cli::parse();
 
     match &cli.command {
@@ -21,23 +49,27 @@ fn main() -> anyhow::Result<()> {
                 println!("{}. {}", i + 1, name);
             }
         }
-        Command::Create { wallet_name, mint } => {
-            let password = prompt_password("Set walled password: ").unwrap();
+        Command::Create {
+            wallet_name,
+            mint,
+            store,
+        } => {
+            let password = Zeroizing::new(prompt_password("Set walled password: ").unwrap());
             let password = password.trim();
 
-            let password_again = prompt_password("Password again: ").unwrap();
+            let password_again = Zeroizing::new(prompt_password("Password again: ").unwrap());
             let password_again = password_again.trim();
 
             if password != password_again {
                 bail!("Password mismatch, aborting...");
             }
 
-            let wallet = Wallet::create(wallet_name, mint.as_str(), password)?;
+            let wallet = Wallet::create(wallet_name, mint.as_str(), password, *store)?;
 
             repl::start(wallet)?;
         }
         Command::Open { wallet_name } => {
-            let password = prompt_password("Wallet password: ").unwrap();
+            let password = Zeroizing::new(prompt_password("Wallet password: ").unwrap());
             let password = password.trim();
 
             let wallet = Wallet::open(wallet_name, password)?;
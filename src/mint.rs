@@ -1,28 +1,31 @@
 use std::collections::HashMap;
 
-use anyhow::{Result, bail};
 use reqwest::Url;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
 
 use crate::cashu::{
-    BlindSignatures, BlindedMessage, Proof,
+    Amount, BlindSignatures, BlindedMessage, CurrencyUnit, Proof,
     crypto::PublicKey,
-    types::{AllKeysetInfos, AllKeysets, MeltQuote, MintQuote},
+    types::{
+        AllKeysetInfos, AllKeysets, KeysetChange, MeltOptions, MeltQuote, MintQuote, ProofStateInfo,
+    },
 };
 
+pub use error::MintError;
+
+mod error;
+#[cfg(test)]
+pub(crate) mod mock;
+
+type Result<T, E = MintError> = std::result::Result<T, E>;
+
 /// Mint object represents remote mint. Used by [`super::Wallet`] to communicate with mint server specified by its `url`.
-#[derive(Deserialize, Serialize)]
 pub struct Mint {
-    #[serde(rename = "mint")]
     url: Url,
-    #[serde(skip)]
     info: Option<MintInfo>,
-    #[serde(skip)]
     all_keysets: Option<AllKeysets>,
-    #[serde(skip)]
     all_keyset_infos: Option<AllKeysetInfos>,
-    #[serde(skip, default = "reqwest::blocking::Client::new")]
-    http: reqwest::blocking::Client,
+    http: reqwest::Client,
 }
 
 #[derive(Clone, Deserialize)]
@@ -35,12 +38,41 @@ pub struct MintInfo {
     pub nuts: HashMap<u16, Nut>,
 }
 
+impl MintInfo {
+    /// Does mint advertise paying BOLT11 invoices without amount in `unit` (NUT-05)?
+    pub fn supports_amountless_melt(&self, unit: &CurrencyUnit) -> bool {
+        self.supports_bolt11(5, unit, |m| m.options.amountless)
+    }
+
+    /// Does mint support partial melt quotes for multi-path payments in `unit` (NUT-15)?
+    pub fn supports_mpp(&self, unit: &CurrencyUnit) -> bool {
+        self.supports_bolt11(15, unit, |_| true)
+    }
+
+    fn supports_bolt11(
+        &self,
+        nut: u16,
+        unit: &CurrencyUnit,
+        filter: impl Fn(&MethodSettings) -> bool,
+    ) -> bool {
+        self.nuts.get(&nut).is_some_and(|nut| {
+            nut.is_active()
+                && nut
+                    .methods
+                    .iter()
+                    .any(|m| m.method == "bolt11" && &m.unit == unit && filter(m))
+        })
+    }
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct Nut {
     #[serde(default)]
     supported: serde_json::Value,
     #[serde(default)]
     disabled: bool,
+    #[serde(default)]
+    methods: Vec<MethodSettings>,
 }
 
 impl Nut {
@@ -50,10 +82,25 @@ impl Nut {
     }
 }
 
+/// Settings of payment method advertised in NUT-04 and NUT-05 info
+#[derive(Debug, Clone, Deserialize)]
+struct MethodSettings {
+    method: String,
+    unit: CurrencyUnit,
+    #[serde(default)]
+    options: MethodOptions,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+struct MethodOptions {
+    #[serde(default)]
+    amountless: bool,
+}
+
 impl Mint {
     pub fn new(url: &str) -> Result<Self> {
-        let url = Url::parse(url)?;
-        let c = reqwest::blocking::Client::new();
+        let url = Url::parse(url).map_err(|e| MintError::Url(e.to_string()))?;
+        let c = reqwest::Client::new();
 
         Ok(Self {
             url,
@@ -64,15 +111,33 @@ impl Mint {
         })
     }
 
+    /// Sets keysets cached in previous sessions, so changes of keys can be detected
+    pub fn with_cached_keysets(
+        mut self,
+        keysets: Option<AllKeysets>,
+        keyset_infos: Option<AllKeysetInfos>,
+    ) -> Self {
+        self.all_keysets = keysets;
+        self.all_keyset_infos = keyset_infos;
+        self
+    }
+
+    /// Returns currently cached keys and keyset infos
+    pub fn cached_keysets(&self) -> Option<(&AllKeysets, &AllKeysetInfos)> {
+        self.all_keysets
+            .as_ref()
+            .zip(self.all_keyset_infos.as_ref())
+    }
+
     pub fn url(&self) -> String {
         self.url.to_string()
     }
 
     /// NUT-06: Mint information
-    pub fn get_info(&mut self) -> Result<&MintInfo> {
+    pub async fn get_info(&mut self) -> Result<&MintInfo> {
         if self.info.is_none() {
-            let r = self.http.get(self.url.join("/v1/info")?).send()?;
-            let info: MintInfo = r.json()?;
+            let r = self.http.get(self.endpoint("/v1/info")?).send().await?;
+            let info: MintInfo = Self::parse_response(r).await?;
             self.info = Some(info);
         }
 
@@ -80,22 +145,18 @@ impl Mint {
     }
 
     /// NUT-01: Mint public key exchange
-    pub fn get_keys(&mut self) -> Result<&AllKeysets> {
+    pub async fn get_keys(&mut self) -> Result<&AllKeysets> {
         if self.all_keysets.is_none() {
-            let r = self.http.get(self.url.join("/v1/keys")?).send()?;
-            let keys: AllKeysets = r.json()?;
-            self.all_keysets = Some(keys);
+            self.all_keysets = Some(self.fetch_keys().await?);
         }
 
         Ok(self.all_keysets.as_ref().expect("keys were downloaded"))
     }
 
     /// NUT-02: Keysets and fees
-    pub fn get_keysets(&mut self) -> Result<&AllKeysetInfos> {
+    pub async fn get_keysets(&mut self) -> Result<&AllKeysetInfos> {
         if self.all_keyset_infos.is_none() {
-            let r = self.http.get(self.url.join("/v1/keysets")?).send()?;
-            let keysets: AllKeysetInfos = r.json()?;
-            self.all_keyset_infos = Some(keysets);
+            self.all_keyset_infos = Some(self.fetch_keysets().await?);
         }
         Ok(self
             .all_keyset_infos
@@ -103,12 +164,48 @@ impl Mint {
             .expect("keysets were downloaded"))
     }
 
+    /// Drops all cached mint data, downloads keysets again and returns changes against the cached ones.
+    /// Keys are always downloaded again, mint may change them without touching keyset infos.
+    pub async fn refresh(&mut self) -> Result<Vec<KeysetChange>> {
+        let keyset_infos = self.fetch_keysets().await?;
+        let keysets = self.fetch_keys().await?;
+
+        let mut changes = vec![];
+        if let Some(cached_infos) = &self.all_keyset_infos {
+            changes.extend(cached_infos.changes(&keyset_infos));
+        }
+        if let Some(cached_keysets) = &self.all_keysets {
+            changes.extend(cached_keysets.changes(&keysets));
+        }
+
+        self.info = None;
+        self.all_keyset_infos = Some(keyset_infos);
+        self.all_keysets = Some(keysets);
+
+        Ok(changes)
+    }
+
+    async fn fetch_keys(&self) -> Result<AllKeysets> {
+        let r = self.http.get(self.endpoint("/v1/keys")?).send().await?;
+        Self::parse_response(r).await
+    }
+
+    async fn fetch_keysets(&self) -> Result<AllKeysetInfos> {
+        let r = self.http.get(self.endpoint("/v1/keysets")?).send().await?;
+        Self::parse_response(r).await
+    }
+
     /// NUT-23: BOLT11
-    pub fn create_mint_quote(&self, amount: u64, pubkey: PublicKey) -> Result<MintQuote> {
+    pub async fn create_mint_quote(
+        &self,
+        amount: Amount,
+        unit: &CurrencyUnit,
+        pubkey: PublicKey,
+    ) -> Result<MintQuote> {
         #[derive(Serialize)]
-        struct QuoteRequest {
-            amount: u64,
-            unit: String,
+        struct QuoteRequest<'a> {
+            amount: Amount,
+            unit: &'a CurrencyUnit,
             #[serde(skip_serializing_if = "Option::is_none")]
             pubkey: Option<String>, // NUT-20: Signature on Mint Quote
         }
@@ -116,43 +213,34 @@ impl Mint {
         let payment_method = "bolt11";
         let req = QuoteRequest {
             amount,
-            unit: "sat".to_owned(),
+            unit,
             pubkey: Some(pubkey.to_hex()),
         };
 
         let r = self
             .http
-            .post(self.url.join(&format!("/v1/mint/quote/{payment_method}"))?)
+            .post(self.endpoint(&format!("/v1/mint/quote/{payment_method}"))?)
             .json(&req)
-            .send()?;
+            .send()
+            .await?;
 
-        if r.status().is_success() {
-            Ok(r.json()?)
-        } else {
-            bail!("Response: {} \n  {}", r.status(), r.text()?);
-        }
+        Self::parse_response(r).await
     }
 
-    pub fn get_mint_quote(&self, quote_id: &str) -> Result<MintQuote> {
+    pub async fn get_mint_quote(&self, quote_id: &str) -> Result<MintQuote> {
         let payment_method = "bolt11";
 
         let r = self
             .http
-            .get(
-                self.url
-                    .join(&format!("/v1/mint/quote/{payment_method}/{quote_id}"))?,
-            )
-            .send()?;
-
-        if r.status().is_success() {
-            Ok(r.json()?)
-        } else {
-            bail!("Response: {} \n  {}", r.status(), r.text()?);
-        }
+            .get(self.endpoint(&format!("/v1/mint/quote/{payment_method}/{quote_id}"))?)
+            .send()
+            .await?;
+
+        Self::parse_response(r).await
     }
 
     // NUT-04: Mint tokens
-    pub fn do_minting(
+    pub async fn do_minting(
         &self,
         quote_id: &str,
         outputs: &[BlindedMessage],
@@ -174,19 +262,20 @@ impl Mint {
 
         let r = self
             .http
-            .post(self.url.join(&format!("/v1/mint/{payment_method}"))?)
+            .post(self.endpoint(&format!("/v1/mint/{payment_method}"))?)
             .json(&req)
-            .send()?;
+            .send()
+            .await?;
 
-        if r.status().is_success() {
-            Ok(r.json()?)
-        } else {
-            bail!("Response: {} \n  {}", r.status(), r.text()?);
-        }
+        Self::parse_response(r).await
     }
 
     // NUT-03: Swap tokens
-    pub fn do_swap(&self, inputs: &[Proof], outputs: &[BlindedMessage]) -> Result<BlindSignatures> {
+    pub async fn do_swap(
+        &self,
+        inputs: &[Proof],
+        outputs: &[BlindedMessage],
+    ) -> Result<BlindSignatures> {
         // Privacy: The blinding factor r should not be shared with the mint or otherwise,
         // the mint will be able to associate the BlindSignature with the Proof
         let mut sanitized_proofs = vec![];
@@ -208,46 +297,60 @@ impl Mint {
 
         let r = self
             .http
-            .post(self.url.join("/v1/swap")?)
+            .post(self.endpoint("/v1/swap")?)
             .json(&req)
-            .send()?;
+            .send()
+            .await?;
 
-        if r.status().is_success() {
-            Ok(r.json()?)
-        } else {
-            bail!("Response: {} \n  {}", r.status(), r.text()?);
-        }
+        Self::parse_response(r).await
     }
 
     /// NUT-23: BOLT11
-    pub fn create_melt_quote(&self, invoice: &str) -> Result<MeltQuote> {
+    pub async fn create_melt_quote(
+        &self,
+        invoice: &str,
+        unit: &CurrencyUnit,
+        options: Option<MeltOptions>,
+    ) -> Result<MeltQuote> {
         #[derive(Serialize)]
         struct QuoteRequest<'a> {
             request: &'a str,
-            unit: &'a str,
+            unit: &'a CurrencyUnit,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            options: Option<MeltOptions>,
         }
 
         let payment_method = "bolt11";
         let req = QuoteRequest {
             request: invoice,
-            unit: "sat",
+            unit,
+            options,
         };
 
         let r = self
             .http
-            .post(self.url.join(&format!("/v1/melt/quote/{payment_method}"))?)
+            .post(self.endpoint(&format!("/v1/melt/quote/{payment_method}"))?)
             .json(&req)
-            .send()?;
+            .send()
+            .await?;
 
-        if r.status().is_success() {
-            Ok(r.json()?)
-        } else {
-            bail!("Response: {} \n  {}", r.status(), r.text()?);
-        }
+        Self::parse_response(r).await
+    }
+
+    pub async fn get_melt_quote(&self, quote_id: &str) -> Result<MeltQuote> {
+        let payment_method = "bolt11";
+
+        let r = self
+            .http
+            .get(self.endpoint(&format!("/v1/melt/quote/{payment_method}/{quote_id}"))?)
+            .send()
+            .await?;
+
+        Self::parse_response(r).await
     }
 
     // NUT-05: Melt tokens
-    pub fn do_melting(
+    pub async fn do_melting(
         &self,
         quote_id: &str,
         proofs: &[Proof],
@@ -277,14 +380,147 @@ impl Mint {
 
         let r = self
             .http
-            .post(self.url.join(&format!("/v1/melt/{payment_method}"))?)
+            .post(self.endpoint(&format!("/v1/melt/{payment_method}"))?)
             .json(&req)
-            .send()?;
+            .send()
+            .await?;
+
+        Self::parse_response(r).await
+    }
 
-        if r.status().is_success() {
-            Ok(r.json()?)
+    /// NUT-07: Token state check of proofs with `ys` (`Y = hash_to_curve(secret)`)
+    pub async fn check_state(&self, ys: &[PublicKey]) -> Result<Vec<ProofStateInfo>> {
+        #[derive(Serialize)]
+        struct CheckStateRequest<'a> {
+            #[serde(rename = "Ys")]
+            ys: &'a [PublicKey],
+        }
+
+        #[derive(Deserialize)]
+        struct CheckStateResponse {
+            states: Vec<ProofStateInfo>,
+        }
+
+        let r = self
+            .http
+            .post(self.endpoint("/v1/checkstate")?)
+            .json(&CheckStateRequest { ys })
+            .send()
+            .await?;
+
+        let response: CheckStateResponse = Self::parse_response(r).await?;
+        Ok(response.states)
+    }
+
+    fn endpoint(&self, path: &str) -> Result<Url> {
+        self.url
+            .join(path)
+            .map_err(|e| MintError::Url(e.to_string()))
+    }
+
+    /// Deserializes successful response, otherwise parses NUT-00 error response
+    async fn parse_response<T: DeserializeOwned>(r: reqwest::Response) -> Result<T> {
+        let status = r.status();
+        if status.is_success() {
+            Ok(r.json().await?)
         } else {
-            bail!("Response: {} \n  {}", r.status(), r.text()?);
+            Err(MintError::from_response(status, r.text().await?))
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_supports_amountless_melt() {
+        let info = |nut5: serde_json::Value| -> MintInfo {
+            serde_json::from_value(serde_json::json!({
+                "name": "mint",
+                "pubkey": "02",
+                "version": "test/0.1",
+                "nuts": { "4": { "methods": [], "disabled": false }, "5": nut5 },
+            }))
+            .unwrap()
+        };
+
+        let amountless = serde_json::json!({
+            "methods": [
+                { "method": "bolt11", "unit": "sat", "options": { "amountless": true } }
+            ],
+            "disabled": false,
+        });
+        let sat = CurrencyUnit::Sat;
+        assert!(info(amountless.clone()).supports_amountless_melt(&sat));
+        assert!(!info(amountless).supports_amountless_melt(&CurrencyUnit::Usd));
+
+        let without_options = serde_json::json!({
+            "methods": [ { "method": "bolt11", "unit": "sat", "min_amount": 1 } ],
+            "disabled": false,
+        });
+        assert!(!info(without_options).supports_amountless_melt(&sat));
+
+        let disabled = serde_json::json!({
+            "methods": [
+                { "method": "bolt11", "unit": "sat", "options": { "amountless": true } }
+            ],
+            "disabled": true,
+        });
+        assert!(!info(disabled).supports_amountless_melt(&sat));
+        assert!(!info(serde_json::json!({})).supports_mpp(&sat));
+    }
+
+    #[test]
+    fn test_supports_mpp() {
+        let info: MintInfo = serde_json::from_value(serde_json::json!({
+            "name": "mint",
+            "pubkey": "02",
+            "version": "test/0.1",
+            "nuts": { "15": { "methods": [ { "method": "bolt11", "unit": "sat" } ] } },
+        }))
+        .unwrap();
+        assert!(info.supports_mpp(&CurrencyUnit::Sat));
+        assert!(!info.supports_mpp(&CurrencyUnit::Msat));
+        assert!(!info.supports_amountless_melt(&CurrencyUnit::Sat));
+    }
+
+    #[test]
+    fn test_refresh_detects_changed_keys() {
+        let keysets = |key: &str| {
+            serde_json::json!({
+                "keysets": [{ "id": "00ad268c4d1f5826", "unit": "sat", "keys": { "1": key } }]
+            })
+        };
+        let infos = serde_json::json!({
+            "keysets": [{ "id": "00ad268c4d1f5826", "unit": "sat", "active": true }]
+        });
+
+        let old_key = "0273129c5719e599379a974a626363c333c56cafc0e6d01abe46d5808280789c63";
+        let new_key = "02a9acc1e48c25eeeb9289b5031cc57da9fe72f3fe2861d264bdc074209b107ba2";
+
+        let (served_keysets, served_infos) = (keysets(new_key), infos.clone());
+        let mock = mock::MockMint::start(move |request| match request.path.as_str() {
+            "/v1/keys" => (200, served_keysets.clone()),
+            "/v1/keysets" => (200, served_infos.clone()),
+            _ => (404, serde_json::json!({})),
+        });
+
+        // keyset infos stay the same, only keys behind the ID change
+        let mut mint = Mint::new(mock.url()).unwrap().with_cached_keysets(
+            Some(serde_json::from_value(keysets(old_key)).unwrap()),
+            Some(serde_json::from_value(infos).unwrap()),
+        );
+
+        let changes = mock::block_on(mint.refresh()).unwrap();
+        assert_eq!(
+            changes,
+            vec![KeysetChange::KeysChanged(
+                "00ad268c4d1f5826".parse().unwrap()
+            )]
+        );
+        assert!(mock::block_on(mint.refresh()).unwrap().is_empty());
+        // keys are downloaded on every refresh
+        assert_eq!(mock.requests("/v1/keys").len(), 2);
+    }
+}
@@ -0,0 +1,181 @@
+use reqwest::StatusCode;
+use serde::Deserialize;
+
+/// Error reported by mint (NUT-00 error response) or failure to communicate with it
+#[derive(Debug)]
+pub enum MintError {
+    /// 10002: Blinded message of output already signed
+    OutputAlreadySigned,
+    /// 10003: Token could not be verified
+    TokenNotVerified,
+    /// 11001: Token is already spent
+    TokenAlreadySpent,
+    /// 11002: Transaction is not balanced (inputs != outputs)
+    TransactionUnbalanced,
+    /// 11005: Unit in request is not supported
+    UnitNotSupported,
+    /// 11006: Amount outside of limit range
+    AmountOutOfLimitRange,
+    /// 11007: Duplicate inputs provided
+    DuplicateInputs,
+    /// 11008: Duplicate outputs provided
+    DuplicateOutputs,
+    /// 11009: Inputs/Outputs of multiple units
+    MultipleUnits,
+    /// 11010: Inputs and outputs not of same unit
+    UnitMismatch,
+    /// 11011: Amountless invoice is not supported
+    AmountlessInvoiceNotSupported,
+    /// 11012: Amount in request does not equal invoice
+    InvoiceAmountMismatch,
+    /// 12001: Keyset is not known
+    KeysetNotFound,
+    /// 12002: Keyset is inactive, cannot sign messages
+    KeysetInactive,
+    /// 20001: Quote request is not paid
+    QuoteNotPaid,
+    /// 20002: Tokens have already been issued for quote
+    TokensAlreadyIssued,
+    /// 20003: Minting is disabled
+    MintingDisabled,
+    /// 20005: Quote is pending
+    QuotePending,
+    /// 20006: Invoice already paid
+    InvoiceAlreadyPaid,
+    /// 20007: Quote is expired
+    QuoteExpired,
+    /// 20008: Signature for mint request invalid
+    InvalidQuoteSignature,
+    /// 20009: Pubkey required for mint quote
+    PubkeyRequired,
+    /// Error code unknown to the wallet
+    Unknown { code: u32, detail: String },
+    /// Unsuccessful response without NUT-00 error body
+    Response { status: StatusCode, text: String },
+    /// Request could not be sent or response could not be read
+    Http(reqwest::Error),
+    /// Invalid URL of mint endpoint
+    Url(String),
+}
+
+/// NUT-00: Error response
+#[derive(Deserialize)]
+struct ErrorResponse {
+    detail: String,
+    code: u32,
+}
+
+impl MintError {
+    /// Parses NUT-00 error body of unsuccessful response
+    pub fn from_response(status: StatusCode, text: String) -> Self {
+        match serde_json::from_str::<ErrorResponse>(&text) {
+            Ok(err) => Self::from_code(err.code, err.detail),
+            Err(_) => Self::Response { status, text },
+        }
+    }
+
+    fn from_code(code: u32, detail: String) -> Self {
+        match code {
+            10002 => Self::OutputAlreadySigned,
+            10003 => Self::TokenNotVerified,
+            11001 => Self::TokenAlreadySpent,
+            11002 => Self::TransactionUnbalanced,
+            11005 => Self::UnitNotSupported,
+            11006 => Self::AmountOutOfLimitRange,
+            11007 => Self::DuplicateInputs,
+            11008 => Self::DuplicateOutputs,
+            11009 => Self::MultipleUnits,
+            11010 => Self::UnitMismatch,
+            11011 => Self::AmountlessInvoiceNotSupported,
+            11012 => Self::InvoiceAmountMismatch,
+            12001 => Self::KeysetNotFound,
+            12002 => Self::KeysetInactive,
+            20001 => Self::QuoteNotPaid,
+            20002 => Self::TokensAlreadyIssued,
+            20003 => Self::MintingDisabled,
+            20005 => Self::QuotePending,
+            20006 => Self::InvoiceAlreadyPaid,
+            20007 => Self::QuoteExpired,
+            20008 => Self::InvalidQuoteSignature,
+            20009 => Self::PubkeyRequired,
+            _ => Self::Unknown { code, detail },
+        }
+    }
+}
+
+impl std::fmt::Display for MintError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::OutputAlreadySigned => write!(f, "Blinded message of output already signed"),
+            Self::TokenNotVerified => write!(f, "Token could not be verified"),
+            Self::TokenAlreadySpent => write!(f, "Token is already spent"),
+            Self::TransactionUnbalanced => write!(f, "Transaction is not balanced"),
+            Self::UnitNotSupported => write!(f, "Unit in request is not supported"),
+            Self::AmountOutOfLimitRange => write!(f, "Amount outside of limit range"),
+            Self::DuplicateInputs => write!(f, "Duplicate inputs provided"),
+            Self::DuplicateOutputs => write!(f, "Duplicate outputs provided"),
+            Self::MultipleUnits => write!(f, "Inputs/Outputs of multiple units"),
+            Self::UnitMismatch => write!(f, "Inputs and outputs not of same unit"),
+            Self::AmountlessInvoiceNotSupported => write!(f, "Amountless invoice is not supported"),
+            Self::InvoiceAmountMismatch => write!(f, "Amount in request does not equal invoice"),
+            Self::KeysetNotFound => write!(f, "Keyset is not known"),
+            Self::KeysetInactive => write!(f, "Keyset is inactive, cannot sign messages"),
+            Self::QuoteNotPaid => write!(f, "Quote request is not paid"),
+            Self::TokensAlreadyIssued => write!(f, "Tokens have already been issued for quote"),
+            Self::MintingDisabled => write!(f, "Minting is disabled"),
+            Self::QuotePending => write!(f, "Quote is pending"),
+            Self::InvoiceAlreadyPaid => write!(f, "Invoice already paid"),
+            Self::QuoteExpired => write!(f, "Quote is expired"),
+            Self::InvalidQuoteSignature => write!(f, "Signature for mint request invalid"),
+            Self::PubkeyRequired => write!(f, "Pubkey required for mint quote"),
+            Self::Unknown { code, detail } => write!(f, "Mint error {}: {}", code, detail),
+            Self::Response { status, text } => write!(f, "Response: {} \n  {}", status, text),
+            Self::Http(_) => write!(f, "Request to mint failed"),
+            Self::Url(e) => write!(f, "Invalid mint URL: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for MintError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Http(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for MintError {
+    fn from(e: reqwest::Error) -> Self {
+        Self::Http(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_response() {
+        let err = MintError::from_response(
+            StatusCode::BAD_REQUEST,
+            r#"{"detail": "Token already spent.", "code": 11001}"#.to_string(),
+        );
+        assert!(matches!(err, MintError::TokenAlreadySpent));
+
+        let err = MintError::from_response(
+            StatusCode::BAD_REQUEST,
+            r#"{"detail": "oops", "code": 99999}"#.to_string(),
+        );
+        assert!(matches!(err, MintError::Unknown { code: 99999, detail } if detail == "oops"));
+
+        let err = MintError::from_response(StatusCode::BAD_GATEWAY, "Bad gateway".to_string());
+        assert!(matches!(
+            err,
+            MintError::Response {
+                status: StatusCode::BAD_GATEWAY,
+                ..
+            }
+        ));
+    }
+}
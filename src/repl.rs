@@ -1,9 +1,15 @@
-use std::{io::Write, str::FromStr};
+use std::{collections::BTreeSet, io::Write, str::FromStr};
 
 use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
 
-use crate::{cashu, wallet::Wallet};
+use toy_cashu::{
+    SpendPreview,
+    blocking::Wallet,
+    bolt11::Invoice,
+    cashu::{self, Amount, CurrencyUnit},
+    coin_selection::CoinSelection,
+};
 
 #[derive(Debug, Parser)]
 #[command(multicall = true)]
@@ -14,8 +20,16 @@ struct Cli {
 
 #[derive(Debug, Subcommand)]
 enum Command {
-    /// Display wallet balance
-    Balance,
+    /// Display wallet balance of each unit
+    Balance {
+        /// Display only balance in this unit
+        #[arg(long)]
+        unit: Option<CurrencyUnit>,
+    },
+    /// Display finished wallet operations
+    History,
+    /// Display unfinished mint quotes and pending melts
+    Quotes,
     /// Display wallet info
     #[command(name = "info")]
     WalletInfo,
@@ -27,22 +41,79 @@ enum Command {
     /// Get mint keysets
     #[command(name = "keysets")]
     MintKeysets,
+    /// Clear cached mint data and download it again
+    Refresh,
+    /// Swap proofs from inactive keysets into the active keyset
+    Rotate {
+        /// Swap only proofs in this unit
+        #[arg(long)]
+        unit: Option<CurrencyUnit>,
+    },
+    /// Display or set how proofs are selected for spending
+    CoinSelection {
+        /// One of: min-fee, min-proofs, prefer-inactive, oldest
+        strategy: Option<CoinSelection>,
+    },
+    /// Display or set number of proofs kept of each denomination
+    Denominations {
+        /// Proofs per denomination, 0 keeps as few proofs as possible
+        count: Option<u32>,
+    },
+    /// Swap proofs into the target distribution of denominations
+    #[command(alias = "consolidate")]
+    Rebalance {
+        /// Swap only proofs in this unit
+        #[arg(long)]
+        unit: Option<CurrencyUnit>,
+    },
     /// Mint tokens
     #[command(name = "mint")]
     MintTokens {
-        /// Amount in sats
-        sats: u64,
+        /// Amount in the unit
+        amount: Amount,
+        /// Unit of minted tokens, e.g. sat, msat, usd, eur
+        #[arg(long, default_value = "sat")]
+        unit: CurrencyUnit,
     },
     #[command(name = "melt")]
     /// Melt tokens
     MeltTokens {
         /// LN Invoice
         invoice: String,
+        /// Amount in sats to pay, only for invoices without amount
+        #[arg(long)]
+        amount: Option<Amount>,
+        /// Only show expected fees, do not pay
+        #[arg(long)]
+        dry_run: bool,
+        /// Split payment with other wallets (multi-path payment)
+        #[arg(long, value_name = "WALLET", num_args = 1.., conflicts_with_all = ["amount", "dry_run"])]
+        mpp: Vec<String>,
+        /// Proof selection strategy for this payment only
+        #[arg(long, value_name = "STRATEGY")]
+        coin_selection: Option<CoinSelection>,
+        /// Unit of proofs to pay with
+        #[arg(long, default_value = "sat", conflicts_with = "mpp")]
+        unit: CurrencyUnit,
+    },
+    /// Check state of pending melt, recover returned change or inputs
+    MeltStatus {
+        /// Melt quote ID
+        quote_id: String,
     },
     /// Generate Cashu V4 token
     Send {
-        /// Amount in sats
-        sats: u64,
+        /// Amount in the unit
+        amount: Amount,
+        /// Unit of the token
+        #[arg(long, default_value = "sat")]
+        unit: CurrencyUnit,
+        /// Only show expected fees, do not create token
+        #[arg(long)]
+        dry_run: bool,
+        /// Proof selection strategy for this token only
+        #[arg(long, value_name = "STRATEGY")]
+        coin_selection: Option<CoinSelection>,
     },
     /// Receive via Cashu V4 token
     Receive {
@@ -55,6 +126,18 @@ enum Command {
 
 pub fn start(wallet: Wallet) -> Result<()> {
     let mut repl = Repl { wallet };
+
+    // proofs from inactive keysets may become unredeemable, offer to swap them
+    let rotated = repl
+        .held_units()
+        .and_then(|units| units.iter().try_for_each(|unit| repl.rotate(unit)));
+    if let Err(err) = rotated {
+        writeln!(
+            std::io::stdout(),
+            "  WARN: failed to rotate proofs: {err:?}"
+        )?;
+    }
+
     loop {
         let line = repl.readline()?;
         let line = line.trim();
@@ -91,13 +174,94 @@ impl Repl {
         let args = line.split_whitespace();
         let cli = Cli::try_parse_from(args)?;
 
-        match cli.command {
-            Command::Balance => {
-                writeln!(std::io::stdout(), "  Total: {}", self.wallet.balance())?;
-                let mut amounts = self.wallet.proofs().map(|p| p.amount).collect::<Vec<_>>();
-                amounts.sort();
-                amounts.reverse();
-                writeln!(std::io::stdout(), "  Amounts: {:?}", amounts)?;
+        let strategy = match &cli.command {
+            Command::MeltTokens { coin_selection, .. } | Command::Send { coin_selection, .. } => {
+                *coin_selection
+            }
+            _ => None,
+        };
+        let Some(strategy) = strategy else {
+            return self.execute(cli.command);
+        };
+
+        // the strategy applies only to this command, the previous one is restored even on error
+        let previous = self.wallet.set_coin_selector(Box::new(strategy));
+        let result = self.execute(cli.command);
+        self.wallet.set_coin_selector(previous);
+        result
+    }
+
+    fn execute(&mut self, command: Command) -> Result<bool> {
+        match command {
+            Command::Balance { unit } => {
+                let units = match unit {
+                    Some(unit) => vec![unit],
+                    None => self.held_units()?,
+                };
+                for unit in units {
+                    writeln!(
+                        std::io::stdout(),
+                        "  Spendable: {} {}",
+                        self.wallet.balance(&unit)?,
+                        unit
+                    )?;
+                    let pending = self.wallet.pending_balance(&unit)?;
+                    if pending > Amount::ZERO {
+                        writeln!(std::io::stdout(), "  Pending: {} {}", pending, unit)?;
+                    }
+                    let mut amounts = self
+                        .wallet
+                        .unit_proofs(&unit)?
+                        .iter()
+                        .map(|p| p.amount)
+                        .collect::<Vec<_>>();
+                    amounts.sort();
+                    amounts.reverse();
+                    writeln!(std::io::stdout(), "  Amounts: {:?}", amounts)?;
+                }
+                std::io::stdout().flush()?;
+            }
+            Command::History => {
+                for entry in self.wallet.history()? {
+                    write!(
+                        std::io::stdout(),
+                        "  {} {:?}: {} {} (fee: {} {})",
+                        entry.timestamp,
+                        entry.kind,
+                        entry.amount,
+                        entry.unit,
+                        entry.fee,
+                        entry.unit
+                    )?;
+                    if let Some(preimage) = entry.preimage {
+                        write!(std::io::stdout(), "; preimage: {}", preimage)?;
+                    }
+                    writeln!(std::io::stdout())?;
+                }
+                std::io::stdout().flush()?;
+            }
+            Command::Quotes => {
+                for quote in self.wallet.mint_quotes()? {
+                    writeln!(
+                        std::io::stdout(),
+                        "  Mint quote {}: {} {}; state: {:?}; expiry: {}",
+                        quote.quote,
+                        quote.amount,
+                        quote.unit,
+                        quote.state,
+                        format_expiry(quote.expiry),
+                    )?;
+                }
+                for melt in self.wallet.pending_melts()? {
+                    writeln!(
+                        std::io::stdout(),
+                        "  Pending melt {}: {} {}; expiry: {}",
+                        melt.quote_id,
+                        melt.amount,
+                        melt.unit,
+                        format_expiry(melt.expiry),
+                    )?;
+                }
                 std::io::stdout().flush()?;
             }
             Command::WalletInfo => {
@@ -105,7 +269,7 @@ impl Repl {
                 writeln!(
                     std::io::stdout(),
                     "  Name: {}, Mint: {}",
-                    w.name,
+                    w.name(),
                     w.mint_url()
                 )?;
                 std::io::stdout().flush()?;
@@ -138,46 +302,159 @@ impl Repl {
                 writeln!(std::io::stdout(), "{:#?}", self.wallet.mint_keysets(false)?)?;
                 std::io::stdout().flush()?;
             }
-            Command::MintTokens { sats } => {
-                let mut amounts = self.wallet.mint_tokens(sats)?;
+            Command::Refresh => {
+                let changes = self.wallet.refresh()?;
+                if changes.is_empty() {
+                    writeln!(std::io::stdout(), "  Keysets unchanged")?;
+                }
+                for change in changes {
+                    writeln!(std::io::stdout(), "  WARN: {}", change)?;
+                }
+                std::io::stdout().flush()?;
+            }
+            Command::Rotate { unit } => {
+                let units = match unit {
+                    Some(unit) => vec![unit],
+                    None => self.held_units()?,
+                };
+                for unit in units {
+                    self.rotate(&unit)?;
+                }
+            }
+            Command::CoinSelection { strategy } => {
+                if let Some(strategy) = strategy {
+                    self.wallet.set_coin_selection(strategy)?;
+                }
+                writeln!(
+                    std::io::stdout(),
+                    "  Coin selection: {}",
+                    self.wallet.coin_selection()?
+                )?;
+                std::io::stdout().flush()?;
+            }
+            Command::Denominations { count } => {
+                if let Some(count) = count {
+                    self.wallet.set_proofs_per_denomination(count)?;
+                }
+                writeln!(
+                    std::io::stdout(),
+                    "  Proofs per denomination: {}",
+                    self.wallet.proofs_per_denomination()?
+                )?;
+                std::io::stdout().flush()?;
+            }
+            Command::Rebalance { unit } => {
+                let units = match unit {
+                    Some(unit) => vec![unit],
+                    None => self.held_units()?,
+                };
+                for unit in units {
+                    self.rebalance(&unit)?;
+                }
+            }
+            Command::MintTokens { amount, unit } => {
+                let mut amounts = self.wallet.mint_tokens(amount, &unit)?;
                 amounts.sort();
                 amounts.reverse();
-                writeln!(std::io::stdout(), "  Minted amounts: {:?}", amounts)?;
+                writeln!(
+                    std::io::stdout(),
+                    "  Minted amounts ({}): {:?}",
+                    unit,
+                    amounts
+                )?;
                 std::io::stdout().flush()?;
             }
-            Command::MeltTokens { invoice } => {
-                let res = self.wallet.melt_tokens(&invoice)?;
-                let returned_change: u64 = res
-                    .change
-                    .unwrap_or_default()
-                    .iter()
-                    .map(|s| s.amount)
-                    .sum();
+            Command::MeltTokens { invoice, mpp, .. } if !mpp.is_empty() => {
+                self.melt_mpp(&invoice, &mpp)?;
+            }
+            Command::MeltTokens {
+                invoice,
+                amount,
+                dry_run: true,
+                unit,
+                ..
+            } => {
+                self.print_invoice(&Wallet::validate_invoice(&invoice, amount)?, amount)?;
+                let preview = self.wallet.preview_melt(&invoice, amount, &unit)?;
+                self.print_preview(&preview, &unit)?;
+            }
+            Command::MeltTokens {
+                invoice,
+                amount,
+                dry_run: false,
+                unit,
+                ..
+            } => {
+                self.print_invoice(&Wallet::validate_invoice(&invoice, amount)?, amount)?;
+                if !self.confirm("  Pay this invoice? [y/N] ")? {
+                    return Ok(false);
+                }
+
+                let res = self.wallet.melt_tokens(&invoice, amount, &unit)?;
+                let returned_change =
+                    cashu::Amount::try_sum(res.change.iter().flatten().map(|s| s.amount))?;
+                let ln_fee = res
+                    .fee_reserve
+                    .try_sub(returned_change)
+                    .context("mint returned more change than LN fee reserve")?;
 
                 writeln!(
                     std::io::stdout(),
-                    "  Melted: {} sats; LN invoice: {:?}; LN fee: {}; quote ID: {}",
+                    "  Melted: {} {}; LN invoice: {:?}; LN fee: {}; quote ID: {}",
                     res.amount,
+                    res.unit,
                     res.state,
-                    res.fee_reserve - returned_change,
+                    ln_fee,
                     res.quote,
                 )?;
+                self.print_preimage(&res)?;
+                std::io::stdout().flush()?;
+            }
+            Command::MeltStatus { quote_id } => {
+                let res = self.wallet.melt_status(&quote_id)?;
+                writeln!(
+                    std::io::stdout(),
+                    "  Quote ID: {}; amount: {} {}; LN invoice: {:?}; expiry: {}",
+                    res.quote,
+                    res.amount,
+                    res.unit,
+                    res.state,
+                    format_expiry(res.expiry),
+                )?;
+                self.print_preimage(&res)?;
                 std::io::stdout().flush()?;
             }
-            Command::Send { sats } => {
-                let (token, fee) = self.wallet.prepare_cashu_token(sats)?;
+            Command::Send {
+                amount,
+                unit,
+                dry_run: true,
+                ..
+            } => {
+                let preview = self.wallet.preview_send(amount, &unit)?;
+                self.print_preview(&preview, &unit)?;
+            }
+            Command::Send {
+                amount,
+                unit,
+                dry_run: false,
+                ..
+            } => {
+                let (token, fee) = self.wallet.prepare_cashu_token(amount, &unit)?;
                 writeln!(std::io::stdout(), "  Token: {}", token)?;
-                writeln!(std::io::stdout(), "  Fee: {} sat", fee)?;
+                writeln!(std::io::stdout(), "  Fee: {} {}", fee, unit)?;
                 std::io::stdout().flush()?;
             }
             Command::Receive { token } => {
                 let token = cashu::TokenV4::from_str(&token).context("parse token")?;
+                let unit = token.unit().clone();
                 let (amount, fee) = self.wallet.receive_via_cashu_token(token)?;
                 writeln!(
                     std::io::stdout(),
-                    "  Received: {} sats (fee: {} sat)",
-                    amount - fee,
-                    fee
+                    "  Received: {} {} (fee: {} {})",
+                    amount.try_sub(fee)?,
+                    unit,
+                    fee,
+                    unit
                 )?;
                 std::io::stdout().flush()?;
             }
@@ -188,11 +465,245 @@ impl Repl {
         Ok(false)
     }
 
+    /// Returns units of spendable proofs and pending melts, sat if the wallet is empty
+    fn held_units(&self) -> Result<Vec<CurrencyUnit>> {
+        let mut units = self.wallet.balances()?.into_keys().collect::<BTreeSet<_>>();
+        units.extend(self.wallet.pending_melts()?.into_iter().map(|m| m.unit));
+        if units.is_empty() {
+            units.insert(CurrencyUnit::Sat);
+        }
+        Ok(units.into_iter().collect())
+    }
+
+    /// Shows the fee for swapping proofs in `unit` from inactive keysets and swaps them if user agrees
+    fn rotate(&mut self, unit: &CurrencyUnit) -> Result<()> {
+        let (amount, fee) = self.wallet.estimate_rotation(unit)?;
+        if amount == 0 {
+            return Ok(());
+        }
+
+        writeln!(
+            std::io::stdout(),
+            "  {} {} are held in proofs from inactive keysets, swap fee: {} {}",
+            amount,
+            unit,
+            fee,
+            unit
+        )?;
+        if !self.confirm("  Swap them into active keyset? [y/N] ")? {
+            return Ok(());
+        }
+
+        let (amount, fee) = self.wallet.rotate_proofs(unit)?;
+        writeln!(
+            std::io::stdout(),
+            "  Swapped: {} {} (fee: {} {})",
+            amount.try_sub(fee)?,
+            unit,
+            fee,
+            unit
+        )?;
+        std::io::stdout().flush()?;
+
+        Ok(())
+    }
+
+    /// Shows the fee for swapping proofs in `unit` into the target distribution and swaps them if user agrees
+    fn rebalance(&mut self, unit: &CurrencyUnit) -> Result<()> {
+        let (amount, fee) = self.wallet.estimate_rebalance(unit)?;
+        if amount == 0 {
+            writeln!(std::io::stdout(), "  Proofs already match the target")?;
+            std::io::stdout().flush()?;
+            return Ok(());
+        }
+
+        writeln!(
+            std::io::stdout(),
+            "  {} {} have to be swapped, swap fee: {} {}",
+            amount,
+            unit,
+            fee,
+            unit
+        )?;
+        if !self.confirm("  Swap them? [y/N] ")? {
+            return Ok(());
+        }
+
+        let (amount, fee) = self.wallet.rebalance_proofs(unit)?;
+        writeln!(
+            std::io::stdout(),
+            "  Swapped: {} {} (fee: {} {})",
+            amount.try_sub(fee)?,
+            unit,
+            fee,
+            unit
+        )?;
+        std::io::stdout().flush()?;
+
+        Ok(())
+    }
+
+    /// Pays invoice together with other wallets, each of them pays part of it
+    fn melt_mpp(&mut self, invoice: &str, wallet_names: &[String]) -> Result<()> {
+        self.print_invoice(&Wallet::validate_invoice(invoice, None)?, None)?;
+
+        let mut others = vec![];
+        for name in wallet_names {
+            if name == self.wallet.name() {
+                continue;
+            }
+            let password = zeroize::Zeroizing::new(rpassword::prompt_password(format!(
+                "  Password of wallet {}: ",
+                name
+            ))?);
+            others.push(Wallet::open(name, password.trim())?);
+        }
+
+        if !self.confirm("  Pay this invoice? [y/N] ")? {
+            return Ok(());
+        }
+
+        let mut wallets = std::iter::once(&mut self.wallet)
+            .chain(others.iter_mut())
+            .collect::<Vec<_>>();
+        for (name, result) in Wallet::melt_mpp(&mut wallets, invoice)? {
+            match result {
+                Ok(res) => {
+                    writeln!(
+                        std::io::stdout(),
+                        "  {}: melted {} {}; LN invoice: {:?}; quote ID: {}",
+                        name,
+                        res.amount,
+                        res.unit,
+                        res.state,
+                        res.quote,
+                    )?;
+                    self.print_preimage(&res)?;
+                }
+                Err(err) => writeln!(std::io::stdout(), "  {}: error: {err:?}", name)?,
+            }
+        }
+        std::io::stdout().flush()?;
+        Ok(())
+    }
+
+    fn print_invoice(&self, invoice: &Invoice, amount: Option<Amount>) -> Result<()> {
+        match invoice.amount_msat {
+            Some(msat) if msat.is_multiple_of(1000) => {
+                writeln!(std::io::stdout(), "  Invoice amount: {} sats", msat / 1000)?
+            }
+            Some(msat) => writeln!(std::io::stdout(), "  Invoice amount: {} msat", msat)?,
+            None => writeln!(
+                std::io::stdout(),
+                "  Invoice amount: none (paying {} sats)",
+                amount.unwrap_or_default()
+            )?,
+        }
+        if let Some(description) = &invoice.description {
+            writeln!(std::io::stdout(), "  Description: {}", description)?;
+        }
+        writeln!(
+            std::io::stdout(),
+            "  Payment hash: {}",
+            invoice.payment_hash_hex()
+        )?;
+        writeln!(
+            std::io::stdout(),
+            "  Expiry: {}",
+            format_expiry(Some(invoice.expires_at()))
+        )?;
+        writeln!(std::io::stdout(), "  Network: {}", invoice.network)?;
+        std::io::stdout().flush()?;
+        Ok(())
+    }
+
+    /// Prints payment preimage and whether it proves payment of the invoice
+    fn print_preimage(&self, quote: &cashu::types::MeltQuote) -> Result<()> {
+        let Some(preimage) = &quote.payment_preimage else {
+            return Ok(());
+        };
+
+        let verified = Invoice::from_str(&quote.request)
+            .is_ok_and(|invoice| invoice.verify_preimage(preimage));
+        if verified {
+            writeln!(std::io::stdout(), "  Payment preimage: {}", preimage)?;
+        } else {
+            writeln!(
+                std::io::stdout(),
+                "  Payment preimage: {} (does NOT match payment hash, payment is not proven!)",
+                preimage
+            )?;
+        }
+        Ok(())
+    }
+
+    fn print_preview(&self, preview: &SpendPreview, unit: &CurrencyUnit) -> Result<()> {
+        writeln!(std::io::stdout(), "  Amount: {} {}", preview.amount, unit)?;
+        if preview.fee_reserve > Amount::ZERO {
+            writeln!(
+                std::io::stdout(),
+                "  LN fee reserve: {} {}",
+                preview.fee_reserve,
+                unit
+            )?;
+        }
+        writeln!(
+            std::io::stdout(),
+            "  Input fee: {} {}",
+            preview.input_fee,
+            unit
+        )?;
+        if preview.swap_needed {
+            writeln!(
+                std::io::stdout(),
+                "  Change swap needed (fee: {} {})",
+                preview.swap_fee,
+                unit
+            )?;
+        } else {
+            writeln!(std::io::stdout(), "  No change swap needed")?;
+        }
+        writeln!(
+            std::io::stdout(),
+            "  Worst-case total: {} {}",
+            preview.worst_case_total()?,
+            unit
+        )?;
+        std::io::stdout().flush()?;
+        Ok(())
+    }
+
+    fn confirm(&self, question: &str) -> Result<bool> {
+        write!(std::io::stdout(), "{}", question)?;
+        std::io::stdout().flush()?;
+        let mut buffer = String::new();
+        std::io::stdin().read_line(&mut buffer)?;
+        Ok(matches!(buffer.trim(), "y" | "Y" | "yes"))
+    }
+
     fn readline(&self) -> Result<String> {
-        write!(std::io::stdout(), "{}> ", self.wallet.name)?;
+        write!(std::io::stdout(), "{}> ", self.wallet.name())?;
         std::io::stdout().flush()?;
         let mut buffer = String::new();
         std::io::stdin().read_line(&mut buffer)?;
         Ok(buffer)
     }
 }
+
+/// Formats quote expiry relative to current time
+fn format_expiry(expiry: Option<u64>) -> String {
+    let Some(expiry) = expiry else {
+        return "none".to_string();
+    };
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+
+    if expiry <= now {
+        "expired".to_string()
+    } else {
+        format!("in {} s", expiry - now)
+    }
+}
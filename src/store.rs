@@ -0,0 +1,523 @@
+use std::str::FromStr;
+
+use anyhow::{Result, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::cashu::{
+    Amount, CurrencyUnit, KeysetId, Proof, Proofs,
+    crypto::{Secret, SecretKey},
+    types::{AllKeysetInfos, AllKeysets, MintQuote},
+};
+
+pub use file::FileStore;
+pub use memory::MemoryStore;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStore;
+
+mod file;
+mod memory;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+/// Persistent storage of wallet data
+pub trait WalletStore: Send + Sync {
+    /// URL of the mint the wallet belongs to
+    fn mint_url(&self) -> Result<String>;
+
+    /// Returns all stored proofs with their states
+    fn proofs(&self) -> Result<Vec<StoredProof>>;
+    /// Atomically adds `added` proofs as unspent and moves `updated` proofs (matched by their secrets) to `state`.
+    /// Fails without any change if some of `updated` proofs is not stored.
+    fn update_proofs(
+        &mut self,
+        added: &[Proof],
+        updated: &[Proof],
+        state: ProofState,
+    ) -> Result<()>;
+    /// Removes proofs from the store
+    fn remove_proofs(&mut self, proofs: &[Proof]) -> Result<()>;
+
+    fn add_proofs(&mut self, proofs: &[Proof]) -> Result<()> {
+        self.update_proofs(proofs, &[], ProofState::Unspent)
+    }
+
+    fn set_proofs_state(&mut self, proofs: &[Proof], state: ProofState) -> Result<()> {
+        self.update_proofs(&[], proofs, state)
+    }
+
+    fn mint_quotes(&self) -> Result<Vec<MintQuote>>;
+    fn add_mint_quote(&mut self, quote: &MintQuote) -> Result<()>;
+    fn remove_mint_quote(&mut self, quote_id: &str) -> Result<()>;
+
+    fn pending_melts(&self) -> Result<Vec<PendingMelt>>;
+    fn add_pending_melt(&mut self, melt: &PendingMelt) -> Result<()>;
+    fn remove_pending_melt(&mut self, quote_id: &str) -> Result<()>;
+
+    /// Returns cached mint keys and keyset infos
+    fn keysets(&self) -> Result<(Option<AllKeysets>, Option<AllKeysetInfos>)>;
+    fn set_keysets(&mut self, keys: &AllKeysets, keyset_infos: &AllKeysetInfos) -> Result<()>;
+
+    fn history(&self) -> Result<Vec<HistoryEntry>>;
+    fn add_history(&mut self, entry: &HistoryEntry) -> Result<()>;
+
+    /// Returns current value of the keyset counter (e.g. for deterministic secrets)
+    fn counter(&self, keyset_id: &KeysetId) -> Result<u32>;
+    /// Increments the keyset counter by `n` and returns its new value
+    fn increment_counter(&mut self, keyset_id: &KeysetId, n: u32) -> Result<u32>;
+
+    /// Returns value of wallet setting, e.g. preferred coin selection
+    fn setting(&self, key: &str) -> Result<Option<String>>;
+    fn set_setting(&mut self, key: &str, value: &str) -> Result<()>;
+}
+
+/// Proof together with its state in the wallet
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredProof {
+    #[serde(flatten)]
+    pub proof: Proof,
+    #[serde(default)]
+    pub state: ProofState,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProofState {
+    /// Can be spent
+    #[default]
+    Unspent,
+    /// Selected as input by an operation in progress
+    Reserved,
+    /// Sent to mint in a payment which is not settled yet
+    PendingSpend,
+    /// Spent or given away
+    Spent,
+}
+
+/// Melt sent to mint, kept until its payment is settled, so the change can be recovered or inputs restored
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingMelt {
+    pub quote_id: String,
+    /// LN invoice
+    pub request: String,
+    pub amount: Amount,
+    pub fee_reserve: Amount,
+    #[serde(default)]
+    pub unit: CurrencyUnit,
+    /// Unix timestamp of quote expiry
+    #[serde(default)]
+    pub expiry: Option<u64>,
+    /// Keyset of blank outputs
+    pub keyset_id: KeysetId,
+    pub inputs: Proofs,
+    /// NUT-08 blank outputs for receiving overpaid fee
+    pub blank_outputs: Vec<BlankOutput>,
+}
+
+/// Secret and blinding factor of a blank output
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlankOutput {
+    pub secret: Secret,
+    /// Blinding factor `r`
+    pub r: SecretKey,
+}
+
+/// Record of finished wallet operation
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// Unix timestamp
+    pub timestamp: u64,
+    pub kind: TransactionKind,
+    pub amount: Amount,
+    pub fee: Amount,
+    #[serde(default)]
+    pub unit: CurrencyUnit,
+    /// Hex encoded payment preimage of melt, proof of LN payment
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preimage: Option<String>,
+}
+
+impl HistoryEntry {
+    pub fn new(kind: TransactionKind, amount: Amount, fee: Amount) -> Self {
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+
+        Self {
+            timestamp,
+            kind,
+            amount,
+            fee,
+            unit: CurrencyUnit::Sat,
+            preimage: None,
+        }
+    }
+
+    pub fn with_unit(mut self, unit: &CurrencyUnit) -> Self {
+        self.unit = unit.clone();
+        self
+    }
+
+    pub fn with_preimage(mut self, preimage: Option<String>) -> Self {
+        self.preimage = preimage;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransactionKind {
+    Mint,
+    Melt,
+    Send,
+    Receive,
+}
+
+/// Kind of store used by wallets kept in wallets directory
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum StoreKind {
+    /// Age-encrypted file
+    #[default]
+    File,
+    /// SQLite database
+    #[cfg(feature = "sqlite")]
+    Sqlite,
+}
+
+impl StoreKind {
+    pub const ALL: &[StoreKind] = &[
+        StoreKind::File,
+        #[cfg(feature = "sqlite")]
+        StoreKind::Sqlite,
+    ];
+
+    /// Extension of the wallet file
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::File => ".bin",
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite => ".db",
+        }
+    }
+}
+
+impl FromStr for StoreKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "file" => Ok(Self::File),
+            #[cfg(feature = "sqlite")]
+            "sqlite" => Ok(Self::Sqlite),
+            _ => bail!("Unknown store kind '{}'", s),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::cashu::{test_proof, types::QuoteState};
+
+    const KEYSET_ID: &str = "00ad268c4d1f5826";
+
+    fn keyset_id() -> KeysetId {
+        KEYSET_ID.parse().unwrap()
+    }
+
+    fn keysets() -> (AllKeysets, AllKeysetInfos) {
+        let keys = serde_json::from_value(serde_json::json!({
+            "keysets": [{
+                "id": KEYSET_ID,
+                "unit": "sat",
+                "keys": {"1": "0273129c5719e599379a974a626363c333c56cafc0e6d01abe46d5808280789c63"},
+            }]
+        }))
+        .unwrap();
+        let infos = serde_json::from_value(serde_json::json!({
+            "keysets": [{"id": KEYSET_ID, "unit": "sat", "active": true}]
+        }))
+        .unwrap();
+        (keys, infos)
+    }
+
+    /// Runs the same checks against every store implementation
+    fn check_store(store: &mut dyn WalletStore) {
+        assert_eq!(store.mint_url().unwrap(), "http://localhost:3338/");
+
+        // proofs
+        store
+            .add_proofs(&[
+                test_proof(1, KEYSET_ID, "s1"),
+                test_proof(2, KEYSET_ID, "s2"),
+            ])
+            .unwrap();
+        store
+            .update_proofs(
+                &[test_proof(4, KEYSET_ID, "s3")],
+                &[test_proof(1, KEYSET_ID, "s1")],
+                ProofState::Spent,
+            )
+            .unwrap();
+        store
+            .set_proofs_state(&[test_proof(2, KEYSET_ID, "s2")], ProofState::Reserved)
+            .unwrap();
+        let states = store
+            .proofs()
+            .unwrap()
+            .iter()
+            .map(|p| (p.proof.amount.value(), p.state))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            states,
+            vec![
+                (1, ProofState::Spent),
+                (2, ProofState::Reserved),
+                (4, ProofState::Unspent)
+            ]
+        );
+        // unknown proofs are not updated, nor is anything else
+        assert!(
+            store
+                .update_proofs(
+                    &[test_proof(16, KEYSET_ID, "s6")],
+                    &[
+                        test_proof(2, KEYSET_ID, "s2"),
+                        test_proof(8, KEYSET_ID, "unknown")
+                    ],
+                    ProofState::Spent
+                )
+                .is_err()
+        );
+        assert_eq!(store.proofs().unwrap().len(), 3);
+        assert_eq!(store.proofs().unwrap()[1].state, ProofState::Reserved);
+        // proofs with already stored secrets are rejected
+        assert!(
+            store
+                .add_proofs(&[test_proof(16, KEYSET_ID, "s3")])
+                .is_err()
+        );
+        assert!(
+            store
+                .add_proofs(&[
+                    test_proof(16, KEYSET_ID, "s6"),
+                    test_proof(16, KEYSET_ID, "s6")
+                ])
+                .is_err()
+        );
+        assert_eq!(store.proofs().unwrap().len(), 3);
+        store
+            .remove_proofs(&[
+                test_proof(1, KEYSET_ID, "s1"),
+                test_proof(2, KEYSET_ID, "s2"),
+                test_proof(4, KEYSET_ID, "s3"),
+            ])
+            .unwrap();
+        assert!(store.proofs().unwrap().is_empty());
+
+        // mint quotes
+        let quote = MintQuote {
+            quote: "q1".to_string(),
+            request: "lnbc".to_string(),
+            amount: 10.into(),
+            unit: CurrencyUnit::Sat,
+            state: QuoteState::Unpaid,
+            expiry: None,
+            pubkey: None,
+        };
+        store.add_mint_quote(&quote).unwrap();
+        assert_eq!(store.mint_quotes().unwrap()[0].quote, "q1");
+        // quote with the same id replaces the stored one
+        let paid = MintQuote {
+            state: QuoteState::Paid,
+            ..quote.clone()
+        };
+        store.add_mint_quote(&paid).unwrap();
+        let quotes = store.mint_quotes().unwrap();
+        assert_eq!(quotes.len(), 1);
+        assert_eq!(quotes[0].state, QuoteState::Paid);
+        store.remove_mint_quote("q1").unwrap();
+        assert!(store.mint_quotes().unwrap().is_empty());
+
+        // pending melts
+        let melt = PendingMelt {
+            quote_id: "q2".to_string(),
+            request: "lnbc".to_string(),
+            amount: 5.into(),
+            fee_reserve: 2.into(),
+            unit: CurrencyUnit::Sat,
+            expiry: Some(1700000000),
+            keyset_id: keyset_id(),
+            inputs: vec![test_proof(8, KEYSET_ID, "s4")],
+            blank_outputs: vec![BlankOutput {
+                secret: "s5".into(),
+                r: SecretKey::from_slice(&[1; 32]).unwrap(),
+            }],
+        };
+        store.add_pending_melt(&melt).unwrap();
+        store.add_pending_melt(&melt).unwrap();
+        let melts = store.pending_melts().unwrap();
+        assert_eq!(melts.len(), 1);
+        assert_eq!(melts[0].quote_id, "q2");
+        assert_eq!(melts[0].inputs[0].secret().as_bytes(), b"s4");
+        assert_eq!(melts[0].blank_outputs[0].secret.as_bytes(), b"s5");
+        store.remove_pending_melt("q2").unwrap();
+        assert!(store.pending_melts().unwrap().is_empty());
+
+        // keysets
+        assert!(matches!(store.keysets().unwrap(), (None, None)));
+        let (keys, infos) = keysets();
+        store.set_keysets(&keys, &infos).unwrap();
+        let (stored_keys, stored_infos) = store.keysets().unwrap();
+        assert!(stored_keys.unwrap().changes(&keys).is_empty());
+        assert!(stored_infos.unwrap().changes(&infos).is_empty());
+
+        // history
+        let entry = HistoryEntry::new(TransactionKind::Receive, 8.into(), 1.into())
+            .with_unit(&CurrencyUnit::Usd);
+        store.add_history(&entry).unwrap();
+        let melt = HistoryEntry::new(TransactionKind::Melt, 16.into(), 2.into())
+            .with_preimage(Some("00".into()));
+        store.add_history(&melt).unwrap();
+        assert_eq!(store.history().unwrap(), vec![entry, melt]);
+
+        // counters
+        assert_eq!(store.counter(&keyset_id()).unwrap(), 0);
+        assert_eq!(store.increment_counter(&keyset_id(), 3).unwrap(), 3);
+        assert_eq!(store.increment_counter(&keyset_id(), 2).unwrap(), 5);
+        assert_eq!(store.counter(&keyset_id()).unwrap(), 5);
+
+        // settings
+        assert_eq!(store.setting("coin_selection").unwrap(), None);
+        store.set_setting("coin_selection", "oldest").unwrap();
+        store.set_setting("coin_selection", "min-proofs").unwrap();
+        assert_eq!(
+            store.setting("coin_selection").unwrap().as_deref(),
+            Some("min-proofs")
+        );
+    }
+
+    #[test]
+    fn test_memory_store() {
+        let mut store = MemoryStore::new("http://localhost:3338/");
+        check_store(&mut store);
+
+        assert!(store.increment_counter(&keyset_id(), u32::MAX).is_err());
+        assert_eq!(store.counter(&keyset_id()).unwrap(), 5);
+    }
+
+    #[test]
+    fn test_file_store() {
+        let path = std::env::temp_dir().join(format!("toy-cashu-test-{}.bin", std::process::id()));
+        let key = zeroize::Zeroizing::new([7u8; 32]);
+
+        let mut store = FileStore::create(&path, key.clone(), "http://localhost:3338/").unwrap();
+        check_store(&mut store);
+
+        // data survive reopening
+        let store = FileStore::open(&path, key).unwrap();
+        assert_eq!(store.history().unwrap().len(), 2);
+        assert_eq!(store.counter(&keyset_id()).unwrap(), 5);
+        // temporary file was renamed over the wallet file
+        let tmp_path = path.with_extension("bin.tmp");
+        assert!(!tmp_path.exists());
+
+        // failed save leaves the data unchanged
+        let mut store = store;
+        std::fs::create_dir(&tmp_path).unwrap();
+        assert!(
+            store
+                .add_history(&HistoryEntry::new(
+                    TransactionKind::Send,
+                    1.into(),
+                    Amount::ZERO
+                ))
+                .is_err()
+        );
+        assert_eq!(store.history().unwrap().len(), 2);
+        std::fs::remove_dir(&tmp_path).unwrap();
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_sqlite_store() {
+        check_store(&mut SqliteStore::in_memory("http://localhost:3338/").unwrap());
+
+        let path = std::env::temp_dir().join(format!("toy-cashu-test-{}.db", std::process::id()));
+        let key = zeroize::Zeroizing::new([7u8; 32]);
+
+        let mut store = SqliteStore::create(&path, key.clone(), "http://localhost:3338/").unwrap();
+        store
+            .add_proofs(&[test_proof(1, KEYSET_ID, "secret-1")])
+            .unwrap();
+        drop(store);
+
+        // secrets are not readable without the key
+        let raw = std::fs::read(&path).unwrap();
+        assert!(!raw.windows(8).any(|w| w == b"secret-1"));
+        assert!(SqliteStore::open(&path, zeroize::Zeroizing::new([8u8; 32])).is_err());
+
+        let mut store = SqliteStore::open(&path, key).unwrap();
+        assert_eq!(
+            store.proofs().unwrap()[0].proof.secret().as_bytes(),
+            b"secret-1"
+        );
+        store
+            .set_proofs_state(&[test_proof(1, KEYSET_ID, "secret-1")], ProofState::Spent)
+            .unwrap();
+        assert_eq!(store.proofs().unwrap()[0].state, ProofState::Spent);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_sqlite_store_encrypts_plaintext_data() {
+        let path = std::env::temp_dir().join(format!(
+            "toy-cashu-test-plaintext-{}.db",
+            std::process::id()
+        ));
+        let key = zeroize::Zeroizing::new([7u8; 32]);
+
+        // database written by version storing proofs in plain text
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE meta (key TEXT PRIMARY KEY, value TEXT NOT NULL);
+             CREATE TABLE proofs (secret TEXT PRIMARY KEY, data TEXT NOT NULL);
+             INSERT INTO meta (key, value) VALUES ('mint_url', 'http://localhost:3338/');",
+        )
+        .unwrap();
+        let stored = StoredProof {
+            proof: test_proof(2, KEYSET_ID, "secret-2"),
+            state: ProofState::Unspent,
+        };
+        conn.execute(
+            "INSERT INTO proofs (secret, data) VALUES ('secret-2', ?1)",
+            [serde_json::to_string(&stored).unwrap()],
+        )
+        .unwrap();
+        drop(conn);
+
+        let mut store = SqliteStore::open(&path, key.clone()).unwrap();
+        store
+            .set_proofs_state(
+                &[test_proof(2, KEYSET_ID, "secret-2")],
+                ProofState::Reserved,
+            )
+            .unwrap();
+        drop(store);
+
+        let store = SqliteStore::open(&path, key).unwrap();
+        let proofs = store.proofs().unwrap();
+        assert_eq!(proofs.len(), 1);
+        assert_eq!(proofs[0].state, ProofState::Reserved);
+        drop(store);
+        let raw = std::fs::read(&path).unwrap();
+        assert!(!raw.windows(8).any(|w| w == b"secret-2"));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,197 @@
+use std::{
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use zeroize::Zeroizing;
+
+use super::{HistoryEntry, MemoryStore, PendingMelt, ProofState, StoredProof, WalletStore};
+use crate::cashu::{
+    KeysetId, Proof,
+    types::{AllKeysetInfos, AllKeysets, MintQuote},
+};
+
+/// Store keeping wallet data in age-encrypted file. The whole file is rewritten after every change.
+pub struct FileStore {
+    path: PathBuf,
+    encryption_key: Zeroizing<[u8; 32]>,
+    data: MemoryStore,
+}
+
+impl FileStore {
+    /// Creates new wallet file for the mint with `mint_url`
+    pub fn create(
+        path: &Path,
+        encryption_key: Zeroizing<[u8; 32]>,
+        mint_url: &str,
+    ) -> Result<Self> {
+        let store = Self {
+            path: path.to_owned(),
+            encryption_key,
+            data: MemoryStore::new(mint_url),
+        };
+        store.save()?;
+        Ok(store)
+    }
+
+    /// Opens existing wallet file
+    pub fn open(path: &Path, encryption_key: Zeroizing<[u8; 32]>) -> Result<Self> {
+        let data = load(path, &encryption_key)?;
+        Ok(Self {
+            path: path.to_owned(),
+            encryption_key,
+            data,
+        })
+    }
+
+    fn save(&self) -> Result<()> {
+        save(&self.data, &self.path, &self.encryption_key)
+    }
+
+    /// Applies `change` to a copy of the data, which replaces current data only once it is saved
+    fn update<T>(&mut self, change: impl FnOnce(&mut MemoryStore) -> Result<T>) -> Result<T> {
+        let mut data = self.data.clone();
+        let result = change(&mut data)?;
+        save(&data, &self.path, &self.encryption_key)?;
+        self.data = data;
+        Ok(result)
+    }
+}
+
+impl WalletStore for FileStore {
+    fn mint_url(&self) -> Result<String> {
+        self.data.mint_url()
+    }
+
+    fn proofs(&self) -> Result<Vec<StoredProof>> {
+        self.data.proofs()
+    }
+
+    fn update_proofs(
+        &mut self,
+        added: &[Proof],
+        updated: &[Proof],
+        state: ProofState,
+    ) -> Result<()> {
+        self.update(|data| data.update_proofs(added, updated, state))
+    }
+
+    fn remove_proofs(&mut self, proofs: &[Proof]) -> Result<()> {
+        self.update(|data| data.remove_proofs(proofs))
+    }
+
+    fn mint_quotes(&self) -> Result<Vec<MintQuote>> {
+        self.data.mint_quotes()
+    }
+
+    fn add_mint_quote(&mut self, quote: &MintQuote) -> Result<()> {
+        self.update(|data| data.add_mint_quote(quote))
+    }
+
+    fn remove_mint_quote(&mut self, quote_id: &str) -> Result<()> {
+        self.update(|data| data.remove_mint_quote(quote_id))
+    }
+
+    fn pending_melts(&self) -> Result<Vec<PendingMelt>> {
+        self.data.pending_melts()
+    }
+
+    fn add_pending_melt(&mut self, melt: &PendingMelt) -> Result<()> {
+        self.update(|data| data.add_pending_melt(melt))
+    }
+
+    fn remove_pending_melt(&mut self, quote_id: &str) -> Result<()> {
+        self.update(|data| data.remove_pending_melt(quote_id))
+    }
+
+    fn keysets(&self) -> Result<(Option<AllKeysets>, Option<AllKeysetInfos>)> {
+        self.data.keysets()
+    }
+
+    fn set_keysets(&mut self, keys: &AllKeysets, keyset_infos: &AllKeysetInfos) -> Result<()> {
+        self.update(|data| data.set_keysets(keys, keyset_infos))
+    }
+
+    fn history(&self) -> Result<Vec<HistoryEntry>> {
+        self.data.history()
+    }
+
+    fn add_history(&mut self, entry: &HistoryEntry) -> Result<()> {
+        self.update(|data| data.add_history(entry))
+    }
+
+    fn counter(&self, keyset_id: &KeysetId) -> Result<u32> {
+        self.data.counter(keyset_id)
+    }
+
+    fn increment_counter(&mut self, keyset_id: &KeysetId, n: u32) -> Result<u32> {
+        self.update(|data| data.increment_counter(keyset_id, n))
+    }
+
+    fn setting(&self, key: &str) -> Result<Option<String>> {
+        self.data.setting(key)
+    }
+
+    fn set_setting(&mut self, key: &str, value: &str) -> Result<()> {
+        self.update(|data| data.set_setting(key, value))
+    }
+}
+
+/// Load and decrypt wallet data from disk
+fn load(path: &Path, decryption_key: &[u8; 32]) -> Result<MemoryStore> {
+    let file = File::open(path).with_context(|| format!("open file {}", path.display()))?;
+
+    let decryptor = age::Decryptor::new(file)?;
+
+    let identity = age::scrypt::Identity::new(passphrase(decryption_key)?);
+    let reader = decryptor.decrypt(std::iter::once(&identity as _))?;
+
+    let data = serde_json::from_reader(reader).context("deserialize from file")?;
+
+    Ok(data)
+}
+
+/// Encrypt and save wallet data to disk
+fn save(data: &MemoryStore, path: &Path, encryption_key: &[u8; 32]) -> Result<()> {
+    let dir = path.parent().unwrap();
+    if !dir.exists() {
+        std::fs::create_dir(dir).with_context(|| format!("create dir {}", dir.display()))?;
+    }
+
+    // write to temporary file first, so the old file stays intact if writing fails
+    let mut tmp_name = path.file_name().context("wallet file name")?.to_owned();
+    tmp_name.push(".tmp");
+    let tmp_path = dir.join(tmp_name);
+
+    let mut file =
+        File::create(&tmp_path).with_context(|| format!("create file {}", tmp_path.display()))?;
+
+    let encryptor = age::Encryptor::with_user_passphrase(passphrase(encryption_key)?);
+
+    let mut encryptor_writer = encryptor
+        .wrap_output(&mut file)
+        .context("write encryption header")?;
+
+    let plaintext = Zeroizing::new(serde_json::to_vec(data).context("serialize wallet data")?);
+    encryptor_writer
+        .write_all(&plaintext)
+        .context("write wallet data to encrypted file")?;
+    encryptor_writer
+        .finish()
+        .context("finish writing of encrypted file")?;
+    file.sync_all()
+        .with_context(|| format!("sync file {}", tmp_path.display()))?;
+
+    std::fs::rename(&tmp_path, path).with_context(|| format!("replace file {}", path.display()))?;
+
+    Ok(())
+}
+
+/// Hex encoded `key` used as age passphrase, encoded without leaving copies behind
+fn passphrase(key: &[u8; 32]) -> Result<age::secrecy::SecretString> {
+    let mut key_hex = Zeroizing::new([0u8; 64]);
+    hex::encode_to_slice(key, key_hex.as_mut())?;
+    Ok(std::str::from_utf8(key_hex.as_ref())?.into())
+}
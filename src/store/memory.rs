@@ -0,0 +1,172 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Result, anyhow, bail};
+use serde::{Deserialize, Serialize};
+
+use super::{HistoryEntry, PendingMelt, ProofState, StoredProof, WalletStore};
+use crate::cashu::{
+    KeysetId, Proof,
+    types::{AllKeysetInfos, AllKeysets, MintQuote},
+};
+
+/// Store keeping wallet data in memory only, useful for tests.
+/// It is also serialized as a whole by [`super::FileStore`].
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct MemoryStore {
+    #[serde(rename = "mint")]
+    mint_url: String,
+    #[serde(default)]
+    keys: Option<AllKeysets>,
+    #[serde(default)]
+    keysets: Option<AllKeysetInfos>,
+    #[serde(default)]
+    proofs: Vec<StoredProof>,
+    #[serde(default)]
+    mint_quotes: Vec<MintQuote>,
+    #[serde(default)]
+    pending_melts: Vec<PendingMelt>,
+    #[serde(default)]
+    history: Vec<HistoryEntry>,
+    #[serde(default)]
+    counters: BTreeMap<KeysetId, u32>,
+    #[serde(default)]
+    settings: BTreeMap<String, String>,
+}
+
+impl MemoryStore {
+    pub fn new(mint_url: &str) -> Self {
+        Self {
+            mint_url: mint_url.to_owned(),
+            ..Default::default()
+        }
+    }
+}
+
+impl WalletStore for MemoryStore {
+    fn mint_url(&self) -> Result<String> {
+        Ok(self.mint_url.clone())
+    }
+
+    fn proofs(&self) -> Result<Vec<StoredProof>> {
+        Ok(self.proofs.clone())
+    }
+
+    fn update_proofs(
+        &mut self,
+        added: &[Proof],
+        updated: &[Proof],
+        state: ProofState,
+    ) -> Result<()> {
+        if let Some(unknown) = updated
+            .iter()
+            .find(|u| !self.proofs.iter().any(|p| p.proof.secret() == u.secret()))
+        {
+            bail!(
+                "Proof of amount {} from keyset {} is not stored",
+                unknown.amount,
+                unknown.keyset_id
+            );
+        }
+        for (i, proof) in added.iter().enumerate() {
+            if self
+                .proofs
+                .iter()
+                .any(|p| p.proof.secret() == proof.secret())
+                || added[..i].iter().any(|a| a.secret() == proof.secret())
+            {
+                bail!(
+                    "Proof of amount {} from keyset {} is already stored",
+                    proof.amount,
+                    proof.keyset_id
+                );
+            }
+        }
+
+        for p in self.proofs.iter_mut() {
+            if updated.iter().any(|u| u.secret() == p.proof.secret()) {
+                p.state = state;
+            }
+        }
+        self.proofs.extend(added.iter().map(|proof| StoredProof {
+            proof: proof.clone(),
+            state: ProofState::Unspent,
+        }));
+        Ok(())
+    }
+
+    fn remove_proofs(&mut self, proofs: &[Proof]) -> Result<()> {
+        self.proofs
+            .retain(|p| !proofs.iter().any(|r| r.secret() == p.proof.secret()));
+        Ok(())
+    }
+
+    fn mint_quotes(&self) -> Result<Vec<MintQuote>> {
+        Ok(self.mint_quotes.clone())
+    }
+
+    fn add_mint_quote(&mut self, quote: &MintQuote) -> Result<()> {
+        self.mint_quotes.retain(|q| q.quote != quote.quote);
+        self.mint_quotes.push(quote.clone());
+        Ok(())
+    }
+
+    fn remove_mint_quote(&mut self, quote_id: &str) -> Result<()> {
+        self.mint_quotes.retain(|q| q.quote != quote_id);
+        Ok(())
+    }
+
+    fn pending_melts(&self) -> Result<Vec<PendingMelt>> {
+        Ok(self.pending_melts.clone())
+    }
+
+    fn add_pending_melt(&mut self, melt: &PendingMelt) -> Result<()> {
+        self.pending_melts.retain(|m| m.quote_id != melt.quote_id);
+        self.pending_melts.push(melt.clone());
+        Ok(())
+    }
+
+    fn remove_pending_melt(&mut self, quote_id: &str) -> Result<()> {
+        self.pending_melts.retain(|m| m.quote_id != quote_id);
+        Ok(())
+    }
+
+    fn keysets(&self) -> Result<(Option<AllKeysets>, Option<AllKeysetInfos>)> {
+        Ok((self.keys.clone(), self.keysets.clone()))
+    }
+
+    fn set_keysets(&mut self, keys: &AllKeysets, keyset_infos: &AllKeysetInfos) -> Result<()> {
+        self.keys = Some(keys.clone());
+        self.keysets = Some(keyset_infos.clone());
+        Ok(())
+    }
+
+    fn history(&self) -> Result<Vec<HistoryEntry>> {
+        Ok(self.history.clone())
+    }
+
+    fn add_history(&mut self, entry: &HistoryEntry) -> Result<()> {
+        self.history.push(entry.clone());
+        Ok(())
+    }
+
+    fn counter(&self, keyset_id: &KeysetId) -> Result<u32> {
+        Ok(self.counters.get(keyset_id).copied().unwrap_or_default())
+    }
+
+    fn increment_counter(&mut self, keyset_id: &KeysetId, n: u32) -> Result<u32> {
+        let counter = self.counters.entry(*keyset_id).or_default();
+        *counter = counter
+            .checked_add(n)
+            .ok_or_else(|| anyhow!("Counter of keyset {} overflows", keyset_id))?;
+        Ok(*counter)
+    }
+
+    fn setting(&self, key: &str) -> Result<Option<String>> {
+        Ok(self.settings.get(key).cloned())
+    }
+
+    fn set_setting(&mut self, key: &str, value: &str) -> Result<()> {
+        self.settings.insert(key.to_owned(), value.to_owned());
+        Ok(())
+    }
+}
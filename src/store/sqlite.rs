@@ -0,0 +1,423 @@
+use std::{
+    path::Path,
+    sync::{Mutex, MutexGuard},
+};
+
+use anyhow::{Context, Result, anyhow, bail};
+use base64::{Engine as _, engine::general_purpose::STANDARD};
+use chacha20poly1305::{ChaCha20Poly1305, KeyInit, Nonce, aead::Aead};
+use rusqlite::{Connection, OptionalExtension, Transaction, params};
+use secp256k1::{
+    hashes::{Hash, HashEngine, hmac, sha256},
+    rand::RngCore,
+};
+use zeroize::Zeroizing;
+
+use super::{HistoryEntry, PendingMelt, ProofState, StoredProof, WalletStore};
+use crate::cashu::{
+    KeysetId, Proof,
+    types::{AllKeysetInfos, AllKeysets, MintQuote},
+};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS meta (key TEXT PRIMARY KEY, value TEXT NOT NULL);
+    CREATE TABLE IF NOT EXISTS proofs (secret TEXT PRIMARY KEY, data TEXT NOT NULL);
+    CREATE TABLE IF NOT EXISTS mint_quotes (quote TEXT PRIMARY KEY, data TEXT NOT NULL);
+    CREATE TABLE IF NOT EXISTS pending_melts (quote TEXT PRIMARY KEY, data TEXT NOT NULL);
+    CREATE TABLE IF NOT EXISTS history (id INTEGER PRIMARY KEY, data TEXT NOT NULL);
+    CREATE TABLE IF NOT EXISTS counters (keyset_id TEXT PRIMARY KEY, counter INTEGER NOT NULL);
+    CREATE TABLE IF NOT EXISTS settings (key TEXT PRIMARY KEY, value TEXT NOT NULL);
+";
+
+/// Value stored in `meta` table, encrypted to check the key when opening database
+const KEY_CHECK: &str = "toy-cashu";
+
+/// Size of nonce prepended to encrypted values
+const NONCE_SIZE: usize = 12;
+
+/// Store keeping wallet data in SQLite database.
+///
+/// Proofs and pending melts (holding blinding factors of change outputs) are encrypted with
+/// `encryption_key` and proofs are looked up by keyed hash of their secrets. Other data are
+/// stored in plain text.
+pub struct SqliteStore {
+    conn: Mutex<Connection>,
+    encryption_key: Zeroizing<[u8; 32]>,
+}
+
+impl SqliteStore {
+    /// Creates new database for wallet of the mint with `mint_url`
+    pub fn create(
+        path: &Path,
+        encryption_key: Zeroizing<[u8; 32]>,
+        mint_url: &str,
+    ) -> Result<Self> {
+        if let Some(dir) = path.parent()
+            && !dir.exists()
+        {
+            std::fs::create_dir(dir).with_context(|| format!("create dir {}", dir.display()))?;
+        }
+
+        let conn = Connection::open(path)
+            .with_context(|| format!("create database {}", path.display()))?;
+        Self::init(conn, encryption_key, mint_url)
+    }
+
+    /// Opens existing database, encrypting data of databases created by older versions
+    pub fn open(path: &Path, encryption_key: Zeroizing<[u8; 32]>) -> Result<Self> {
+        let conn =
+            Connection::open(path).with_context(|| format!("open database {}", path.display()))?;
+        // add tables introduced by newer versions
+        conn.execute_batch(SCHEMA).context("create tables")?;
+        let store = Self {
+            conn: Mutex::new(conn),
+            encryption_key,
+        };
+
+        match store.meta("key_check")? {
+            Some(check) => {
+                store
+                    .decrypt(&check)
+                    .context("invalid encryption key of database")?;
+            }
+            None => store.encrypt_plaintext().context("encrypt database")?,
+        }
+        Ok(store)
+    }
+
+    /// Creates database living in memory only, encrypted with random key
+    pub fn in_memory(mint_url: &str) -> Result<Self> {
+        let mut encryption_key = Zeroizing::new([0; 32]);
+        secp256k1::rand::rng().fill_bytes(encryption_key.as_mut());
+        Self::init(Connection::open_in_memory()?, encryption_key, mint_url)
+    }
+
+    fn init(conn: Connection, encryption_key: Zeroizing<[u8; 32]>, mint_url: &str) -> Result<Self> {
+        conn.execute_batch(SCHEMA).context("create tables")?;
+        conn.execute(
+            "INSERT INTO meta (key, value) VALUES ('mint_url', ?1)",
+            [mint_url],
+        )?;
+        let store = Self {
+            conn: Mutex::new(conn),
+            encryption_key,
+        };
+        store.set_meta("key_check", &store.encrypt(KEY_CHECK.as_bytes())?)?;
+        Ok(store)
+    }
+
+    /// Encrypts proofs and pending melts stored in plain text by older versions
+    fn encrypt_plaintext(&self) -> Result<()> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        {
+            let proofs = Self::plaintext_rows::<StoredProof>(&tx, "proofs")?;
+            tx.execute("DELETE FROM proofs", [])?;
+            for stored in proofs {
+                self.insert_proof(&tx, &stored)?;
+            }
+
+            let melts = Self::plaintext_rows::<PendingMelt>(&tx, "pending_melts")?;
+            let mut update = tx.prepare("UPDATE pending_melts SET data = ?2 WHERE quote = ?1")?;
+            for melt in melts {
+                update.execute(params![melt.quote_id, self.encrypt_json(&melt)?])?;
+            }
+
+            tx.execute(
+                "INSERT INTO meta (key, value) VALUES ('key_check', ?1)",
+                [self.encrypt(KEY_CHECK.as_bytes())?],
+            )?;
+        }
+        tx.commit()?;
+        // drop plain text left in free pages
+        conn.execute_batch("VACUUM")?;
+        Ok(())
+    }
+
+    fn plaintext_rows<T: serde::de::DeserializeOwned>(
+        tx: &Transaction,
+        table: &str,
+    ) -> Result<Vec<T>> {
+        let mut stmt = tx.prepare(&format!("SELECT data FROM {} ORDER BY rowid", table))?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+
+        let mut values = vec![];
+        for data in rows {
+            let data = Zeroizing::new(data?);
+            values.push(serde_json::from_str(&data).with_context(|| format!("parse {}", table))?);
+        }
+        Ok(values)
+    }
+
+    fn cipher(&self) -> ChaCha20Poly1305 {
+        ChaCha20Poly1305::new(self.encryption_key.as_ref().into())
+    }
+
+    /// Encrypts `data` with random nonce, returns base64 of nonce followed by ciphertext
+    fn encrypt(&self, data: &[u8]) -> Result<String> {
+        let mut nonce = [0; NONCE_SIZE];
+        secp256k1::rand::rng().fill_bytes(&mut nonce);
+
+        let ciphertext = self
+            .cipher()
+            .encrypt(&Nonce::from(nonce), data)
+            .map_err(|_| anyhow!("encryption failed"))?;
+
+        Ok(STANDARD.encode([nonce.as_slice(), &ciphertext].concat()))
+    }
+
+    fn decrypt(&self, data: &str) -> Result<Zeroizing<Vec<u8>>> {
+        let data = STANDARD.decode(data).context("decode base64")?;
+        if data.len() < NONCE_SIZE {
+            bail!("encrypted data are too short");
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_SIZE);
+        let nonce: [u8; NONCE_SIZE] = nonce.try_into()?;
+
+        Ok(Zeroizing::new(
+            self.cipher()
+                .decrypt(&Nonce::from(nonce), ciphertext)
+                .map_err(|_| anyhow!("decryption failed"))?,
+        ))
+    }
+
+    fn encrypt_json<T: serde::Serialize>(&self, value: &T) -> Result<String> {
+        self.encrypt(&Zeroizing::new(serde_json::to_vec(value)?))
+    }
+
+    /// Returns keyed hash of proof secret identifying the proof in database
+    fn proof_id(&self, proof: &Proof) -> String {
+        let mut engine = hmac::HmacEngine::<sha256::Hash>::new(self.encryption_key.as_ref());
+        engine.input(proof.secret().as_bytes());
+        hmac::Hmac::<sha256::Hash>::from_engine(engine).to_string()
+    }
+
+    fn insert_proof(&self, tx: &Transaction, stored: &StoredProof) -> Result<()> {
+        tx.execute(
+            "INSERT INTO proofs (secret, data) VALUES (?1, ?2)",
+            params![self.proof_id(&stored.proof), self.encrypt_json(stored)?],
+        )?;
+        Ok(())
+    }
+
+    fn conn(&self) -> Result<MutexGuard<'_, Connection>> {
+        self.conn
+            .lock()
+            .map_err(|_| anyhow!("database connection lock is poisoned"))
+    }
+
+    fn meta(&self, key: &str) -> Result<Option<String>> {
+        Ok(self
+            .conn()?
+            .query_row("SELECT value FROM meta WHERE key = ?1", [key], |row| {
+                row.get(0)
+            })
+            .optional()?)
+    }
+
+    fn set_meta(&self, key: &str, value: &str) -> Result<()> {
+        self.conn()?.execute(
+            "INSERT OR REPLACE INTO meta (key, value) VALUES (?1, ?2)",
+            [key, value],
+        )?;
+        Ok(())
+    }
+
+    /// Returns all values from the `data` column of `table` deserialized from JSON
+    fn load_all<T: serde::de::DeserializeOwned>(&self, table: &str) -> Result<Vec<T>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(&format!("SELECT data FROM {} ORDER BY rowid", table))?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+
+        let mut values = vec![];
+        for data in rows {
+            values.push(serde_json::from_str(&data?).with_context(|| format!("parse {}", table))?);
+        }
+        Ok(values)
+    }
+
+    /// Returns all values from the `data` column of `table` decrypted and deserialized from JSON
+    fn load_encrypted<T: serde::de::DeserializeOwned>(&self, table: &str) -> Result<Vec<T>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(&format!("SELECT data FROM {} ORDER BY rowid", table))?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+
+        let mut values = vec![];
+        for data in rows {
+            let data = self
+                .decrypt(&data?)
+                .with_context(|| format!("decrypt {}", table))?;
+            values.push(serde_json::from_slice(&data).with_context(|| format!("parse {}", table))?);
+        }
+        Ok(values)
+    }
+}
+
+impl WalletStore for SqliteStore {
+    fn mint_url(&self) -> Result<String> {
+        self.meta("mint_url")?
+            .ok_or_else(|| anyhow!("mint url is missing in the database"))
+    }
+
+    fn proofs(&self) -> Result<Vec<StoredProof>> {
+        self.load_encrypted("proofs")
+    }
+
+    fn update_proofs(
+        &mut self,
+        added: &[Proof],
+        updated: &[Proof],
+        state: ProofState,
+    ) -> Result<()> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        {
+            let mut update = tx.prepare("UPDATE proofs SET data = ?2 WHERE secret = ?1")?;
+            for proof in updated {
+                let stored = StoredProof {
+                    proof: proof.clone(),
+                    state,
+                };
+                let changed =
+                    update.execute(params![self.proof_id(proof), self.encrypt_json(&stored)?])?;
+                if changed == 0 {
+                    // dropped transaction is rolled back
+                    bail!(
+                        "Proof of amount {} from keyset {} is not stored",
+                        proof.amount,
+                        proof.keyset_id
+                    );
+                }
+            }
+
+            for proof in added {
+                let stored = StoredProof {
+                    proof: proof.clone(),
+                    state: ProofState::Unspent,
+                };
+                self.insert_proof(&tx, &stored)?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn remove_proofs(&mut self, proofs: &[Proof]) -> Result<()> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        {
+            let mut delete = tx.prepare("DELETE FROM proofs WHERE secret = ?1")?;
+            for proof in proofs {
+                delete.execute([self.proof_id(proof)])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn mint_quotes(&self) -> Result<Vec<MintQuote>> {
+        self.load_all("mint_quotes")
+    }
+
+    fn add_mint_quote(&mut self, quote: &MintQuote) -> Result<()> {
+        self.conn()?.execute(
+            "INSERT OR REPLACE INTO mint_quotes (quote, data) VALUES (?1, ?2)",
+            params![quote.quote, serde_json::to_string(quote)?],
+        )?;
+        Ok(())
+    }
+
+    fn remove_mint_quote(&mut self, quote_id: &str) -> Result<()> {
+        self.conn()?
+            .execute("DELETE FROM mint_quotes WHERE quote = ?1", [quote_id])?;
+        Ok(())
+    }
+
+    fn pending_melts(&self) -> Result<Vec<PendingMelt>> {
+        self.load_encrypted("pending_melts")
+    }
+
+    fn add_pending_melt(&mut self, melt: &PendingMelt) -> Result<()> {
+        self.conn()?.execute(
+            "INSERT OR REPLACE INTO pending_melts (quote, data) VALUES (?1, ?2)",
+            params![melt.quote_id, self.encrypt_json(melt)?],
+        )?;
+        Ok(())
+    }
+
+    fn remove_pending_melt(&mut self, quote_id: &str) -> Result<()> {
+        self.conn()?
+            .execute("DELETE FROM pending_melts WHERE quote = ?1", [quote_id])?;
+        Ok(())
+    }
+
+    fn keysets(&self) -> Result<(Option<AllKeysets>, Option<AllKeysetInfos>)> {
+        let keys = self
+            .meta("keys")?
+            .map(|data| serde_json::from_str(&data))
+            .transpose()
+            .context("parse keys")?;
+        let keyset_infos = self
+            .meta("keysets")?
+            .map(|data| serde_json::from_str(&data))
+            .transpose()
+            .context("parse keysets")?;
+        Ok((keys, keyset_infos))
+    }
+
+    fn set_keysets(&mut self, keys: &AllKeysets, keyset_infos: &AllKeysetInfos) -> Result<()> {
+        self.set_meta("keys", &serde_json::to_string(keys)?)?;
+        self.set_meta("keysets", &serde_json::to_string(keyset_infos)?)
+    }
+
+    fn history(&self) -> Result<Vec<HistoryEntry>> {
+        self.load_all("history")
+    }
+
+    fn add_history(&mut self, entry: &HistoryEntry) -> Result<()> {
+        self.conn()?.execute(
+            "INSERT INTO history (data) VALUES (?1)",
+            [serde_json::to_string(entry)?],
+        )?;
+        Ok(())
+    }
+
+    fn counter(&self, keyset_id: &KeysetId) -> Result<u32> {
+        Ok(self
+            .conn()?
+            .query_row(
+                "SELECT counter FROM counters WHERE keyset_id = ?1",
+                [keyset_id.to_string()],
+                |row| row.get(0),
+            )
+            .optional()?
+            .unwrap_or_default())
+    }
+
+    fn increment_counter(&mut self, keyset_id: &KeysetId, n: u32) -> Result<u32> {
+        Ok(self.conn()?.query_row(
+            "INSERT INTO counters (keyset_id, counter) VALUES (?1, ?2)
+             ON CONFLICT (keyset_id) DO UPDATE SET counter = counter + excluded.counter
+             RETURNING counter",
+            params![keyset_id.to_string(), n],
+            |row| row.get(0),
+        )?)
+    }
+
+    fn setting(&self, key: &str) -> Result<Option<String>> {
+        Ok(self
+            .conn()?
+            .query_row("SELECT value FROM settings WHERE key = ?1", [key], |row| {
+                row.get(0)
+            })
+            .optional()?)
+    }
+
+    fn set_setting(&mut self, key: &str, value: &str) -> Result<()> {
+        self.conn()?.execute(
+            "INSERT OR REPLACE INTO settings (key, value) VALUES (?1, ?2)",
+            [key, value],
+        )?;
+        Ok(())
+    }
+}
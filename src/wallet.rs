@@ -3,37 +3,62 @@ use std::{
     fs::File,
     io::{Read, Write},
     path::{Path, PathBuf},
+    str::FromStr,
 };
 
-use anyhow::{Context, Result, anyhow, bail};
-use serde::{Deserialize, Serialize};
+use anyhow::{Context, anyhow, bail};
+use zeroize::Zeroizing;
 
-use crate::{
-    cashu::types::AmountKeys,
-    mint::{Mint, MintInfo},
+use crate::cashu::{
+    Amount, BlindSignatures, BlindedMessage, BlindedSecret, CurrencyUnit, KeysetId, Proof, Proofs,
+    ProofsMethods, TokenV4,
+    crypto::{Secret, SecretKey, hash_to_curve},
+    types::{
+        AllKeysetInfos, AllKeysets, KeysetChange, MeltOptions, MeltQuote, MintQuote, QuoteState,
+        SpendingState,
+    },
 };
+#[cfg(feature = "sqlite")]
+use crate::store::SqliteStore;
 use crate::{
-    cashu::{
-        BlindedMessage, BlindedSecret, Proof, Proofs, TokenV4,
-        crypto::{PublicKey, Secret, SecretKey},
-        types::{AllKeysetInfos, AllKeysets, MeltQuote, MintQuote, QuoteState},
+    bolt11::Invoice,
+    cashu::types::AmountKeys,
+    mint::{Mint, MintError, MintInfo},
+    store::{
+        BlankOutput, FileStore, HistoryEntry, PendingMelt, ProofState, StoreKind, TransactionKind,
+        WalletStore,
     },
-    file, helpers,
 };
 
+use coin_selection::{Candidate, CoinSelection, CoinSelector};
+pub use error::WalletError;
+
+pub mod coin_selection;
+mod distribution;
+mod error;
+mod mpp;
+
+type Result<T, E = WalletError> = std::result::Result<T, E>;
+
 const WALLETS_DIR: &str = ".wallets";
-const WALLET_FILE_EXT: &str = ".bin";
 
-#[derive(Deserialize, Serialize)]
+/// Maximal number of outputs an amount can be split into
+const MAX_OUTPUTS: u64 = 10_000;
+
+/// Maximal number of steps searching for a split of amount into keyset denominations
+const MAX_SPLIT_TRIES: u32 = 100_000;
+
+/// Store setting with name of the wallet [`CoinSelection`] strategy
+const COIN_SELECTION_SETTING: &str = "coin_selection";
+
+/// Store setting with number of proofs the wallet keeps of each denomination
+const PROOFS_PER_DENOMINATION_SETTING: &str = "proofs_per_denomination";
+
 pub struct Wallet {
     pub name: String,
-    #[serde(flatten)]
     mint: Mint,
-    proofs: Proofs,
-    #[serde(skip)]
-    mint_quotes: Vec<MintQuote>,
-    #[serde(skip)]
-    encryption_key: [u8; 32],
+    store: Box<dyn WalletStore>,
+    coin_selector: Box<dyn CoinSelector>,
 }
 
 impl Wallet {
@@ -42,20 +67,16 @@ impl Wallet {
 
         let mut wallet_names = path
             .read_dir()
-            .context("read_dir call failed")?
+            .context("read_dir call failed")
+            .map_err(WalletError::Storage)?
             .flatten()
             .filter_map(|entry| {
                 let filename = entry.file_name().into_string().unwrap_or("???".to_string());
 
-                if filename.ends_with(WALLET_FILE_EXT) {
-                    let wallet_name = filename
-                        .strip_suffix(WALLET_FILE_EXT)
-                        .unwrap_or("???")
-                        .to_string();
-                    Some(wallet_name)
-                } else {
-                    None
-                }
+                StoreKind::ALL
+                    .iter()
+                    .find_map(|kind| filename.strip_suffix(kind.extension()))
+                    .map(|wallet_name| wallet_name.to_string())
             })
             .collect::<Vec<_>>();
 
@@ -64,43 +85,97 @@ impl Wallet {
         Ok(wallet_names)
     }
 
-    pub fn create(name: &str, mint: &str, password: &str) -> Result<Self> {
-        let file = PathBuf::from(WALLETS_DIR).join(Self::filename(name));
-
-        if file.exists() {
-            bail!("Wallet {} already exists!", name);
+    pub fn create(name: &str, mint: &str, password: &str, store_kind: StoreKind) -> Result<Self> {
+        if Self::stored_kind(name).is_some() {
+            return Err(anyhow!("Wallet {} already exists!", name).into());
         }
 
-        password::save(name, password)?;
-
-        let encryption_key = password::derive_encryption_key(password, name)?;
+        let mint = Mint::new(mint)?;
+
+        password::save(name, password).map_err(WalletError::Storage)?;
+
+        let path = Self::path(name, store_kind);
+        let encryption_key =
+            password::derive_encryption_key(password, name).map_err(WalletError::Crypto)?;
+        let store: Box<dyn WalletStore> = match store_kind {
+            StoreKind::File => Box::new(
+                FileStore::create(&path, encryption_key, &mint.url())
+                    .map_err(WalletError::Storage)?,
+            ),
+            #[cfg(feature = "sqlite")]
+            StoreKind::Sqlite => Box::new(
+                SqliteStore::create(&path, encryption_key, &mint.url())
+                    .map_err(WalletError::Storage)?,
+            ),
+        };
 
-        let w = Self {
+        Ok(Self {
             name: name.to_owned(),
-            mint: Mint::new(mint)?,
-            proofs: Default::default(),
-            mint_quotes: Default::default(),
-            encryption_key,
+            mint,
+            store,
+            coin_selector: Box::new(CoinSelection::default()),
+        })
+    }
+
+    pub async fn open(name: &str, password: &str) -> Result<Self> {
+        let Some(store_kind) = Self::stored_kind(name) else {
+            return Err(anyhow!("Wallet {} does not exist!", name).into());
         };
 
-        w.save()
-            .with_context(|| format!("save newly created wallet '{}'", name))?;
+        if !password::is_valid_for_wallet(password, name)
+            .context("validate password")
+            .map_err(WalletError::Storage)?
+        {
+            return Err(WalletError::InvalidPassword);
+        }
+
+        let path = Self::path(name, store_kind);
+        let decryption_key =
+            password::derive_encryption_key(password, name).map_err(WalletError::Crypto)?;
+        let store: Box<dyn WalletStore> = match store_kind {
+            StoreKind::File => Box::new(
+                FileStore::open(&path, decryption_key)
+                    .with_context(|| format!("load wallet {}", name))
+                    .map_err(WalletError::Storage)?,
+            ),
+            #[cfg(feature = "sqlite")]
+            StoreKind::Sqlite => Box::new(
+                SqliteStore::open(&path, decryption_key)
+                    .with_context(|| format!("load wallet {}", name))
+                    .map_err(WalletError::Storage)?,
+            ),
+        };
 
-        Ok(w)
+        Self::with_store(name, store).await
     }
 
-    pub fn open(name: &str, password: &str) -> Result<Self> {
-        let file = PathBuf::from(WALLETS_DIR).join(Self::filename(name));
+    /// Opens wallet backed by provided `store`, e.g. [`crate::store::MemoryStore`]
+    pub async fn with_store(name: &str, store: Box<dyn WalletStore>) -> Result<Self> {
+        let mint_url = store.mint_url().map_err(WalletError::Storage)?;
+        let (keysets, keyset_infos) = store.keysets().map_err(WalletError::Storage)?;
 
-        if !file.exists() {
-            bail!("Wallet {} does not exist!", name);
-        }
+        let mut w = Self {
+            name: name.to_owned(),
+            mint: Mint::new(&mint_url)?.with_cached_keysets(keysets, keyset_infos),
+            store,
+            coin_selector: Box::new(CoinSelection::default()),
+        };
+        w.coin_selector = Box::new(w.coin_selection()?);
 
-        if !password::is_valid_for_wallet(password, name).context("validate password")? {
-            bail!("Invalid password!");
+        // proofs may stay reserved if the previous session was interrupted
+        w.release_reserved_proofs();
+
+        // detect changes in mint keysets since the last session
+        match w.mint.refresh().await {
+            Ok(changes) => {
+                for change in changes {
+                    eprintln!("WARN: {}", change);
+                }
+                w.store_keysets()?;
+            }
+            Err(e) => eprintln!("WARN: failed to check mint keysets: {:#}", e),
         }
 
-        let w = Self::load(name, password).with_context(|| format!("load wallet {}", name))?;
         // TODO check mint quotes
         Ok(w)
     }
@@ -109,18 +184,69 @@ impl Wallet {
         self.mint.url()
     }
 
-    pub fn mint_info(&mut self) -> Result<MintInfo> {
-        let mut info = self.mint.get_info().cloned()?;
+    /// Returns coin selection strategy stored for this wallet
+    pub fn coin_selection(&self) -> Result<CoinSelection> {
+        let setting = self
+            .store
+            .setting(COIN_SELECTION_SETTING)
+            .map_err(WalletError::Storage)?;
+        match setting {
+            Some(strategy) => strategy.parse().map_err(WalletError::Storage),
+            None => Ok(CoinSelection::default()),
+        }
+    }
+
+    /// Stores coin selection strategy used for all spending from this wallet
+    pub fn set_coin_selection(&mut self, strategy: CoinSelection) -> Result<()> {
+        self.store
+            .set_setting(COIN_SELECTION_SETTING, &strategy.to_string())
+            .map_err(WalletError::Storage)?;
+        self.coin_selector = Box::new(strategy);
+        Ok(())
+    }
+
+    /// Returns number of proofs the wallet tries to keep of each denomination,
+    /// 0 keeps as few proofs as possible
+    pub fn proofs_per_denomination(&self) -> Result<u32> {
+        let setting = self
+            .store
+            .setting(PROOFS_PER_DENOMINATION_SETTING)
+            .map_err(WalletError::Storage)?;
+        match setting {
+            Some(count) => Ok(count
+                .parse()
+                .context("parse proofs per denomination")
+                .map_err(WalletError::Storage)?),
+            None => Ok(0),
+        }
+    }
+
+    /// Stores number of proofs to keep of each denomination. Swaps and receives then produce
+    /// outputs moving the wallet toward this distribution, see also [`Wallet::rebalance_proofs`].
+    pub fn set_proofs_per_denomination(&mut self, count: u32) -> Result<()> {
+        self.store
+            .set_setting(PROOFS_PER_DENOMINATION_SETTING, &count.to_string())
+            .map_err(WalletError::Storage)
+    }
+
+    /// Replaces coin selector until the wallet is closed or the selector is replaced again,
+    /// e.g. for a single command. Returns the previous selector.
+    pub fn set_coin_selector(&mut self, selector: Box<dyn CoinSelector>) -> Box<dyn CoinSelector> {
+        std::mem::replace(&mut self.coin_selector, selector)
+    }
+
+    pub async fn mint_info(&mut self) -> Result<MintInfo> {
+        let mut info = self.mint.get_info().await.cloned()?;
         info.url = self.mint_url();
         Ok(info)
     }
 
-    pub fn mint_keys(&mut self) -> Result<AllKeysets> {
-        self.mint.get_keys().cloned()
+    pub async fn mint_keys(&mut self) -> Result<AllKeysets> {
+        Ok(self.mint.get_keys().await.cloned()?)
     }
 
-    pub fn mint_keysets(&mut self, only_active: bool) -> Result<AllKeysetInfos> {
-        let mut ks = self.mint.get_keysets().cloned().context("get_keysets")?;
+    pub async fn mint_keysets(&mut self, only_active: bool) -> Result<AllKeysetInfos> {
+        let mut ks = self.mint.get_keysets().await.cloned()?;
         if only_active {
             let ks_vec = ks
                 .keysets
@@ -132,184 +258,385 @@ impl Wallet {
         Ok(ks)
     }
 
-    pub fn balance(&self) -> u64 {
-        self.proofs.iter().fold(0, |acc, x| acc + x.amount)
+    /// Clears cached mint data and downloads it again. Returns detected changes in keysets.
+    pub async fn refresh(&mut self) -> Result<Vec<KeysetChange>> {
+        let changes = self.mint.refresh().await?;
+        self.store_keysets()?;
+        Ok(changes)
     }
 
-    pub fn proofs(&self) -> std::slice::Iter<'_, Proof> {
-        self.proofs.iter()
+    /// Returns spendable amount in `unit`
+    pub fn balance(&self, unit: &CurrencyUnit) -> Result<Amount> {
+        Ok(self.unit_proofs(unit)?.total_amount()?)
     }
 
-    pub fn mint_tokens(&mut self, amount: u64) -> Result<Vec<u64>> {
-        let (quote, secret_key) = self.create_mint_quote(amount)?;
+    /// Returns spendable amount of each unit the wallet holds proofs in
+    pub fn balances(&self) -> Result<BTreeMap<CurrencyUnit, Amount>> {
+        self.group_by_unit(self.proofs()?)
+            .into_iter()
+            .map(|(unit, proofs)| Ok((unit, proofs.total_amount()?)))
+            .collect()
+    }
+
+    /// Returns amount in `unit` held in proofs used in payments which are not settled yet
+    pub fn pending_balance(&self, unit: &CurrencyUnit) -> Result<Amount> {
+        let pending = self.proofs_in_state(ProofState::PendingSpend)?;
+        Ok(self
+            .group_by_unit(pending)
+            .remove(unit)
+            .unwrap_or_default()
+            .total_amount()?)
+    }
+
+    /// Returns spendable proofs
+    pub fn proofs(&self) -> Result<Proofs> {
+        self.proofs_in_state(ProofState::Unspent)
+    }
+
+    /// Returns spendable proofs in `unit`
+    pub fn unit_proofs(&self, unit: &CurrencyUnit) -> Result<Proofs> {
+        Ok(self
+            .group_by_unit(self.proofs()?)
+            .remove(unit)
+            .unwrap_or_default())
+    }
+
+    /// Returns stored mint quotes whose tokens were not issued yet
+    pub fn mint_quotes(&self) -> Result<Vec<MintQuote>> {
+        self.store.mint_quotes().map_err(WalletError::Storage)
+    }
+
+    /// Returns melts whose payments are not settled yet
+    pub fn pending_melts(&self) -> Result<Vec<PendingMelt>> {
+        self.store.pending_melts().map_err(WalletError::Storage)
+    }
+
+    pub fn history(&self) -> Result<Vec<HistoryEntry>> {
+        self.store.history().map_err(WalletError::Storage)
+    }
+
+    /// Mints `amount` in `unit` after the returned quote is paid
+    pub async fn mint_tokens(
+        &mut self,
+        amount: Amount,
+        unit: &CurrencyUnit,
+    ) -> Result<Vec<Amount>> {
+        let (quote, secret_key) = self.create_mint_quote(amount, unit).await?;
 
         let quote_id = quote.quote.clone();
         let unit = quote.unit.clone();
 
-        // TODO store quote and secret key and request the mint later for its state
-        self.mint_quotes.push(quote);
+        // TODO store secret key and request the mint later for its state
+        self.store
+            .add_mint_quote(&quote)
+            .map_err(WalletError::Storage)?;
 
         // TODO loop over stored quotes
-        let quote = self.check_mint_quote(&quote_id)?;
+        let quote = self.check_mint_quote(&quote_id).await?;
         let invoice_state = quote.state;
 
+        if quote.is_expired() {
+            if let Err(e) = self.store.remove_mint_quote(&quote_id) {
+                eprintln!("WARN: failed to remove expired quote {}: {:#}", quote_id, e);
+            }
+            return Err(WalletError::QuoteExpired(quote_id));
+        }
+
         if invoice_state == QuoteState::Issued {
-            bail!("Tokens from the quote {} were already issued", quote_id);
+            return Err(anyhow!("Tokens from the quote {} were already issued", quote_id).into());
         }
 
         if invoice_state == QuoteState::Paid {
-            let amounts = Self::split_amount(amount);
-
             let active_keyset_info = self
-                .mint_keysets(true)?
+                .mint_keysets(true)
+                .await?
                 .for_unit(&unit)
                 .ok_or_else(|| anyhow!("No active keyset for '{}'", unit))?;
             let keyset_id = active_keyset_info.id;
 
             let active_keyset = self
-                .mint_keys()?
+                .mint_keys()
+                .await?
                 .by_id(&keyset_id)
                 .ok_or_else(|| anyhow!("Mint did not provided active keys"))?;
 
             let active_keys = active_keyset.keys;
+            let amounts = Self::split_amount(amount.into(), &Self::denominations(&active_keys))?;
 
             let mut outputs = vec![];
-            let mut minting_secrets: BTreeMap<u64, MintSecret> = BTreeMap::new();
+            let mut minting_secrets = VecDeque::new();
 
             for amount in amounts {
                 let secret = Secret::generate();
 
-                let (b_, r) = BlindedSecret::from_bytes(secret.as_bytes())?;
+                let (b_, r) =
+                    BlindedSecret::from_bytes(secret.as_bytes()).map_err(WalletError::Crypto)?;
 
-                let blinded_message = BlindedMessage::new(amount, &keyset_id, b_.clone());
+                let blinded_message = BlindedMessage::new(amount.into(), &keyset_id, b_.clone());
                 outputs.push(blinded_message);
 
-                minting_secrets.insert(amount, MintSecret { secret, r });
+                minting_secrets.push_back(MintSecret { secret, r });
             }
 
             let signature = quote.sign(&outputs, secret_key); // NUT-20: Signature on Mint Quote
 
-            let blind_signatures = self.mint.do_minting(&quote_id, &outputs, &signature)?;
-            blind_signatures
-                .validate_dleq(&outputs, &active_keys)
-                .context("validate DLEQ proofs on blind signatures returned by mint")?;
-
-            self.save()?; // save balance
+            let blind_signatures = self
+                .mint
+                .do_minting(&quote_id, &outputs, &signature)
+                .await?;
 
-            let promises = blind_signatures.signatures;
-
-            let mut minted_amounts = vec![];
-            for promise in promises {
-                let amount = &promise.amount;
-                let amount_key = active_keys
-                    .get(amount)
-                    .ok_or_else(|| anyhow!("Mint error: key for amount does not exist"))?
-                    .clone();
+            let new_proofs =
+                Self::construct_proofs(blind_signatures, &outputs, minting_secrets, &active_keys)?;
+            let minted_amounts = new_proofs.iter().map(|p| p.amount).collect();
 
-                let minting_secret = minting_secrets
-                    .get(amount)
-                    .ok_or_else(|| anyhow!("Missing secret for amount: {}", amount))?;
+            self.add_proofs(&new_proofs)?;
 
-                let r = &minting_secret.r;
-                let amount_pubkey = &PublicKey::from_hex(amount_key)?;
-                let secret = &minting_secret.secret;
-                let proof = promise
-                    .construct_proof(r, amount_pubkey, secret)
-                    .context("construct proof")?;
-                self.proofs.push(proof);
-
-                minted_amounts.push(*amount);
+            if let Err(e) = self.store.remove_mint_quote(&quote_id) {
+                eprintln!("WARN: failed to remove issued quote {}: {:#}", quote_id, e);
             }
-
-            self.save()?;
+            self.record(
+                HistoryEntry::new(TransactionKind::Mint, amount, Amount::ZERO).with_unit(&unit),
+            );
 
             Ok(minted_amounts)
         } else {
-            bail!("Invoice not paid");
+            Err(WalletError::QuoteNotPaid(quote_id))
         }
     }
 
-    pub fn melt_tokens(&mut self, invoice: &str) -> Result<MeltQuote> {
-        let available_amounts = self.proofs().map(|p| p.amount).collect::<Vec<_>>();
-        let have_total = available_amounts.iter().sum::<u64>();
+    /// Pays LN invoice from proofs in `unit`, `amount` in sats must be specified only for invoices without amount
+    pub async fn melt_tokens(
+        &mut self,
+        invoice: &str,
+        amount: Option<Amount>,
+        unit: &CurrencyUnit,
+    ) -> Result<MeltQuote> {
+        let result = self.melt(invoice, amount, unit).await;
+        self.release_reserved_proofs();
+        result
+    }
 
-        let quote = self.create_melt_quote(invoice)?;
+    async fn melt(
+        &mut self,
+        invoice: &str,
+        amount: Option<Amount>,
+        unit: &CurrencyUnit,
+    ) -> Result<MeltQuote> {
+        let quote = self.create_melt_quote(invoice, amount, unit).await?;
+        self.pay_melt_quote(quote).await
+    }
+
+    async fn pay_melt_quote(&mut self, quote: MeltQuote) -> Result<MeltQuote> {
+        let unit = quote.unit.clone();
+        let have_total = self.balance(&unit)?;
 
         let quote_id = quote.quote.clone();
-        let fee_reserve = quote.fee_reserve;
 
-        let total_amount = quote.amount + fee_reserve;
+        if quote.is_expired() {
+            return Err(WalletError::QuoteExpired(quote_id));
+        }
+
+        let total_amount = quote.amount.try_add(quote.fee_reserve)?;
         if have_total < total_amount {
-            bail!(
-                "Insufficient funds, requested: {} including fee reserve; available: {}",
-                total_amount,
-                have_total
-            );
+            return Err(WalletError::InsufficientFunds {
+                requested: total_amount,
+                available: have_total,
+            });
         }
 
-        let unit = "sat";
         let active_keyset_info = self
-            .mint_keysets(true)?
-            .for_unit(unit)
+            .mint_keysets(true)
+            .await?
+            .for_unit(&unit)
             .ok_or_else(|| anyhow!("No active keyset for '{}'", unit))?;
         let keyset_id = active_keyset_info.id;
 
-        let active_keyset = self
-            .mint_keys()?
-            .by_id(&keyset_id)
-            .ok_or_else(|| anyhow!("Mint did not provided active keys"))?;
-        let active_keys = active_keyset.keys;
-
-        let mut proofs = self.extract_proofs_for_melting(total_amount)?;
+        let proofs = self.reserve_proofs_for_melting(total_amount, &unit).await?;
 
         // prepare_blank_outputs to receive LN fee return after melting
-        let (blank_outputs, melting_secrets) = self
-            .prepare_blank_outputs(fee_reserve, &keyset_id)
-            .inspect_err(|_| self.proofs.append(&mut proofs))?;
+        let (blank_outputs, melting_secrets) =
+            self.prepare_blank_outputs(quote.fee_reserve, &keyset_id)?;
+
+        // persist everything needed to settle the melt later, in case the payment does not finish now
+        let pending_melt = PendingMelt {
+            quote_id: quote_id.clone(),
+            request: quote.request.clone(),
+            amount: quote.amount,
+            fee_reserve: quote.fee_reserve,
+            unit,
+            expiry: quote.expiry,
+            keyset_id,
+            inputs: proofs.clone(),
+            blank_outputs: melting_secrets
+                .iter()
+                .map(MintSecret::to_blank_output)
+                .collect(),
+        };
+        self.store
+            .add_pending_melt(&pending_melt)
+            .context("save pending melt")
+            .map_err(WalletError::Storage)?;
+        self.set_proofs_state(&proofs, ProofState::PendingSpend)?;
 
         let melt_result = self
             .mint
             .do_melting(&quote_id, &proofs, &blank_outputs)
-            .with_context(|| format!("do melting with quote: {}", quote_id));
+            .await
+            .map_err(WalletError::from);
 
         let melt_quote = match melt_result {
-            Ok(v) => Ok(v),
+            Ok(v) => v,
             Err(e) => {
-                self.proofs.append(&mut proofs);
-                self.save()?;
-                Err(e)
+                match &e {
+                    // the payment may be in flight, its outcome is resolved by `melt_status`
+                    WalletError::Mint(
+                        MintError::Http(_) | MintError::Response { .. } | MintError::QuotePending,
+                    ) => eprintln!(
+                        "WARN: melting did not finish, inputs stay pending until quote {} is checked",
+                        quote_id
+                    ),
+                    _ => {
+                        self.handle_failed_inputs(&e, &proofs).await;
+                        self.remove_pending_melt(&quote_id);
+                    }
+                }
+                return Err(e);
             }
-        }?;
+        };
 
-        println!("--> Saving wallet balance after successful melting...");
-        self.save()?; // save after successful payment
+        self.settle_melt(&pending_melt, &melt_quote).await?;
 
-        if melt_quote.state != QuoteState::Paid {
-            eprintln!("WARN: LN payment failed: {:?}", melt_quote.state)
-        }
+        Ok(melt_quote)
+    }
 
-        // deal with returned change from overpaid LN fee reserve
-        self.process_returned_change(&melt_quote, &melting_secrets, &active_keys)
-            .context("process_returned_change")?;
+    /// Checks state of pending melt with `quote_id`.
+    /// Recovers returned change if its payment succeeded, returns inputs to the wallet if it failed.
+    pub async fn melt_status(&mut self, quote_id: &str) -> Result<MeltQuote> {
+        let pending_melt = self
+            .pending_melts()?
+            .into_iter()
+            .find(|m| m.quote_id == quote_id)
+            .ok_or_else(|| anyhow!("No pending melt with quote {}", quote_id))?;
+
+        let melt_quote = self.mint.get_melt_quote(quote_id).await?;
+
+        self.settle_melt(&pending_melt, &melt_quote).await?;
 
         Ok(melt_quote)
     }
 
+    /// Updates wallet according to state of melt quote returned by mint
+    async fn settle_melt(
+        &mut self,
+        pending_melt: &PendingMelt,
+        melt_quote: &MeltQuote,
+    ) -> Result<()> {
+        let inputs = &pending_melt.inputs;
+
+        match melt_quote.state {
+            QuoteState::Paid => {
+                self.set_proofs_state(inputs, ProofState::Spent)?;
+
+                // deal with returned change from overpaid LN fee reserve
+                let keys = self
+                    .mint_keys()
+                    .await?
+                    .by_id(&pending_melt.keyset_id)
+                    .ok_or_else(|| anyhow!("Missing keyset {}", pending_melt.keyset_id))?
+                    .keys;
+                let melting_secrets = pending_melt
+                    .blank_outputs
+                    .iter()
+                    .map(MintSecret::from_blank_output)
+                    .collect::<Vec<_>>();
+                let change = self
+                    .process_returned_change(melt_quote, &melting_secrets, &keys)
+                    .context("process_returned_change")?;
+
+                self.remove_pending_melt(&pending_melt.quote_id);
+
+                let preimage = Self::verified_preimage(pending_melt, melt_quote);
+
+                // state is updated already, so history is recorded even for inconsistent amounts
+                let fee = pending_melt
+                    .amount
+                    .checked_add(change)
+                    .and_then(|paid| inputs.total_amount().ok()?.checked_sub(paid))
+                    .unwrap_or_else(|| {
+                        eprintln!(
+                            "WARN: change returned for quote {} exceeds fee reserve",
+                            pending_melt.quote_id
+                        );
+                        Amount::ZERO
+                    });
+                self.record(
+                    HistoryEntry::new(TransactionKind::Melt, pending_melt.amount, fee)
+                        .with_unit(&pending_melt.unit)
+                        .with_preimage(preimage),
+                );
+            }
+            QuoteState::Unpaid => {
+                eprintln!("WARN: LN payment failed, returning inputs to the wallet");
+                self.set_proofs_state(inputs, ProofState::Unspent)?;
+                self.remove_pending_melt(&pending_melt.quote_id);
+            }
+            state => eprintln!(
+                "WARN: LN payment is {:?}, inputs stay pending until quote {} is checked",
+                state, pending_melt.quote_id
+            ),
+        }
+
+        Ok(())
+    }
+
+    /// Returns payment preimage if it proves that the invoice was paid, warns otherwise
+    fn verified_preimage(pending_melt: &PendingMelt, melt_quote: &MeltQuote) -> Option<String> {
+        let Some(preimage) = &melt_quote.payment_preimage else {
+            eprintln!(
+                "WARN: mint claims quote {} is paid but returned no payment preimage",
+                pending_melt.quote_id
+            );
+            return None;
+        };
+
+        match Invoice::from_str(&pending_melt.request) {
+            Ok(invoice) if invoice.verify_preimage(preimage) => Some(preimage.clone()),
+            Ok(_) => {
+                eprintln!(
+                    "WARN: mint claims quote {} is paid but preimage {} does not match payment hash",
+                    pending_melt.quote_id, preimage
+                );
+                None
+            }
+            Err(e) => {
+                eprintln!("WARN: failed to decode invoice to verify preimage: {:#}", e);
+                None
+            }
+        }
+    }
+
+    fn remove_pending_melt(&mut self, quote_id: &str) {
+        if let Err(e) = self.store.remove_pending_melt(quote_id) {
+            eprintln!("WARN: failed to remove pending melt {}: {:#}", quote_id, e);
+        }
+    }
+
     /// Returns blank outputs for receiving LN fee return after melting
     fn prepare_blank_outputs(
         &self,
-        fee_reserve: u64,
-        keyset_id: &str,
+        fee_reserve: Amount,
+        keyset_id: &KeysetId,
     ) -> Result<(Vec<BlindedMessage>, Vec<MintSecret>)> {
         let mut blank_outputs = vec![];
         let mut melting_secrets = vec![];
 
-        let blank_outputs_num = Self::calculate_number_of_blank_outputs(fee_reserve);
+        let blank_outputs_num = Self::calculate_number_of_blank_outputs(fee_reserve.into());
 
         for _ in 0..blank_outputs_num {
             let secret = Secret::generate();
-            let (b_, r) = BlindedSecret::from_bytes(secret.as_bytes())?;
-            let blinded_message = BlindedMessage::new(1, keyset_id, b_.clone());
+            let (b_, r) =
+                BlindedSecret::from_bytes(secret.as_bytes()).map_err(WalletError::Crypto)?;
+            let blinded_message = BlindedMessage::new(Amount::new(1), keyset_id, b_.clone());
 
             blank_outputs.push(blinded_message);
 
@@ -319,284 +646,470 @@ impl Wallet {
         Ok((blank_outputs, melting_secrets))
     }
 
-    /// Processes quote response to receive LN fee return after melting
+    /// Processes quote response to receive LN fee return after melting. Returns the returned amount.
     fn process_returned_change(
         &mut self,
         melt_quote: &MeltQuote,
         melting_secrets: &[MintSecret],
         active_keys: &AmountKeys,
-    ) -> Result<()> {
+    ) -> Result<Amount> {
+        let mut change_proofs = vec![];
         if let Some(promises) = &melt_quote.change {
             for (i, promise) in promises.iter().enumerate() {
                 let amount = &promise.amount;
-                let amount_key = active_keys
+                let amount_pubkey = active_keys
                     .get(amount)
-                    .ok_or_else(|| anyhow!("Mint error: key for amount does not exist"))?
-                    .clone();
+                    .ok_or_else(|| anyhow!("Mint error: key for amount does not exist"))?;
 
                 let minting_secret = melting_secrets.get(i).ok_or_else(|| {
                     anyhow!("Missing secret for change amount: {} (index {})", amount, i)
                 })?;
 
                 let r = &minting_secret.r;
-                let amount_pubkey = &PublicKey::from_hex(amount_key)?;
                 let secret = &minting_secret.secret;
                 let proof = promise
                     .construct_proof(r, amount_pubkey, secret)
-                    .context("construct proof")?;
-                self.proofs.push(proof);
+                    .context("construct proof")
+                    .map_err(WalletError::Crypto)?;
+                change_proofs.push(proof);
             }
         }
 
-        Ok(())
+        self.add_proofs(&change_proofs)?;
+
+        Ok(change_proofs.total_amount()?)
     }
 
-    /// Used for melting.
-    fn extract_proofs_for_melting(&mut self, amount_to_melt: u64) -> Result<Proofs> {
-        let mut available_amounts = self.proofs.iter().map(|p| p.amount).collect::<Vec<_>>();
-        available_amounts.sort();
+    /// Reserves proofs in `unit` covering `amount_to_melt` and their input fee. Used for melting.
+    async fn reserve_proofs_for_melting(
+        &mut self,
+        amount_to_melt: Amount,
+        unit: &CurrencyUnit,
+    ) -> Result<Proofs> {
+        let (proofs, input_fee, _) = self
+            .reserve_proofs_for_spending(amount_to_melt, unit, true)
+            .await?;
 
-        let have_total = available_amounts.iter().sum::<u64>();
-        assert!(have_total >= amount_to_melt);
+        println!("--> Melting fee: {}", input_fee);
 
-        let unit = "sat";
+        Ok(proofs)
+    }
 
-        // first try to find combinations in 'have_amounts', so we would not need to do swap for a change
-        let (amounts_to_melt, missing_amount) =
-            match helpers::find_subset_sum(&available_amounts, amount_to_melt) {
-                Some(amounts) => (amounts, 0),
-                None => {
-                    let mut to_spend_acc = 0;
-                    let mut amounts_to_melt = vec![];
+    /// Reserves proofs in `unit` with sum of exactly `amount`, plus their input fee if `pay_input_fee` is set.
+    /// If no such combination exists, one proof is swapped for the exact amount and change first.
+    /// Returns (proofs, input_fee, swap_fee).
+    async fn reserve_proofs_for_spending(
+        &mut self,
+        amount: Amount,
+        unit: &CurrencyUnit,
+        pay_input_fee: bool,
+    ) -> Result<(Proofs, Amount, Amount)> {
+        let active_fee_ppk = self
+            .mint_keysets(true)
+            .await?
+            .for_unit(unit)
+            .ok_or_else(|| anyhow!("No active keyset for '{}'", unit))?
+            .input_fee_ppk;
+        let denominations = self.active_denominations(unit).await?;
+
+        let proofs = self.unit_proofs(unit)?;
+        let candidates = self.candidates(&proofs).await?;
+        let plan = coin_selection::plan_spend(
+            self.coin_selector.as_ref(),
+            &candidates,
+            amount.into(),
+            active_fee_ppk,
+            &denominations,
+            pay_input_fee,
+        )?;
 
-                    for amount in available_amounts.iter() {
-                        to_spend_acc += *amount;
-                        if to_spend_acc < amount_to_melt {
-                            amounts_to_melt.push(*amount);
-                        } else {
-                            to_spend_acc -= *amount;
-                            break;
-                        }
-                    }
+        let mut inputs = plan
+            .inputs
+            .iter()
+            .map(|i| proofs[*i].clone())
+            .collect::<Vec<_>>();
+        self.reserve_proofs(&inputs)?;
 
-                    let missing_amount = amount_to_melt - to_spend_acc;
+        let mut swap_fee = Amount::ZERO;
+        if let Some(swap) = plan.swap {
+            println!("--> Doing swap to get some change");
+            let swapped = [proofs[swap.input].clone()];
+            self.reserve_proofs(&swapped)?;
 
-                    (amounts_to_melt, missing_amount)
-                }
-            };
+            let spend_amounts = Self::split_amount(swap.spend, &denominations)?;
+            let mut output_amounts = spend_amounts.clone();
+            output_amounts.extend(self.split_toward_target(swap.change, unit, &denominations)?);
+            output_amounts.sort();
 
-        let mut proofs_to_melt = self.extract_proofs_with_amounts(&amounts_to_melt)?;
+            let (new_proofs, fee) = self.swap_proofs(&swapped, Some(&output_amounts)).await?;
+            println!("--> Swap done (fee: {})", fee);
+            swap_fee = fee;
 
-        // calculate fee and add additional proofs
-        let mut sum_fee_ppk = 0;
-        for proof in proofs_to_melt.iter() {
-            let proof_keyset_id = &proof.keyset_id;
+            let mut spent = Self::take_proofs_with_amounts(new_proofs, &spend_amounts)?;
+            self.reserve_proofs(&spent)?;
+            inputs.append(&mut spent);
+        }
 
-            let proof_keyset_info = self
-                .mint_keysets(false)?
-                .by_id(proof_keyset_id)
-                .ok_or(anyhow!("Missing keyset {}", proof_keyset_id))?;
+        Ok((inputs, plan.input_fee.into(), swap_fee))
+    }
 
-            sum_fee_ppk += proof_keyset_info.input_fee_ppk;
+    /// Returns proofs as candidates for coin selection
+    async fn candidates(&mut self, proofs: &[Proof]) -> Result<Vec<Candidate>> {
+        let keyset_infos = self.mint_keysets(false).await?;
 
-            let index = available_amounts
-                .iter()
-                .position(|a| a == &proof.amount)
-                .expect("amount is present");
-            available_amounts.remove(index);
-        }
+        proofs
+            .iter()
+            .map(|proof| {
+                let keyset_info = keyset_infos
+                    .keysets
+                    .iter()
+                    .find(|ks| ks.id == proof.keyset_id)
+                    .ok_or_else(|| anyhow!("Missing keyset {}", proof.keyset_id))?;
+                Ok(Candidate {
+                    amount: proof.amount.into(),
+                    fee_ppk: keyset_info.input_fee_ppk,
+                    active: keyset_info.active,
+                })
+            })
+            .collect()
+    }
 
-        let inputs_fee = sum_fee_ppk.div_ceil(1000);
+    /// Estimates fees of melting without touching any proofs. Only a melt quote is requested from mint.
+    pub async fn preview_melt(
+        &mut self,
+        invoice: &str,
+        amount: Option<Amount>,
+        unit: &CurrencyUnit,
+    ) -> Result<SpendPreview> {
+        let quote = self.create_melt_quote(invoice, amount, unit).await?;
+        self.preview_spend(quote.amount, quote.fee_reserve, unit, true)
+            .await
+    }
 
-        let additional_amount_to_spend = inputs_fee + missing_amount;
+    /// Estimates fees of creating Cashu token in `unit` without touching any proofs
+    pub async fn preview_send(
+        &mut self,
+        amount: Amount,
+        unit: &CurrencyUnit,
+    ) -> Result<SpendPreview> {
+        self.preview_spend(amount, Amount::ZERO, unit, false).await
+    }
 
-        if have_total < amount_to_melt + inputs_fee {
-            self.proofs.append(&mut proofs_to_melt);
-            self.save()?;
-            bail!(
-                "Insufficient funds, requested: {} including fees; available: {}",
-                amount_to_melt + inputs_fee,
-                have_total
-            );
-        }
+    async fn preview_spend(
+        &mut self,
+        amount: Amount,
+        fee_reserve: Amount,
+        unit: &CurrencyUnit,
+        pay_input_fee: bool,
+    ) -> Result<SpendPreview> {
+        let active_fee_ppk = self
+            .mint_keysets(true)
+            .await?
+            .for_unit(unit)
+            .ok_or_else(|| anyhow!("No active keyset for '{}'", unit))?
+            .input_fee_ppk;
+        let denominations = self.active_denominations(unit).await?;
+
+        let proofs = self.unit_proofs(unit)?;
+        let candidates = self.candidates(&proofs).await?;
+
+        let plan = coin_selection::plan_spend(
+            self.coin_selector.as_ref(),
+            &candidates,
+            amount.try_add(fee_reserve)?.into(),
+            active_fee_ppk,
+            &denominations,
+            pay_input_fee,
+        )?;
 
-        let (mut proofs_to_swap, mut output_amounts, mut additional_amounts_to_melt) = self
-            .prepare_amounts_for_swap_before_spend(additional_amount_to_spend)
-            .inspect_err(|_| self.proofs.append(&mut proofs_to_melt))?;
+        Ok(SpendPreview {
+            amount,
+            fee_reserve,
+            input_fee: plan.input_fee.into(),
+            swap_needed: plan.swap.is_some(),
+            swap_fee: plan.swap.map(|swap| swap.fee.into()).unwrap_or_default(),
+        })
+    }
 
-        if !proofs_to_swap.is_empty() && !output_amounts.is_empty() {
-            println!("--> Need change");
-            let amounts_count = additional_amounts_to_melt.len() as u64;
+    /// Creates Cashu token of `amount` in `unit`. Returns the token and swap fee.
+    pub async fn prepare_cashu_token(
+        &mut self,
+        amount: Amount,
+        unit: &CurrencyUnit,
+    ) -> Result<(TokenV4, Amount)> {
+        let result = self.prepare_token(amount, unit).await;
+        self.release_reserved_proofs();
+        result
+    }
 
-            let active_keyset_info = self
-                .mint_keysets(true)?
-                .for_unit(unit)
-                .ok_or_else(|| anyhow!("No active keyset for '{}'", unit))
-                .inspect_err(|_| self.proofs.append(&mut proofs_to_swap))?;
-            let active_fee_ppk = active_keyset_info.input_fee_ppk;
-
-            // estimate total fee including additional_amounts_to_melt inputs
-            let fee_estimate = (sum_fee_ppk + amounts_count * active_fee_ppk).div_ceil(1000);
-
-            if inputs_fee < fee_estimate {
-                // not enough to cover input fee, try it again with amount adjusted
-                let new_additional_amount_to_spend =
-                    additional_amount_to_spend + (fee_estimate - inputs_fee);
-
-                println!(
-                    "--> Need to add more input fee: {} => {} sats",
-                    inputs_fee, fee_estimate,
-                );
-                self.proofs.append(&mut proofs_to_swap);
-
-                if have_total < amount_to_melt + fee_estimate {
-                    self.save()?;
-                    bail!(
-                        "Insufficient funds, requested: {} including fees; available: {}",
-                        amount_to_melt + fee_estimate,
-                        have_total
-                    );
-                }
+    async fn prepare_token(
+        &mut self,
+        amount: Amount,
+        unit: &CurrencyUnit,
+    ) -> Result<(TokenV4, Amount)> {
+        let have_total = self.balance(unit)?;
+        if have_total < amount {
+            return Err(WalletError::InsufficientFunds {
+                requested: amount,
+                available: have_total,
+            });
+        }
 
-                let (new_proofs_to_swap, new_output_amounts, new_additional_amounts_to_melt) = self
-                    .prepare_amounts_for_swap_before_spend(new_additional_amount_to_spend)
-                    .inspect_err(|_| self.proofs.append(&mut proofs_to_melt))?;
+        let (proofs_to_spend, fee) = self.prepare_inputs_for_spend(amount, unit).await?;
 
-                proofs_to_swap = new_proofs_to_swap;
-                output_amounts = new_output_amounts;
-                additional_amounts_to_melt = new_additional_amounts_to_melt;
-            }
+        let token =
+            TokenV4::new(&self.mint.url(), unit, &proofs_to_spend).context("create V4 token")?;
 
-            if !proofs_to_swap.is_empty() && !output_amounts.is_empty() {
-                // we need to do a swap to get a change
-                println!("--> Doing swap to get some change");
-                let (mut new_proofs, swap_fee) = self
-                    .swap_proofs(&proofs_to_swap, Some(&output_amounts))
-                    .context("swap proofs")
-                    .inspect_err(|_| self.proofs.append(&mut proofs_to_melt))?;
-                println!("--> Swap done (fee: {}), saving proofs...", swap_fee);
+        self.set_proofs_state(&proofs_to_spend, ProofState::Spent)?;
+        self.record(HistoryEntry::new(TransactionKind::Send, amount, fee).with_unit(unit));
 
-                self.proofs.append(&mut new_proofs);
+        Ok((token, fee))
+    }
 
-                self.save()?; // save newly received proofs
+    /// Returns amount in `unit` held in proofs from inactive keysets and fee for swapping them into the active keyset
+    pub async fn estimate_rotation(&mut self, unit: &CurrencyUnit) -> Result<(Amount, Amount)> {
+        let inactive_ids = self.inactive_keyset_ids().await?;
+
+        let mut amount = Amount::ZERO;
+        let mut fees_ppk = vec![];
+        for proof in self.unit_proofs(unit)?.iter() {
+            if let Some((_, input_fee_ppk)) =
+                inactive_ids.iter().find(|(id, _)| id == &proof.keyset_id)
+            {
+                amount = amount.try_add(proof.amount)?;
+                fees_ppk.push(*input_fee_ppk);
             }
         }
 
-        // potential missing proofs and proofs paying fee
-        let mut additional_proofs_to_melt = self
-            .extract_proofs_with_amounts(&additional_amounts_to_melt)
-            .inspect_err(|_| self.proofs.append(&mut proofs_to_melt))?;
+        Ok((amount, input_fee(fees_ppk)?))
+    }
 
-        for proof in additional_proofs_to_melt.iter() {
-            let proof_keyset_id = &proof.keyset_id;
+    /// Swaps proofs in `unit` from inactive keysets into the active keyset. Returns swapped amount and fee.
+    pub async fn rotate_proofs(&mut self, unit: &CurrencyUnit) -> Result<(Amount, Amount)> {
+        let (amount, fee) = self.estimate_rotation(unit).await?;
+        if amount == Amount::ZERO {
+            return Ok((Amount::ZERO, Amount::ZERO));
+        }
+        if amount <= fee {
+            return Err(WalletError::InsufficientFunds {
+                requested: fee,
+                available: amount,
+            });
+        }
 
-            let proof_keyset_info = self
-                .mint_keysets(false)?
-                .by_id(proof_keyset_id)
-                .ok_or(anyhow!("Missing keyset {}", proof_keyset_id))?;
+        let inactive_ids = self.inactive_keyset_ids().await?;
 
-            sum_fee_ppk += proof_keyset_info.input_fee_ppk;
-        }
+        let proofs = self
+            .unit_proofs(unit)?
+            .into_iter()
+            .filter(|p| inactive_ids.iter().any(|(id, _)| id == &p.keyset_id))
+            .collect::<Vec<_>>();
+        self.set_proofs_state(&proofs, ProofState::Reserved)?;
 
-        proofs_to_melt.append(&mut additional_proofs_to_melt);
+        let result = self.swap_proofs(&proofs, None).await;
+        self.release_reserved_proofs();
+        let (_, fee) = result?;
 
-        let total_inputs_fee = sum_fee_ppk.div_ceil(1000);
+        Ok((amount, fee))
+    }
 
-        // total fee is higher than already included fee (inputs_fee)
-        if total_inputs_fee < sum_fee_ppk.div_ceil(1000) {
-            // rollback
-            self.proofs.append(&mut proofs_to_melt);
-            self.save()?;
-            bail!(
-                "Total fee {} is higher than the fee included in proofs {}",
-                sum_fee_ppk.div_ceil(1000),
-                total_inputs_fee
-            );
+    /// Returns amount in `unit` held in proofs that have to be swapped to reach the target distribution
+    /// of denominations (see [`Wallet::set_proofs_per_denomination`]) and fee for swapping them.
+    /// Fails like [`Wallet::rebalance_proofs`] if the fee takes the whole amount.
+    pub async fn estimate_rebalance(&mut self, unit: &CurrencyUnit) -> Result<(Amount, Amount)> {
+        let (inputs, fee) = self.rebalance_inputs(unit).await?;
+        Ok((inputs.total_amount()?, fee))
+    }
+
+    /// Swaps proofs in `unit` into the target distribution of denominations in one swap.
+    /// With 0 proofs per denomination the proofs are consolidated into as few as possible.
+    /// Returns swapped amount and fee.
+    pub async fn rebalance_proofs(&mut self, unit: &CurrencyUnit) -> Result<(Amount, Amount)> {
+        let (proofs, _) = self.rebalance_inputs(unit).await?;
+        let amount = proofs.total_amount()?;
+        if amount == Amount::ZERO {
+            return Ok((Amount::ZERO, Amount::ZERO));
         }
 
-        println!("--> Melting fee: {}", total_inputs_fee);
+        self.reserve_proofs(&proofs)?;
 
-        Ok(proofs_to_melt)
+        // kept proofs stay unspent, so outputs fill in only what is missing
+        let result = self.swap_proofs(&proofs, None).await;
+        self.release_reserved_proofs();
+        let (_, fee) = result?;
+
+        Ok((amount, fee))
     }
 
-    pub fn prepare_cashu_token(&mut self, amount: u64) -> Result<(TokenV4, u64)> {
-        let available_amounts = self.proofs().map(|p| p.amount).collect::<Vec<_>>();
+    /// Returns proofs not matching the target distribution and fee for swapping them,
+    /// fails if the fee is not less than their amount
+    async fn rebalance_inputs(&mut self, unit: &CurrencyUnit) -> Result<(Proofs, Amount)> {
+        let denominations = self.active_denominations(unit).await?;
+        let proofs = self.unit_proofs(unit)?;
+        let candidates = self.candidates(&proofs).await?;
+        let inputs = distribution::rebalance_inputs(
+            &candidates,
+            self.proofs_per_denomination()?,
+            &denominations,
+        )?;
 
-        let have_total = available_amounts.iter().sum::<u64>();
-        if have_total < amount {
-            bail!("Insufficient funds");
-        }
+        let fee = input_fee(inputs.iter().map(|i| candidates[*i].fee_ppk))?;
+        let proofs = inputs
+            .iter()
+            .map(|i| proofs[*i].clone())
+            .collect::<Vec<_>>();
 
-        let (proofs_to_spend, fee) = self.prepare_inputs_for_spend(amount)?;
+        let amount = proofs.total_amount()?;
+        if amount > Amount::ZERO && amount <= fee {
+            return Err(WalletError::InsufficientFunds {
+                requested: fee,
+                available: amount,
+            });
+        }
 
-        let token =
-            TokenV4::new(&self.mint.url(), "sat", &proofs_to_spend).context("create V4 token")?;
+        Ok((proofs, fee))
+    }
 
-        self.save()?;
+    /// Splits `amount` into output amounts moving unspent proofs in `unit` toward the target distribution
+    fn split_toward_target(
+        &self,
+        amount: u64,
+        unit: &CurrencyUnit,
+        denominations: &[u64],
+    ) -> Result<Vec<u64>> {
+        let held = self
+            .unit_proofs(unit)?
+            .iter()
+            .map(|p| p.amount.into())
+            .collect::<Vec<_>>();
+        Ok(distribution::split_toward_target(
+            amount,
+            &held,
+            self.proofs_per_denomination()?,
+            denominations,
+        )?)
+    }
 
-        Ok((token, fee))
+    /// Returns IDs and input fees of inactive keysets
+    async fn inactive_keyset_ids(&mut self) -> Result<Vec<(KeysetId, u64)>> {
+        Ok(self
+            .mint_keysets(false)
+            .await?
+            .keysets
+            .into_iter()
+            .filter(|ks| !ks.active)
+            .map(|ks| (ks.id, ks.input_fee_ppk))
+            .collect())
     }
 
-    pub fn receive_via_cashu_token(&mut self, token: TokenV4) -> Result<(u64, u64)> {
-        let amount = token.amount();
+    pub async fn receive_via_cashu_token(&mut self, token: TokenV4) -> Result<(Amount, Amount)> {
+        let amount = token.amount()?;
 
         if token.mint_url() != self.mint_url() {
-            bail!(
+            return Err(anyhow!(
                 "Receiving from different mint is not supported. Token is from {}",
                 token.mint_url()
-            );
+            )
+            .into());
         }
 
-        // validate DLEQ in proofs
-        let all_keysets = &self.mint_keys()?;
-        token.validate_dleq_proofs(all_keysets)?;
-
-        // get proofs from token and swap them for new
-        let proofs = token.proofs();
+        // get proofs from token, expanding short keyset IDs against keysets known by mint
+        let keyset_infos = self.mint_keysets(false).await?;
+        let proofs = token.proofs(keyset_infos.keysets.iter().map(|ks| &ks.id))?;
 
-        let (mut new_proofs, fee) = self.swap_proofs(&proofs, None).context("swap proofs")?;
+        // validate DLEQ in proofs
+        let all_keysets = &self.mint_keys().await?;
+        proofs
+            .validate_dleq(all_keysets)
+            .map_err(WalletError::Dleq)?;
+
+        let unit = token.unit().clone();
+        for proof in proofs.iter() {
+            let keyset_info = keyset_infos
+                .keysets
+                .iter()
+                .find(|ks| ks.id == proof.keyset_id)
+                .ok_or_else(|| anyhow!("Missing keyset {}", proof.keyset_id))?;
+            if keyset_info.unit != unit {
+                return Err(anyhow!(
+                    "Token unit '{}' does not match unit '{}' of keyset {}",
+                    unit,
+                    keyset_info.unit,
+                    keyset_info.id
+                )
+                .into());
+            }
+        }
 
-        self.proofs.append(&mut new_proofs);
+        // token proofs are not in the store, only the new ones get saved
+        let (_, fee) = self.swap(&proofs, None, false).await?;
 
-        self.save()?;
+        self.record(HistoryEntry::new(TransactionKind::Receive, amount, fee).with_unit(&unit));
 
         Ok((amount, fee))
     }
 
-    fn swap_proofs(
+    async fn swap_proofs(
         &mut self,
         old_proofs: &[Proof],
         output_amounts: Option<&[u64]>,
-    ) -> Result<(Proofs, u64)> {
-        let mut proof_unit = String::new();
+    ) -> Result<(Proofs, Amount)> {
+        self.swap(old_proofs, output_amounts, true).await
+    }
+
+    /// Swaps `old_proofs` for new ones. Inputs that are not `stored` in the wallet,
+    /// e.g. proofs of received token, are left out of the proof state updates.
+    async fn swap(
+        &mut self,
+        old_proofs: &[Proof],
+        output_amounts: Option<&[u64]>,
+        stored: bool,
+    ) -> Result<(Proofs, Amount)> {
+        let mut proof_unit = None;
 
         // calculate fee
-        let mut sum_fee_ppk = 0;
+        let mut fees_ppk = vec![];
         for proof in old_proofs.iter() {
             let proof_keyset_id = &proof.keyset_id;
 
             let proof_keyset_info = self
-                .mint_keysets(false)?
+                .mint_keysets(false)
+                .await?
                 .by_id(proof_keyset_id)
                 .ok_or_else(|| anyhow!("Missing keyset {}", proof_keyset_id))?;
 
-            if proof_unit.is_empty() {
-                proof_unit = proof_keyset_info.unit.clone();
+            match &proof_unit {
+                None => proof_unit = Some(proof_keyset_info.unit),
+                Some(unit) if unit != &proof_keyset_info.unit => {
+                    return Err(anyhow!(
+                        "Can not swap proofs of different units: '{}' and '{}'",
+                        unit,
+                        proof_keyset_info.unit
+                    )
+                    .into());
+                }
+                Some(_) => {}
             }
 
-            sum_fee_ppk += proof_keyset_info.input_fee_ppk;
+            fees_ppk.push(proof_keyset_info.input_fee_ppk);
         }
 
-        let fee = sum_fee_ppk.div_ceil(1000);
+        let fee = input_fee(fees_ppk)?;
+        let proof_unit = proof_unit.ok_or_else(|| anyhow!("No proofs to swap"))?;
 
-        let amount_minus_fee = old_proofs.iter().map(|p| p.amount).sum::<u64>() - fee;
+        let amount_minus_fee = old_proofs
+            .total_amount()?
+            .try_sub(fee)
+            .context("swap fee exceeds amount of proofs")?;
 
         let output_amounts = if let Some(output_amounts) = output_amounts {
             // swap amounts for change
             output_amounts.to_vec()
         } else {
             // swap proofs when receiving e-cash
-            let mut output_amounts = Self::split_amount(amount_minus_fee);
+            let denominations = self.active_denominations(&proof_unit).await?;
+            let mut output_amounts =
+                self.split_toward_target(amount_minus_fee.into(), &proof_unit, &denominations)?;
 
             // In order to preserve privacy around the amount that a client might want to send to another user and keep the rest as change,
             // the client SHOULD ensure that the list requested outputs is ordered by amount in ascending order.
@@ -607,13 +1120,15 @@ impl Wallet {
         };
 
         let active_keyset_info = self
-            .mint_keysets(true)?
+            .mint_keysets(true)
+            .await?
             .for_unit(&proof_unit)
             .ok_or_else(|| anyhow!("No active keyset for '{}'", proof_unit))?;
         let active_keyset_id = active_keyset_info.id;
 
         let active_keyset = self
-            .mint_keys()?
+            .mint_keys()
+            .await?
             .by_id(&active_keyset_id)
             .ok_or_else(|| anyhow!("Mint did not provided active keys"))?;
 
@@ -625,200 +1140,364 @@ impl Wallet {
         for amount in output_amounts {
             let secret = Secret::generate();
 
-            let (b_, r) = BlindedSecret::from_bytes(secret.as_bytes())?;
+            let (b_, r) =
+                BlindedSecret::from_bytes(secret.as_bytes()).map_err(WalletError::Crypto)?;
 
-            let blinded_message = BlindedMessage::new(amount, &active_keyset_id, b_.clone());
+            let blinded_message = BlindedMessage::new(amount.into(), &active_keyset_id, b_.clone());
             outputs.push(blinded_message);
 
             secrets.push_back(MintSecret { secret, r });
         }
 
-        let blind_signatures = self.mint.do_swap(old_proofs, &outputs)?;
+        let blind_signatures = match self
+            .mint
+            .do_swap(old_proofs, &outputs)
+            .await
+            .map_err(WalletError::from)
+        {
+            Ok(v) => v,
+            Err(e) => {
+                if stored {
+                    self.handle_failed_inputs(&e, old_proofs).await;
+                }
+                return Err(e);
+            }
+        };
+        let stored_inputs = if stored { old_proofs } else { &[] };
+
+        // inputs are spent by the mint now, even if returned signatures turn out to be unusable
+        let new_proofs = Self::construct_proofs(blind_signatures, &outputs, secrets, &active_keys)
+            .inspect_err(|_| {
+                if let Err(e) = self
+                    .store
+                    .set_proofs_state(stored_inputs, ProofState::Spent)
+                {
+                    eprintln!("WARN: failed to mark swapped inputs as spent: {:#}", e);
+                }
+            })?;
+
+        // store new proofs and spend inputs at once
+        self.store
+            .update_proofs(&new_proofs, stored_inputs, ProofState::Spent)
+            .context("save swapped proofs")
+            .map_err(WalletError::Storage)?;
+
+        Ok((new_proofs, fee))
+    }
+
+    /// Validates DLEQ proofs on blind signatures returned by mint and unblinds them into proofs
+    fn construct_proofs(
+        blind_signatures: BlindSignatures,
+        outputs: &[BlindedMessage],
+        mut secrets: VecDeque<MintSecret>,
+        active_keys: &AmountKeys,
+    ) -> Result<Proofs> {
         blind_signatures
-            .validate_dleq(&outputs, &active_keys)
-            .context("validate DLEQ proofs on blind signatures returned by mint")?;
+            .validate_dleq(outputs, active_keys)
+            .context("validate DLEQ proofs on blind signatures returned by mint")
+            .map_err(WalletError::Dleq)?;
 
         let promises = blind_signatures.signatures;
 
         let mut new_proofs = vec![];
         for promise in promises {
             let amount = &promise.amount;
-            let amount_key = active_keys
+            let amount_pubkey = active_keys
                 .get(amount)
-                .ok_or_else(|| anyhow!("Mint error: key for amount does not exist"))?
-                .clone();
+                .ok_or_else(|| anyhow!("Mint error: key for amount does not exist"))?;
 
             let minting_secret = secrets
                 .pop_front()
                 .ok_or_else(|| anyhow!("Missing secret for amount: {}", amount))?;
 
             let r = &minting_secret.r;
-            let amount_pubkey = &PublicKey::from_hex(amount_key)?;
             let secret = &minting_secret.secret;
 
             let proof = promise
                 .construct_proof(r, amount_pubkey, secret)
-                .context("construct proof")?;
+                .context("construct proof")
+                .map_err(WalletError::Crypto)?;
 
             new_proofs.push(proof);
         }
 
-        Ok((new_proofs, fee))
+        Ok(new_proofs)
     }
 
-    /// Get proofs with specified amounts and remove them from the wallet
-    fn extract_proofs_with_amounts(&mut self, amounts: &[u64]) -> Result<Proofs> {
-        let mut extracted_proofs = Vec::new();
-        let mut amounts = amounts.to_vec();
-        let amounts_len = amounts.len();
-
-        self.proofs.retain(|p| {
-            if let Some(index) = amounts.iter().position(|amount| amount == &p.amount) {
-                amounts.swap_remove(index);
-                extracted_proofs.push(p.clone());
-                false
-            } else {
-                true
+    /// Updates state of proofs used as inputs of a failed mint request.
+    /// Mint rejects the whole request if any input is spent, so states of the inputs are checked
+    /// and only those reported as spent or pending are not returned to the wallet.
+    async fn handle_failed_inputs(&mut self, err: &WalletError, proofs: &[Proof]) {
+        match err {
+            WalletError::Mint(MintError::TokenAlreadySpent) => match self.check_spent(proofs).await
+            {
+                Ok((spent, pending, unspent)) => {
+                    eprintln!(
+                        "WARN: mint reports inputs as already spent, discarding {} of {} proofs",
+                        spent.len(),
+                        proofs.len()
+                    );
+                    self.update_failed_inputs(&spent, ProofState::Spent);
+                    self.update_failed_inputs(&pending, ProofState::PendingSpend);
+                    self.update_failed_inputs(&unspent, ProofState::Unspent);
+                }
+                Err(e) => {
+                    eprintln!("WARN: failed to check state of inputs: {:#}", e);
+                    self.update_failed_inputs(proofs, ProofState::Unspent);
+                }
+            },
+            WalletError::Mint(MintError::QuotePending) => {
+                eprintln!("WARN: payment is pending, inputs may be spent once it is settled");
+                self.update_failed_inputs(proofs, ProofState::PendingSpend);
             }
-        });
-
-        if extracted_proofs.len() != amounts_len {
-            // rollback
-            self.proofs.append(&mut extracted_proofs);
-            bail!("Failed to find proofs with corresponding amounts");
+            _ => self.update_failed_inputs(proofs, ProofState::Unspent),
         }
+    }
 
-        Ok(extracted_proofs)
+    fn update_failed_inputs(&mut self, proofs: &[Proof], state: ProofState) {
+        if proofs.is_empty() {
+            return;
+        }
+        if let Err(e) = self.store.set_proofs_state(proofs, state) {
+            eprintln!(
+                "WARN: failed to update state of {} proofs: {:#}",
+                proofs.len(),
+                e
+            );
+        }
     }
 
-    fn split_amount(amount: u64) -> Vec<u64> {
-        let amounts = (0..32).map(|x| 2u64.pow(x)).collect::<Vec<_>>();
-        amounts
+    /// Asks mint for states of `proofs` (NUT-07). Returns spent, pending and unspent proofs.
+    async fn check_spent(&self, proofs: &[Proof]) -> Result<(Proofs, Proofs, Proofs)> {
+        let ys = proofs
             .iter()
-            .rev()
-            .fold((Vec::new(), amount), |(mut acc, total), &amount| {
-                if total >= amount {
-                    acc.push(amount);
-                }
-                (acc, total % amount)
-            })
-            .0
+            .map(|p| hash_to_curve(p.secret().as_bytes()))
+            .collect::<anyhow::Result<Vec<_>>>()
+            .map_err(WalletError::Crypto)?;
+        let states = self.mint.check_state(&ys).await?;
+
+        let (mut spent, mut pending, mut unspent) = (vec![], vec![], vec![]);
+        for (proof, y) in proofs.iter().zip(&ys) {
+            let state = states
+                .iter()
+                .find(|s| &s.y == y)
+                .ok_or_else(|| anyhow!("Mint did not report state of proof {}", y))?
+                .state;
+            match state {
+                SpendingState::Spent => spent.push(proof.clone()),
+                SpendingState::Pending => pending.push(proof.clone()),
+                SpendingState::Unspent => unspent.push(proof.clone()),
+            }
+        }
+        Ok((spent, pending, unspent))
     }
 
-    /// Returns (input_proofs, outputs, amounts_to_spend). Used for Cashu token creation.
-    fn prepare_amounts_for_swap_before_spend(
-        &mut self,
-        total_amount_to_spend: u64,
-    ) -> Result<(Proofs, Vec<u64>, Vec<u64>)> {
-        let mut available_amounts = self.proofs.iter().map(|p| p.amount).collect::<Vec<_>>();
-
-        available_amounts.sort();
-
-        let have_total = available_amounts.iter().sum::<u64>();
-        assert!(have_total >= total_amount_to_spend);
+    /// Moves proofs left reserved by a finished or failed operation back to unspent
+    fn release_reserved_proofs(&mut self) {
+        let result = self
+            .proofs_in_state(ProofState::Reserved)
+            .and_then(|reserved| self.set_proofs_state(&reserved, ProofState::Unspent));
 
-        // first try to find combinations in 'have_amounts', so we would not need to do swap for a change
-        if let Some(amounts_to_spend) =
-            helpers::find_subset_sum(&available_amounts, total_amount_to_spend)
-        {
-            return Ok((vec![], vec![], amounts_to_spend));
-        }
-
-        let mut to_spend_acc = 0;
-        let mut amounts_to_spend = vec![];
-
-        let mut last_amount = 0;
-        let mut last_proof = None;
-
-        for amount in available_amounts.iter() {
-            to_spend_acc += *amount;
-            if to_spend_acc < total_amount_to_spend {
-                amounts_to_spend.push(*amount);
-            } else {
-                last_amount = *amount;
-                last_proof = Some(
-                    self.extract_proofs_with_amounts(&[last_amount])?
-                        .first()
-                        .expect("proof surely exist")
-                        .clone(),
-                );
-
-                to_spend_acc -= last_amount;
-                break;
-            }
+        if let Err(e) = result {
+            eprintln!("WARN: failed to release reserved proofs: {:#}", e);
         }
+    }
+
+    fn proofs_in_state(&self, state: ProofState) -> Result<Proofs> {
+        Ok(self
+            .store
+            .proofs()
+            .map_err(WalletError::Storage)?
+            .into_iter()
+            .filter(|p| p.state == state)
+            .map(|p| p.proof)
+            .collect())
+    }
 
-        if last_proof.is_none() {
-            bail!("failed to find proof");
+    /// Groups proofs by unit of their keysets. Proofs of keysets missing in the cache
+    /// are counted as sats, the only unit used by older versions of the wallet.
+    fn group_by_unit(&self, proofs: Proofs) -> BTreeMap<CurrencyUnit, Proofs> {
+        let keyset_infos = self.mint.cached_keysets().map(|(_, infos)| infos);
+
+        let mut groups = BTreeMap::<CurrencyUnit, Proofs>::new();
+        for proof in proofs {
+            let unit = keyset_infos
+                .and_then(|infos| infos.keysets.iter().find(|ks| ks.id == proof.keyset_id))
+                .map(|ks| ks.unit.clone())
+                .unwrap_or_default();
+            groups.entry(unit).or_default().push(proof);
         }
-        let last_proof = last_proof.expect("proof was found");
+        groups
+    }
 
-        let missing_amount = total_amount_to_spend - to_spend_acc;
+    fn set_proofs_state(&mut self, proofs: &[Proof], state: ProofState) -> Result<()> {
+        self.store
+            .set_proofs_state(proofs, state)
+            .context("update state of proofs")
+            .map_err(WalletError::Storage)
+    }
 
-        let num_inputs = 1;
-        let last_proof_keyset_id = last_proof.keyset_id.clone();
+    /// Adds new proofs to the store
+    fn add_proofs(&mut self, proofs: &[Proof]) -> Result<()> {
+        self.store
+            .add_proofs(proofs)
+            .context("save proofs")
+            .map_err(WalletError::Storage)
+    }
 
-        let last_proof_keyset = self
-            .mint_keysets(false)
-            .inspect_err(|_| self.proofs.push(last_proof.clone()))?
-            .by_id(&last_proof_keyset_id)
-            .ok_or_else(|| anyhow!("Missing keyset {}", last_proof_keyset_id))
-            .inspect_err(|_| self.proofs.push(last_proof.clone()))?;
+    /// Persists keysets cached by mint client, so their changes can be detected in later sessions
+    fn store_keysets(&mut self) -> Result<()> {
+        if let Some((keysets, keyset_infos)) = self.mint.cached_keysets() {
+            self.store
+                .set_keysets(keysets, keyset_infos)
+                .context("save keysets")
+                .map_err(WalletError::Storage)?;
+        }
+        Ok(())
+    }
 
-        let total_fee = (num_inputs * last_proof_keyset.input_fee_ppk).div_ceil(1000);
+    /// Records finished operation in the wallet history
+    fn record(&mut self, entry: HistoryEntry) {
+        if let Err(e) = self.store.add_history(&entry) {
+            eprintln!(
+                "WARN: failed to record {:?} in history: {:#}",
+                entry.kind, e
+            );
+        }
+    }
 
-        if (last_amount - missing_amount) < total_fee {
+    /// Reserves unspent proofs for an operation, fails if some of them is not unspent
+    fn reserve_proofs(&mut self, proofs: &[Proof]) -> anyhow::Result<()> {
+        let unspent = self.proofs()?;
+        if let Some(p) = proofs
+            .iter()
+            .find(|p| !unspent.iter().any(|u| u.secret() == p.secret()))
+        {
             bail!(
-                "Insufficient funds, fee {} exceeds the amount for swap {}",
-                total_fee,
-                last_amount - missing_amount
-            )
+                "Proof of amount {} from keyset {} is not unspent",
+                p.amount,
+                p.keyset_id
+            );
         }
 
-        let mut missing_change = Self::split_amount(missing_amount);
+        self.store.set_proofs_state(proofs, ProofState::Reserved)
+    }
 
-        let mut needed_change_amounts =
-            Self::split_amount(last_amount - missing_amount - total_fee);
-        needed_change_amounts.extend_from_slice(&missing_change);
+    /// Takes proofs with specified amounts out of `proofs`
+    fn take_proofs_with_amounts(proofs: Proofs, amounts: &[u64]) -> anyhow::Result<Proofs> {
+        let mut amounts = amounts.to_vec();
+        let mut taken = vec![];
 
-        assert_eq!(
-            last_amount,
-            needed_change_amounts.iter().sum::<u64>() + total_fee
-        );
+        for p in proofs {
+            if let Some(index) = amounts.iter().position(|amount| p.amount == *amount) {
+                amounts.swap_remove(index);
+                taken.push(p);
+            }
+        }
 
-        // we need to send 'amounts_to_spend' + 'missing_change'
-        amounts_to_spend.append(&mut missing_change);
+        if !amounts.is_empty() {
+            bail!("Failed to find proofs with corresponding amounts");
+        }
 
-        assert_eq!(amounts_to_spend.iter().sum::<u64>(), total_amount_to_spend);
+        Ok(taken)
+    }
+
+    /// Splits `amount` into as few proofs of `denominations` as possible, largest amounts first.
+    /// Fails if the amount can not be composed of the denominations.
+    fn split_amount(amount: u64, denominations: &[u64]) -> anyhow::Result<Vec<u64>> {
+        let mut denominations = denominations
+            .iter()
+            .copied()
+            .filter(|d| *d > 0)
+            .collect::<Vec<_>>();
+        denominations.sort();
+        denominations.dedup();
+        denominations.reverse();
+
+        let mut tries = 0;
+        let counts = Self::split_counts(amount, &denominations, &mut tries).ok_or_else(|| {
+            anyhow!(
+                "Amount {} can not be composed of keyset amounts {:?}",
+                amount,
+                denominations
+            )
+        })?;
 
-        let input_proofs = vec![last_proof];
+        let outputs = counts.iter().sum::<u64>();
+        if outputs > MAX_OUTPUTS {
+            bail!(
+                "Amount {} needs {} proofs of keyset amounts, at most {} are allowed",
+                amount,
+                outputs,
+                MAX_OUTPUTS
+            );
+        }
 
-        Ok((input_proofs, needed_change_amounts, amounts_to_spend))
+        Ok(denominations
+            .iter()
+            .zip(counts)
+            .flat_map(|(d, count)| std::iter::repeat_n(*d, count as usize))
+            .collect())
     }
 
-    /// Extracts and returns proofs to be spend and potential swap fee. Used for Cashu token creation.
-    fn prepare_inputs_for_spend(&mut self, amount: u64) -> Result<(Proofs, u64)> {
-        let (input_proofs, output_amounts, amounts_to_spend) =
-            self.prepare_amounts_for_swap_before_spend(amount)?;
+    /// Returns count of each of descending `denominations` summing to `amount`.
+    /// Greedy split is tried first, it is optimal for powers of two; other denomination systems
+    /// may need a few proofs less of larger amounts, so it backtracks within bounded number of tries.
+    fn split_counts(amount: u64, denominations: &[u64], tries: &mut u32) -> Option<Vec<u64>> {
+        let (&denomination, smaller) = denominations.split_first()?;
+        if smaller.is_empty() {
+            return amount
+                .is_multiple_of(denomination)
+                .then(|| vec![amount / denomination]);
+        }
 
-        let mut swap_fee = 0;
+        for count in (0..=amount / denomination).rev() {
+            *tries += 1;
+            if *tries > MAX_SPLIT_TRIES {
+                return None;
+            }
+            if let Some(mut counts) =
+                Self::split_counts(amount - count * denomination, smaller, tries)
+            {
+                counts.insert(0, count);
+                return Some(counts);
+            }
+        }
 
-        if !input_proofs.is_empty() && !output_amounts.is_empty() {
-            // we need to do a swap to get a change
-            let (mut new_proofs, fee) = self
-                .swap_proofs(&input_proofs, Some(&output_amounts))
-                .context("swap proofs")?;
-            swap_fee = fee;
+        None
+    }
 
-            self.proofs.append(&mut new_proofs);
+    /// Returns amounts of proofs which can be signed by keys of a keyset
+    fn denominations(keys: &AmountKeys) -> Vec<u64> {
+        keys.keys().map(|amount| amount.value()).collect()
+    }
 
-            self.save()?;
-        }
+    /// Returns amounts of proofs which can be signed by the active keyset for `unit`
+    async fn active_denominations(&mut self, unit: &CurrencyUnit) -> Result<Vec<u64>> {
+        let keyset_id = self
+            .mint_keysets(true)
+            .await?
+            .for_unit(unit)
+            .ok_or_else(|| anyhow!("No active keyset for '{}'", unit))?
+            .id;
+        let keyset = self
+            .mint_keys()
+            .await?
+            .by_id(&keyset_id)
+            .ok_or_else(|| anyhow!("Mint did not provided active keys"))?;
 
-        let proofs = self
-            .extract_proofs_with_amounts(&amounts_to_spend)
-            .context("find proofs with required amounts")?;
+        Ok(Self::denominations(&keyset.keys))
+    }
 
+    /// Extracts and returns proofs to be spend and potential swap fee. Used for Cashu token creation.
+    async fn prepare_inputs_for_spend(
+        &mut self,
+        amount: Amount,
+        unit: &CurrencyUnit,
+    ) -> Result<(Proofs, Amount)> {
+        let (proofs, _, swap_fee) = self
+            .reserve_proofs_for_spending(amount, unit, false)
+            .await?;
         Ok((proofs, swap_fee))
     }
 
@@ -829,63 +1508,161 @@ impl Wallet {
         1.max((fee_reserve).ilog2() + 1)
     }
 
-    fn create_mint_quote(&self, amount: u64) -> Result<(MintQuote, SecretKey)> {
+    async fn create_mint_quote(
+        &self,
+        amount: Amount,
+        unit: &CurrencyUnit,
+    ) -> Result<(MintQuote, SecretKey)> {
         let secret_key = SecretKey::generate(); // NUT-20: Signature on Mint Quote
         let pubkey = secret_key.public_key();
 
         let quote = self
             .mint
-            .create_mint_quote(amount, pubkey)
+            .create_mint_quote(amount, unit, pubkey)
+            .await
             .context("create_mint_quote")?;
 
         Ok((quote, secret_key))
     }
 
-    fn check_mint_quote(&self, quote_id: &str) -> Result<MintQuote> {
+    async fn check_mint_quote(&self, quote_id: &str) -> Result<MintQuote> {
         let quote = self
             .mint
             .get_mint_quote(quote_id)
+            .await
             .context("get_mint_quote")?;
         Ok(quote)
     }
 
-    fn create_melt_quote(&self, invoice: &str) -> Result<MeltQuote> {
+    /// Requests melt quote in `unit` after validating the invoice, checks that mint quoted the invoice amount.
+    /// `amount` in sats is required for invoices without amount.
+    async fn create_melt_quote(
+        &mut self,
+        invoice: &str,
+        amount: Option<Amount>,
+        unit: &CurrencyUnit,
+    ) -> Result<MeltQuote> {
+        let decoded = Self::validate_invoice(invoice, amount)?;
+
+        let requested_msat = amount
+            .map(|amount| {
+                amount.value().checked_mul(1000).ok_or_else(|| {
+                    WalletError::InvalidInvoice(format!("amount {} sat is too large", amount))
+                })
+            })
+            .transpose()?;
+
+        let amount_msat = match decoded.amount_msat {
+            Some(amount_msat) => amount_msat,
+            None => {
+                if !self.mint_info().await?.supports_amountless_melt(unit) {
+                    return Err(WalletError::Other(anyhow!(
+                        "Mint does not support paying invoices without amount"
+                    )));
+                }
+                requested_msat.unwrap_or_default()
+            }
+        };
+
+        let options = requested_msat.map(|amount_msat| MeltOptions::Amountless { amount_msat });
+
         let quote = self
             .mint
-            .create_melt_quote(invoice)
+            .create_melt_quote(invoice, unit, options)
+            .await
             .context("create_melt_quote")?;
+
+        Self::check_quote_amount(&quote, amount_msat)?;
         Ok(quote)
     }
 
-    fn load(name: &str, password: &str) -> Result<Self> {
-        let decryption_key = password::derive_encryption_key(password, name)?;
+    /// Quotes in sats may round paid amount with fraction of sat. Quotes in fiat units
+    /// depend on exchange rate of the mint and can not be checked.
+    fn check_quote_amount(quote: &MeltQuote, amount_msat: u64) -> Result<()> {
+        let amount = quote.amount.value();
+        let (min, max) = match quote.unit {
+            CurrencyUnit::Sat => (amount_msat / 1000, amount_msat.div_ceil(1000)),
+            CurrencyUnit::Msat => (amount_msat, amount_msat),
+            _ => return Ok(()),
+        };
+        if amount < min || amount > max {
+            return Err(WalletError::QuoteMismatch {
+                invoice: max.into(),
+                quote: quote.amount,
+            });
+        }
+        Ok(())
+    }
 
-        let path = PathBuf::from(WALLETS_DIR).join(Self::filename(name));
-        let mut w = file::load(path.as_path(), &decryption_key).context("load wallet file")?;
+    /// Decodes the invoice and rejects expired invoices. Amount in sats must be specified
+    /// only for invoices without amount.
+    pub fn validate_invoice(invoice: &str, amount: Option<Amount>) -> Result<Invoice> {
+        let decoded = Invoice::from_str(invoice)
+            .map_err(|e| WalletError::InvalidInvoice(format!("{:#}", e)))?;
 
-        w.encryption_key = decryption_key;
+        if decoded.is_expired() {
+            return Err(WalletError::InvalidInvoice(
+                "invoice has expired".to_string(),
+            ));
+        }
 
-        Ok(w)
+        let reason = match (decoded.amount_msat, amount) {
+            (Some(0), _) | (None, Some(Amount::ZERO)) => "amount must not be zero",
+            (Some(_), Some(_)) => "amount can be specified only for invoice without amount",
+            (None, None) => "invoice has no amount, amount to pay must be specified",
+            _ => return Ok(decoded),
+        };
+        Err(WalletError::InvalidInvoice(reason.to_string()))
     }
 
-    pub fn save(&self) -> Result<()> {
-        let path = PathBuf::from(WALLETS_DIR).join(Self::filename(&self.name));
-        file::save(self, path.as_path(), &self.encryption_key).context("save wallet file")?;
-        Ok(())
+    /// Returns kind of the store of existing wallet
+    fn stored_kind(wallet_name: &str) -> Option<StoreKind> {
+        StoreKind::ALL
+            .iter()
+            .copied()
+            .find(|kind| Self::path(wallet_name, *kind).exists())
     }
 
-    fn filename(wallet_name: &str) -> String {
+    fn path(wallet_name: &str, store_kind: StoreKind) -> PathBuf {
         let mut filename = wallet_name.to_string();
-        filename.push_str(WALLET_FILE_EXT);
-        filename
+        filename.push_str(store_kind.extension());
+        PathBuf::from(WALLETS_DIR).join(filename)
     }
 }
 
-#[expect(dead_code)]
-#[derive(Default, Debug)]
-struct MintSecrets {
-    keyset_id: String,
-    secrets: Vec<MintSecret>,
+/// Estimate of fees of spending proofs, see [`Wallet::preview_melt`] and [`Wallet::preview_send`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpendPreview {
+    /// Amount to be paid or sent
+    pub amount: Amount,
+    /// Lightning fee reserve (melting only)
+    pub fee_reserve: Amount,
+    /// Fee for proofs used as inputs paid by the wallet (melting only, receiver pays it for sent tokens)
+    pub input_fee: Amount,
+    /// Proofs have to be swapped first to get the exact amount
+    pub swap_needed: bool,
+    pub swap_fee: Amount,
+}
+
+/// Sums input fees of proofs in parts per thousand, rounded up to whole units (NUT-02)
+fn input_fee(fees_ppk: impl IntoIterator<Item = u64>) -> Result<Amount> {
+    let fee_ppk = fees_ppk
+        .into_iter()
+        .try_fold(0u64, u64::checked_add)
+        .ok_or_else(|| anyhow!("Input fee overflow"))?;
+    Ok(fee_ppk.div_ceil(1000).into())
+}
+
+impl SpendPreview {
+    /// Maximum amount leaving the wallet, unused fee reserve is returned as change
+    pub fn worst_case_total(&self) -> Result<Amount> {
+        Ok(Amount::try_sum([
+            self.amount,
+            self.fee_reserve,
+            self.input_fee,
+            self.swap_fee,
+        ])?)
+    }
 }
 
 #[derive(Debug)]
@@ -894,6 +1671,22 @@ struct MintSecret {
     pub r: SecretKey,
 }
 
+impl MintSecret {
+    fn to_blank_output(&self) -> BlankOutput {
+        BlankOutput {
+            secret: self.secret.clone(),
+            r: self.r.clone(),
+        }
+    }
+
+    fn from_blank_output(output: &BlankOutput) -> Self {
+        Self {
+            secret: output.secret.clone(),
+            r: output.r.clone(),
+        }
+    }
+}
+
 mod password {
     use super::*;
 
@@ -903,7 +1696,7 @@ mod password {
         password_hash::{PasswordHasher, SaltString, rand_core::OsRng},
     };
 
-    pub(crate) fn is_valid_for_wallet(password: &str, wallet_name: &str) -> Result<bool> {
+    pub(crate) fn is_valid_for_wallet(password: &str, wallet_name: &str) -> anyhow::Result<bool> {
         let mut filename = wallet_name.to_string();
         filename.push_str(".pw");
 
@@ -926,7 +1719,7 @@ mod password {
         Ok(false)
     }
 
-    pub(crate) fn save(wallet_name: &str, password: &str) -> Result<()> {
+    pub(crate) fn save(wallet_name: &str, password: &str) -> anyhow::Result<()> {
         let salt = SaltString::generate(&mut OsRng);
 
         let argon2 = Argon2::default();
@@ -955,14 +1748,17 @@ mod password {
         Ok(())
     }
 
-    pub(crate) fn derive_encryption_key(password: &str, wallet_name: &str) -> Result<[u8; 32]> {
+    pub(crate) fn derive_encryption_key(
+        password: &str,
+        wallet_name: &str,
+    ) -> anyhow::Result<Zeroizing<[u8; 32]>> {
         let salt = wallet_name.repeat(3);
 
-        let mut encryption_key = [0u8; 32];
+        let mut encryption_key = Zeroizing::new([0u8; 32]);
         Argon2::default().hash_password_into(
             password.as_bytes(),
             salt.as_bytes(),
-            &mut encryption_key,
+            encryption_key.as_mut(),
         )?;
 
         Ok(encryption_key)
@@ -973,13 +1769,238 @@ mod password {
 mod tests {
     use super::*;
 
+    use secp256k1::hashes::{Hash, sha256};
+
+    use crate::{
+        cashu::test_proof,
+        mint::mock::{MockMint, block_on},
+    };
+
+    const KEY: &str = "0273129c5719e599379a974a626363c333c56cafc0e6d01abe46d5808280789c63";
+
+    /// Wallet with memory store and cached keysets of mint at `mint_url`.
+    /// `keysets` are tuples of keyset ID, unit, active flag and input fee.
+    fn test_wallet(mint_url: &str, keysets: &[(&str, &str, bool, u64)]) -> Wallet {
+        let keys = (0..8)
+            .map(|i| ((1u64 << i).to_string(), KEY))
+            .collect::<BTreeMap<_, _>>();
+        let all_keysets: AllKeysets = serde_json::from_value(serde_json::json!({
+            "keysets": keysets
+                .iter()
+                .map(|(id, unit, _, _)| serde_json::json!({ "id": id, "unit": unit, "keys": keys }))
+                .collect::<Vec<_>>(),
+        }))
+        .unwrap();
+        let keyset_infos: AllKeysetInfos = serde_json::from_value(serde_json::json!({
+            "keysets": keysets
+                .iter()
+                .map(|(id, unit, active, fee)| {
+                    serde_json::json!({ "id": id, "unit": unit, "active": active, "input_fee_ppk": fee })
+                })
+                .collect::<Vec<_>>(),
+        }))
+        .unwrap();
+
+        Wallet {
+            name: "test".to_string(),
+            mint: Mint::new(mint_url)
+                .unwrap()
+                .with_cached_keysets(Some(all_keysets), Some(keyset_infos)),
+            store: Box::new(crate::store::MemoryStore::new(mint_url)),
+            coin_selector: Box::new(CoinSelection::default()),
+        }
+    }
+
+    /// Invoice with amount of 9 sat created now, paid by preimage of 32 zero bytes
+    fn test_invoice() -> String {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let payment_hash = sha256::Hash::hash(&[0; 32]).to_byte_array();
+        crate::bolt11::test_invoice("90n", now, payment_hash, 3600)
+    }
+
+    /// Melt of 9 sat in quote `q1` waiting for its payment, with inputs of 12 sat
+    /// and 2 blank outputs for change
+    fn pending_melt(w: &mut Wallet) -> PendingMelt {
+        let inputs = vec![
+            test_proof(8, "00aaaaaaaaaaaaaa", "s1"),
+            test_proof(4, "00aaaaaaaaaaaaaa", "s2"),
+        ];
+        w.add_proofs(&inputs).unwrap();
+        w.set_proofs_state(&inputs, ProofState::PendingSpend)
+            .unwrap();
+
+        let melt = PendingMelt {
+            quote_id: "q1".to_string(),
+            request: test_invoice(),
+            amount: 9.into(),
+            fee_reserve: 3.into(),
+            unit: CurrencyUnit::Sat,
+            expiry: None,
+            keyset_id: "00aaaaaaaaaaaaaa".parse().unwrap(),
+            inputs,
+            blank_outputs: [("change2", 2), ("change3", 3)]
+                .into_iter()
+                .map(|(secret, r)| BlankOutput {
+                    secret: secret.into(),
+                    r: SecretKey::from_slice(&[r; 32]).unwrap(),
+                })
+                .collect(),
+        };
+        w.store.add_pending_melt(&melt).unwrap();
+        melt
+    }
+
+    /// Response of mint with melt quote `q1` in `state`
+    fn melt_quote(state: &str, preimage: Option<&str>, change: &[u64]) -> serde_json::Value {
+        serde_json::json!({
+            "quote": "q1",
+            "request": test_invoice(),
+            "amount": 9,
+            "unit": "sat",
+            "state": state,
+            "fee_reserve": 3,
+            "payment_preimage": preimage,
+            "change": change
+                .iter()
+                .map(|amount| {
+                    serde_json::json!({ "amount": amount, "id": "00aaaaaaaaaaaaaa", "C_": KEY })
+                })
+                .collect::<Vec<_>>(),
+        })
+    }
+
+    fn proof_states(w: &Wallet) -> Vec<(Vec<u8>, ProofState)> {
+        w.store
+            .proofs()
+            .unwrap()
+            .into_iter()
+            .map(|p| (p.proof.secret().as_bytes().to_vec(), p.state))
+            .collect()
+    }
+
     #[test]
     fn test_split_amount() {
-        assert_eq!(Wallet::split_amount(1), vec![1]);
-        assert_eq!(Wallet::split_amount(2), vec![2]);
-        assert_eq!(Wallet::split_amount(3), vec![2, 1]);
-        assert_eq!(Wallet::split_amount(11), vec![8, 2, 1]);
-        assert_eq!(Wallet::split_amount(255), vec![128, 64, 32, 16, 8, 4, 2, 1]);
+        let powers = |n: u32| (0..n).map(|x| 1u64 << x).collect::<Vec<_>>();
+        let split = |amount, denominations: &[u64]| {
+            let amounts = Wallet::split_amount(amount, denominations).unwrap();
+            assert_eq!(amounts.iter().sum::<u64>(), amount);
+            amounts
+        };
+
+        assert_eq!(split(1, &powers(32)), vec![1]);
+        assert_eq!(split(2, &powers(32)), vec![2]);
+        assert_eq!(split(3, &powers(32)), vec![2, 1]);
+        assert_eq!(split(11, &powers(32)), vec![8, 2, 1]);
+        assert_eq!(split(255, &powers(32)), vec![128, 64, 32, 16, 8, 4, 2, 1]);
+        assert_eq!(split(0, &powers(32)), Vec::<u64>::new());
+
+        // amounts above the largest denomination are not truncated
+        assert_eq!(split(1 << 32, &powers(32)), vec![1 << 31, 1 << 31]);
+        assert_eq!(split(100, &powers(4)).len(), 13);
+        assert_eq!(split(u64::MAX, &powers(64)).len(), 64);
+        assert_eq!(split(u64::MAX - 1, &powers(64)).len(), 63);
+
+        // other denomination systems
+        assert_eq!(split(18, &[1, 2, 5, 10]), vec![10, 5, 2, 1]);
+        assert_eq!(split(9, &[2, 3, 5]), vec![5, 2, 2]);
+        assert_eq!(split(9, &[3, 5]), vec![3, 3, 3]);
+        assert_eq!(split(12, &[1000, 4, 2]), vec![4, 4, 4]);
+
+        // amounts which can not be represented
+        assert!(Wallet::split_amount(3, &[2, 4, 8]).is_err());
+        assert!(Wallet::split_amount(7, &[3, 5]).is_err());
+        assert!(Wallet::split_amount(1, &[]).is_err());
+        assert!(Wallet::split_amount(u64::MAX, &[2]).is_err());
+        // too many outputs
+        assert!(Wallet::split_amount(u64::MAX, &[1]).is_err());
+        // search for odd amount with even denominations is bounded
+        assert!(Wallet::split_amount(u64::MAX, &[2, 4, 6]).is_err());
+    }
+
+    #[test]
+    fn test_futures_are_send() {
+        // async API has to be usable from multi-threaded runtimes (e.g. in tokio::spawn)
+        fn assert_send<T: Send>(_: &T) {}
+
+        #[expect(dead_code)]
+        fn check(w: &mut Wallet, token: TokenV4) {
+            let sat = CurrencyUnit::Sat;
+            assert_send(&w.mint_tokens(1.into(), &sat));
+            assert_send(&w.melt_tokens("lnbc", None, &sat));
+            assert_send(&w.melt_status("quote"));
+            assert_send(&w.prepare_cashu_token(1.into(), &sat));
+            assert_send(&w.receive_via_cashu_token(token));
+            assert_send(&w.rotate_proofs(&sat));
+            assert_send(&Wallet::open("name", "password"));
+            assert_send(&Wallet::melt_mpp(&mut [w], "lnbc"));
+        }
+    }
+
+    #[test]
+    fn test_reserve_and_release_proofs() {
+        let proof = |amount, secret| test_proof(amount, "00ad268c4d1f5826", secret);
+        let mut w = test_wallet(
+            "http://localhost:3338",
+            &[("00ad268c4d1f5826", "sat", true, 0)],
+        );
+        w.add_proofs(&[proof(1, "s1"), proof(2, "s2"), proof(4, "s3")])
+            .unwrap();
+
+        let reserved = [proof(1, "s1"), proof(4, "s3")];
+        w.reserve_proofs(&reserved).unwrap();
+        assert_eq!(w.balance(&CurrencyUnit::Sat).unwrap(), 2);
+        // error does not reveal secret of the proof
+        let err = w.reserve_proofs(&[proof(4, "s3")]).unwrap_err();
+        assert!(!format!("{:#}", err).contains("s3"));
+
+        let taken = Wallet::take_proofs_with_amounts(w.proofs().unwrap(), &[2]).unwrap();
+        assert_eq!(taken.len(), 1);
+        assert!(Wallet::take_proofs_with_amounts(w.proofs().unwrap(), &[4]).is_err());
+
+        w.set_proofs_state(&reserved[..1], ProofState::PendingSpend)
+            .unwrap();
+        w.release_reserved_proofs();
+        assert_eq!(w.balance(&CurrencyUnit::Sat).unwrap(), 6);
+        assert_eq!(w.pending_balance(&CurrencyUnit::Sat).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_balances_by_unit() {
+        let mut w = test_wallet(
+            "http://localhost:3338",
+            &[
+                ("00aaaaaaaaaaaaaa", "sat", true, 0),
+                ("00bbbbbbbbbbbbbb", "usd", true, 0),
+            ],
+        );
+        w.add_proofs(&[
+            test_proof(1, "00aaaaaaaaaaaaaa", "s1"),
+            test_proof(2, "00bbbbbbbbbbbbbb", "s2"),
+            test_proof(4, "00bbbbbbbbbbbbbb", "s3"),
+            // keysets unknown to the wallet hold sats
+            test_proof(8, "00cccccccccccccc", "s4"),
+        ])
+        .unwrap();
+
+        assert_eq!(w.balance(&CurrencyUnit::Sat).unwrap(), 9);
+        assert_eq!(w.balance(&CurrencyUnit::Usd).unwrap(), 6);
+        assert_eq!(w.balance(&CurrencyUnit::Eur).unwrap(), 0);
+        assert_eq!(
+            w.balances().unwrap().into_iter().collect::<Vec<_>>(),
+            vec![(CurrencyUnit::Sat, 9.into()), (CurrencyUnit::Usd, 6.into())]
+        );
+        assert_eq!(w.unit_proofs(&CurrencyUnit::Usd).unwrap().len(), 2);
+
+        w.set_proofs_state(
+            &[test_proof(4, "00bbbbbbbbbbbbbb", "s3")],
+            ProofState::PendingSpend,
+        )
+        .unwrap();
+        assert_eq!(w.pending_balance(&CurrencyUnit::Usd).unwrap(), 4);
+        assert_eq!(w.pending_balance(&CurrencyUnit::Sat).unwrap(), 0);
     }
 
     #[test]
@@ -994,4 +2015,377 @@ mod tests {
         assert_eq!(Wallet::calculate_number_of_blank_outputs(900), 10);
         assert_eq!(Wallet::calculate_number_of_blank_outputs(1000), 10);
     }
+
+    #[test]
+    fn test_rotation_of_inactive_keysets() {
+        // mint rejects the swap, so only inputs of the request are checked
+        let mock = MockMint::start(|_| {
+            (
+                400,
+                serde_json::json!({ "code": 11002, "detail": "unbalanced" }),
+            )
+        });
+
+        let mut w = test_wallet(
+            mock.url(),
+            &[
+                ("00aaaaaaaaaaaaaa", "sat", true, 100),
+                ("00bbbbbbbbbbbbbb", "sat", false, 300),
+                ("00cccccccccccccc", "sat", false, 200),
+                ("00dddddddddddddd", "usd", false, 1000),
+            ],
+        );
+        w.add_proofs(&[
+            test_proof(1, "00aaaaaaaaaaaaaa", "s1"),
+            test_proof(2, "00bbbbbbbbbbbbbb", "s2"),
+            test_proof(4, "00bbbbbbbbbbbbbb", "s3"),
+            test_proof(8, "00cccccccccccccc", "s4"),
+            test_proof(16, "00dddddddddddddd", "s5"),
+        ])
+        .unwrap();
+
+        let sat = CurrencyUnit::Sat;
+
+        // 2 + 4 + 8 sat from inactive keysets, fee ceil((300 + 300 + 200) / 1000)
+        assert_eq!(
+            block_on(w.estimate_rotation(&sat)).unwrap(),
+            (14.into(), 1.into())
+        );
+        assert_eq!(
+            block_on(w.estimate_rotation(&CurrencyUnit::Usd)).unwrap(),
+            (16.into(), 1.into())
+        );
+
+        assert!(block_on(w.rotate_proofs(&sat)).is_err());
+        let swaps = mock.requests("/v1/swap");
+        assert_eq!(swaps.len(), 1);
+        let secrets = swaps[0]["inputs"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|input| input["secret"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(secrets, vec!["s2", "s3", "s4"]);
+        let output_total: u64 = swaps[0]["outputs"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|output| output["amount"].as_u64().unwrap())
+            .sum();
+        assert_eq!(output_total, 13);
+
+        // inputs of the rejected swap are spendable again
+        assert_eq!(w.balance(&sat).unwrap(), 15);
+    }
+
+    #[test]
+    fn test_rebalance_pays_input_fee() {
+        // mint rejects the swap, so only the request is checked
+        let mock = MockMint::start(|_| {
+            (
+                400,
+                serde_json::json!({ "code": 11002, "detail": "unbalanced" }),
+            )
+        });
+
+        let mut w = test_wallet(mock.url(), &[("00aaaaaaaaaaaaaa", "sat", true, 1000)]);
+        w.add_proofs(&[
+            test_proof(4, "00aaaaaaaaaaaaaa", "s1"),
+            test_proof(4, "00aaaaaaaaaaaaaa", "s2"),
+        ])
+        .unwrap();
+
+        let sat = CurrencyUnit::Sat;
+
+        // 8 less 1 sat fee is consolidated as 4 + 2 + 1, so only one proof is swapped
+        assert_eq!(
+            block_on(w.estimate_rebalance(&sat)).unwrap(),
+            (4.into(), 1.into())
+        );
+        assert!(block_on(w.rebalance_proofs(&sat)).is_err());
+        let swaps = mock.requests("/v1/swap");
+        assert_eq!(swaps.len(), 1);
+        assert_eq!(swaps[0]["inputs"].as_array().unwrap().len(), 1);
+        let output_total: u64 = swaps[0]["outputs"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|output| output["amount"].as_u64().unwrap())
+            .sum();
+        assert_eq!(output_total, 3);
+
+        // fee would take the whole amount
+        let mut w = test_wallet(mock.url(), &[("00aaaaaaaaaaaaaa", "sat", true, 1000)]);
+        w.add_proofs(&[
+            test_proof(1, "00aaaaaaaaaaaaaa", "s1"),
+            test_proof(1, "00aaaaaaaaaaaaaa", "s2"),
+        ])
+        .unwrap();
+        assert!(matches!(
+            block_on(w.estimate_rebalance(&sat)),
+            Err(WalletError::InsufficientFunds { .. })
+        ));
+    }
+
+    #[test]
+    fn test_only_spent_inputs_are_discarded() {
+        let spent_y = hash_to_curve(b"s2").unwrap().to_hex();
+
+        let mock = MockMint::start(move |request| match request.path.as_str() {
+            "/v1/swap" => (
+                400,
+                serde_json::json!({ "code": 11001, "detail": "Token already spent" }),
+            ),
+            "/v1/checkstate" => {
+                let states = request.body["Ys"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|y| {
+                        let state = if y == spent_y.as_str() {
+                            "SPENT"
+                        } else {
+                            "UNSPENT"
+                        };
+                        serde_json::json!({ "Y": y, "state": state })
+                    })
+                    .collect::<Vec<_>>();
+                (200, serde_json::json!({ "states": states }))
+            }
+            _ => (404, serde_json::json!({})),
+        });
+
+        let mut w = test_wallet(
+            mock.url(),
+            &[
+                ("00aaaaaaaaaaaaaa", "sat", true, 0),
+                ("00bbbbbbbbbbbbbb", "sat", false, 0),
+            ],
+        );
+        w.add_proofs(&[
+            test_proof(1, "00bbbbbbbbbbbbbb", "s1"),
+            test_proof(2, "00bbbbbbbbbbbbbb", "s2"),
+            test_proof(4, "00bbbbbbbbbbbbbb", "s3"),
+        ])
+        .unwrap();
+
+        let sat = CurrencyUnit::Sat;
+        assert!(matches!(
+            block_on(w.rotate_proofs(&sat)),
+            Err(WalletError::Mint(MintError::TokenAlreadySpent))
+        ));
+        assert_eq!(mock.requests("/v1/checkstate").len(), 1);
+
+        // only the proof reported as spent is gone
+        let mut secrets = w
+            .proofs()
+            .unwrap()
+            .iter()
+            .map(|p| p.secret().as_bytes().to_vec())
+            .collect::<Vec<_>>();
+        secrets.sort();
+        assert_eq!(secrets, vec![b"s1", b"s3"]);
+        assert_eq!(w.balance(&sat).unwrap(), 5);
+    }
+
+    #[test]
+    fn test_settle_paid_melt() {
+        let preimage = "00".repeat(32);
+        let response = melt_quote("PAID", Some(&preimage), &[2]);
+        let mock = MockMint::start(move |request| match request.path.as_str() {
+            "/v1/melt/quote/bolt11/q1" => (200, response.clone()),
+            _ => (404, serde_json::json!({})),
+        });
+
+        let mut w = test_wallet(mock.url(), &[("00aaaaaaaaaaaaaa", "sat", true, 0)]);
+        pending_melt(&mut w);
+
+        let quote = block_on(w.melt_status("q1")).unwrap();
+        assert_eq!(quote.state, QuoteState::Paid);
+
+        // inputs are spent and change is unblinded with blinding factor of the first blank output
+        let states = proof_states(&w);
+        assert_eq!(
+            states[..2],
+            [
+                (b"s1".to_vec(), ProofState::Spent),
+                (b"s2".to_vec(), ProofState::Spent),
+            ]
+        );
+        assert_eq!(states[2], (b"change2".to_vec(), ProofState::Unspent));
+        assert_eq!(w.balance(&CurrencyUnit::Sat).unwrap(), 2);
+        assert!(w.pending_melts().unwrap().is_empty());
+
+        let history = w.history().unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].kind, TransactionKind::Melt);
+        assert_eq!(history[0].amount, 9);
+        assert_eq!(history[0].fee, 1);
+        assert_eq!(history[0].preimage, Some(preimage));
+    }
+
+    #[test]
+    fn test_verified_preimage() {
+        let mut w = test_wallet(
+            "http://localhost:3338",
+            &[("00aaaaaaaaaaaaaa", "sat", true, 0)],
+        );
+        let mut melt = pending_melt(&mut w);
+        let quote = |preimage: Option<&str>| -> MeltQuote {
+            serde_json::from_value(melt_quote("PAID", preimage, &[])).unwrap()
+        };
+
+        let preimage = "00".repeat(32);
+        assert_eq!(
+            Wallet::verified_preimage(&melt, &quote(Some(&preimage))),
+            Some(preimage.clone())
+        );
+        assert_eq!(Wallet::verified_preimage(&melt, &quote(None)), None);
+        assert_eq!(
+            Wallet::verified_preimage(&melt, &quote(Some(&"01".repeat(32)))),
+            None
+        );
+        assert_eq!(
+            Wallet::verified_preimage(&melt, &quote(Some("not hex"))),
+            None
+        );
+
+        melt.request = "lnbc1invalid".to_string();
+        assert_eq!(
+            Wallet::verified_preimage(&melt, &quote(Some(&preimage))),
+            None
+        );
+    }
+
+    #[test]
+    fn test_failed_melt_returns_inputs() {
+        let mock = MockMint::start(|request| match request.path.as_str() {
+            "/v1/melt/quote/bolt11" | "/v1/melt/quote/bolt11/q1" | "/v1/melt/bolt11" => {
+                (200, melt_quote("UNPAID", None, &[]))
+            }
+            _ => (404, serde_json::json!({})),
+        });
+
+        let mut w = test_wallet(mock.url(), &[("00aaaaaaaaaaaaaa", "sat", true, 0)]);
+        let sat = CurrencyUnit::Sat;
+
+        // pending melt resolved as failed
+        pending_melt(&mut w);
+        assert_eq!(w.balance(&sat).unwrap(), 0);
+        let quote = block_on(w.melt_status("q1")).unwrap();
+        assert_eq!(quote.state, QuoteState::Unpaid);
+        assert_eq!(w.balance(&sat).unwrap(), 12);
+        assert!(w.pending_melts().unwrap().is_empty());
+
+        // payment failing right away
+        let quote = block_on(w.melt_tokens(&test_invoice(), None, &sat)).unwrap();
+        assert_eq!(quote.state, QuoteState::Unpaid);
+        let melt_request = &mock.requests("/v1/melt/bolt11")[0];
+        assert_eq!(melt_request["inputs"].as_array().unwrap().len(), 2);
+
+        assert_eq!(w.balance(&sat).unwrap(), 12);
+        assert!(
+            proof_states(&w)
+                .iter()
+                .all(|(_, state)| *state == ProofState::Unspent)
+        );
+        assert!(w.pending_melts().unwrap().is_empty());
+        assert!(w.history().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_validate_invoice() {
+        let hash = [0; 32];
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let reason = |invoice: &str, amount| match Wallet::validate_invoice(invoice, amount) {
+            Err(WalletError::InvalidInvoice(reason)) => reason,
+            other => panic!("invoice should be rejected, got {:?}", other),
+        };
+
+        let invoice = Wallet::validate_invoice(&test_invoice(), None).unwrap();
+        assert_eq!(invoice.amount_sat(), Some(9));
+        let amountless = crate::bolt11::test_invoice("", now, hash, 3600);
+        assert_eq!(
+            Wallet::validate_invoice(&amountless, Some(5.into()))
+                .unwrap()
+                .amount_msat,
+            None
+        );
+
+        let expired = crate::bolt11::test_invoice("90n", now - 3601, hash, 3600);
+        assert_eq!(reason(&expired, None), "invoice has expired");
+
+        let zero = crate::bolt11::test_invoice("0n", now, hash, 3600);
+        assert_eq!(reason(&zero, None), "amount must not be zero");
+        assert_eq!(
+            reason(&amountless, Some(Amount::ZERO)),
+            "amount must not be zero"
+        );
+
+        assert_eq!(
+            reason(&test_invoice(), Some(9.into())),
+            "amount can be specified only for invoice without amount"
+        );
+        assert_eq!(
+            reason(&amountless, None),
+            "invoice has no amount, amount to pay must be specified"
+        );
+        let signed = crate::bolt11::test_invoice("+90n", now, hash, 3600);
+        assert!(reason(&signed, None).starts_with("invalid amount"));
+    }
+
+    #[test]
+    fn test_check_quote_amount() {
+        let quote = |amount: u64, unit: &str| -> MeltQuote {
+            let mut quote = melt_quote("UNPAID", None, &[]);
+            quote["amount"] = amount.into();
+            quote["unit"] = unit.into();
+            serde_json::from_value(quote).unwrap()
+        };
+
+        assert!(Wallet::check_quote_amount(&quote(9, "sat"), 9_000).is_ok());
+        // fraction of sat may be rounded either way
+        assert!(Wallet::check_quote_amount(&quote(9, "sat"), 9_500).is_ok());
+        assert!(Wallet::check_quote_amount(&quote(10, "sat"), 9_500).is_ok());
+        assert!(Wallet::check_quote_amount(&quote(9_500, "msat"), 9_500).is_ok());
+        // fiat amounts depend on exchange rate of the mint
+        assert!(Wallet::check_quote_amount(&quote(1, "usd"), 9_000).is_ok());
+
+        assert!(matches!(
+            Wallet::check_quote_amount(&quote(10, "sat"), 9_000),
+            Err(WalletError::QuoteMismatch { invoice, quote }) if invoice == 9 && quote == 10
+        ));
+        assert!(matches!(
+            Wallet::check_quote_amount(&quote(8, "sat"), 9_500),
+            Err(WalletError::QuoteMismatch { invoice, quote }) if invoice == 10 && quote == 8
+        ));
+        assert!(matches!(
+            Wallet::check_quote_amount(&quote(9_499, "msat"), 9_500),
+            Err(WalletError::QuoteMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_checked_fees_and_amounts() {
+        assert_eq!(input_fee([]).unwrap(), 0);
+        assert_eq!(input_fee([100, 100, 801]).unwrap(), 2);
+        assert!(input_fee([u64::MAX, 1]).is_err());
+
+        // amount in msat overflows before quote is requested
+        let mock = MockMint::start(|_| (404, serde_json::json!({})));
+        let mut w = test_wallet(mock.url(), &[("00aaaaaaaaaaaaaa", "sat", true, 0)]);
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let amountless = crate::bolt11::test_invoice("", now, [0; 32], 3600);
+        assert!(matches!(
+            block_on(w.melt_tokens(&amountless, Some(u64::MAX.into()), &CurrencyUnit::Sat)),
+            Err(WalletError::InvalidInvoice(_))
+        ));
+        assert!(mock.requests("/v1/melt/quote/bolt11").is_empty());
+    }
 }
@@ -0,0 +1,640 @@
+//! Selection of proofs for spending
+
+use anyhow::anyhow;
+
+use super::{Result, Wallet, WalletError};
+
+/// Maximum number of explored branches, bounds the search for wallets holding many proofs
+const MAX_TRIES: usize = 100_000;
+
+/// Maximum number of rounds settling input fee of swap outputs, see [`plan_spend`]
+pub(super) const MAX_FEE_ROUNDS: usize = 8;
+
+/// Proof considered for spending
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Candidate {
+    pub amount: u64,
+    /// Input fee of the proof keyset in parts per thousand
+    pub fee_ppk: u64,
+    /// Is the proof keyset active?
+    pub active: bool,
+}
+
+/// Candidates selected by [`CoinSelector`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Selection {
+    /// Indices of selected candidates
+    pub indices: Vec<usize>,
+    /// Sum of selected amounts
+    pub total: u64,
+    /// Input fee of selected candidates
+    pub fee: u64,
+}
+
+/// How to spend proofs to pay exactly the target amount, see [`plan_spend`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpendPlan {
+    /// Indices of candidates spent as they are
+    pub inputs: Vec<usize>,
+    /// Candidate which has to be swapped for exact amount first
+    pub swap: Option<SwapPlan>,
+    /// Input fee of all spent proofs, zero if the fee is not paid by the spender
+    pub input_fee: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SwapPlan {
+    /// Index of swapped candidate
+    pub input: usize,
+    /// Amount of swap outputs which are spent
+    pub spend: u64,
+    /// Amount of swap outputs kept as change
+    pub change: u64,
+    /// Input fee of the swap
+    pub fee: u64,
+}
+
+/// Policy of selecting proofs for spending
+pub trait CoinSelector: Send + Sync {
+    /// Selects candidates covering `target`, and their own input fee too if `pay_input_fee` is set.
+    /// Candidates are ordered from the oldest. Returns `None` if the candidates are not enough.
+    fn select(
+        &self,
+        candidates: &[Candidate],
+        target: u64,
+        pay_input_fee: bool,
+    ) -> Option<Selection>;
+}
+
+/// Built-in coin selection strategies
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum CoinSelection {
+    /// Minimizes input fee plus change, i.e. the amount exceeding what is needed
+    #[default]
+    MinFee,
+    /// Minimizes number of spent proofs, then fee plus change
+    MinProofs,
+    /// Spends proofs from inactive keysets first, then minimizes fee plus change
+    PreferInactive,
+    /// Spends the oldest proofs first
+    Oldest,
+}
+
+impl CoinSelection {
+    pub const ALL: [CoinSelection; 4] = [
+        Self::MinFee,
+        Self::MinProofs,
+        Self::PreferInactive,
+        Self::Oldest,
+    ];
+}
+
+impl CoinSelector for CoinSelection {
+    fn select(
+        &self,
+        candidates: &[Candidate],
+        target: u64,
+        pay_input_fee: bool,
+    ) -> Option<Selection> {
+        // largest amounts first, so the first solution is found quickly
+        let mut order = (0..candidates.len()).collect::<Vec<_>>();
+        order.sort_by_key(|i| {
+            let c = &candidates[*i];
+            (std::cmp::Reverse(c.amount), c.fee_ppk)
+        });
+
+        match self {
+            Self::MinFee => branch_and_bound(candidates, order, target, pay_input_fee, |_| 0),
+            Self::MinProofs => branch_and_bound(candidates, order, target, pay_input_fee, |_| 1),
+            Self::PreferInactive => {
+                order.sort_by_key(|i| candidates[*i].active);
+                branch_and_bound(candidates, order, target, pay_input_fee, |c| {
+                    c.active as u64
+                })
+            }
+            Self::Oldest => oldest_first(candidates, target, pay_input_fee),
+        }
+    }
+}
+
+impl std::fmt::Display for CoinSelection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MinFee => write!(f, "min-fee"),
+            Self::MinProofs => write!(f, "min-proofs"),
+            Self::PreferInactive => write!(f, "prefer-inactive"),
+            Self::Oldest => write!(f, "oldest"),
+        }
+    }
+}
+
+impl std::str::FromStr for CoinSelection {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|strategy| strategy.to_string() == s)
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Unknown coin selection '{}', expected one of: {}",
+                    s,
+                    Self::ALL.map(|strategy| strategy.to_string()).join(", ")
+                )
+            })
+    }
+}
+
+fn fee(fee_ppk: u128) -> u128 {
+    fee_ppk.div_ceil(1000)
+}
+
+/// Branch and bound search over candidates in `order` minimizing sum of `penalty` of selected
+/// candidates first, then input fee plus change.
+/// Number of explored branches is bounded, the best selection found so far is returned then.
+fn branch_and_bound(
+    candidates: &[Candidate],
+    order: Vec<usize>,
+    target: u64,
+    pay_input_fee: bool,
+    penalty: impl Fn(&Candidate) -> u64,
+) -> Option<Selection> {
+    let needed = |fee_ppk: u128| target as u128 + if pay_input_fee { fee(fee_ppk) } else { 0 };
+
+    // remaining[pos] is sum of amounts at positions pos..
+    let mut remaining = vec![0u128; order.len() + 1];
+    for pos in (0..order.len()).rev() {
+        remaining[pos] = remaining[pos + 1] + candidates[order[pos]].amount as u128;
+    }
+
+    let mut best: Option<((u64, u128), Vec<usize>)> = None;
+    let mut path: Vec<usize> = vec![];
+    let mut total = 0u128;
+    let mut fee_ppk = 0u128;
+    let mut penalties = 0u64;
+    let mut pos = 0;
+
+    for _ in 0..MAX_TRIES {
+        let backtrack = if total >= needed(fee_ppk) {
+            let cost = (penalties, fee(fee_ppk) + total - needed(fee_ppk));
+            if best.as_ref().is_none_or(|(best_cost, _)| cost < *best_cost) {
+                best = Some((cost, path.clone()));
+            }
+            true
+        } else {
+            // adding candidates only increases penalties and fee, so they are a lower bound of the cost
+            pos == order.len()
+                || total + remaining[pos] < needed(fee_ppk)
+                || best
+                    .as_ref()
+                    .is_some_and(|(best_cost, _)| (penalties, fee(fee_ppk)) >= *best_cost)
+        };
+
+        if !backtrack {
+            let c = &candidates[order[pos]];
+            path.push(pos);
+            total += c.amount as u128;
+            fee_ppk += c.fee_ppk as u128;
+            penalties += penalty(c);
+            pos += 1;
+            continue;
+        }
+
+        if best.as_ref().is_some_and(|(cost, _)| *cost == (0, 0)) {
+            break;
+        }
+
+        // exclude the last included candidate and continue with the next one,
+        // candidates equal to the excluded one would only repeat already explored branches
+        let mut exhausted = true;
+        while let Some(last) = path.pop() {
+            let c = &candidates[order[last]];
+            total -= c.amount as u128;
+            fee_ppk -= c.fee_ppk as u128;
+            penalties -= penalty(c);
+
+            pos = last + 1;
+            while pos < order.len() && candidates[order[pos]] == *c {
+                pos += 1;
+            }
+            if pos < order.len() {
+                exhausted = false;
+                break;
+            }
+        }
+        if exhausted {
+            break;
+        }
+    }
+
+    let (_, path) = best?;
+    selection(candidates, path.iter().map(|pos| order[*pos]).collect())
+}
+
+/// Takes candidates in their order until they cover `target`
+fn oldest_first(candidates: &[Candidate], target: u64, pay_input_fee: bool) -> Option<Selection> {
+    let mut indices = vec![];
+    let mut total = 0u128;
+    let mut fee_ppk = 0u128;
+
+    for (i, c) in candidates.iter().enumerate() {
+        if total >= target as u128 + if pay_input_fee { fee(fee_ppk) } else { 0 } {
+            break;
+        }
+        indices.push(i);
+        total += c.amount as u128;
+        fee_ppk += c.fee_ppk as u128;
+    }
+
+    let selection = selection(candidates, indices)?;
+    let fee = if pay_input_fee { selection.fee } else { 0 };
+    (selection.total >= target.checked_add(fee)?).then_some(selection)
+}
+
+fn selection(candidates: &[Candidate], indices: Vec<usize>) -> Option<Selection> {
+    let total = indices
+        .iter()
+        .map(|i| candidates[*i].amount as u128)
+        .sum::<u128>();
+    let fee_ppk = indices
+        .iter()
+        .map(|i| candidates[*i].fee_ppk as u128)
+        .sum::<u128>();
+
+    Some(Selection {
+        indices,
+        total: total.try_into().ok()?,
+        fee: fee(fee_ppk).try_into().ok()?,
+    })
+}
+
+/// Plans spending of exactly `target`, plus input fee of spent proofs if `pay_input_fee` is set.
+///
+/// If no combination of candidates matches exactly, one selected candidate is swapped first
+/// for outputs of the spent amount and change. Outputs of the swap are in the active keyset
+/// with `active_fee_ppk` input fee and amounts of `denominations`.
+pub fn plan_spend(
+    selector: &dyn CoinSelector,
+    candidates: &[Candidate],
+    target: u64,
+    active_fee_ppk: u64,
+    denominations: &[u64],
+    pay_input_fee: bool,
+) -> Result<SpendPlan> {
+    let insufficient = || {
+        let available = candidates
+            .iter()
+            .fold(0u64, |acc, c| acc.saturating_add(c.amount));
+        let fee_ppk = candidates
+            .iter()
+            .fold(0u64, |acc, c| acc.saturating_add(c.fee_ppk));
+        let fee = if pay_input_fee {
+            fee_ppk.div_ceil(1000)
+        } else {
+            0
+        };
+        WalletError::InsufficientFunds {
+            requested: target.saturating_add(fee).into(),
+            available: available.into(),
+        }
+    };
+
+    let selection = selector
+        .select(candidates, target, pay_input_fee)
+        .ok_or_else(insufficient)?;
+    let input_fee = if pay_input_fee { selection.fee } else { 0 };
+
+    // selection of custom selector may not cover the target
+    let excess = selection
+        .total
+        .checked_sub(target)
+        .and_then(|excess| excess.checked_sub(input_fee))
+        .ok_or_else(insufficient)?;
+    if excess == 0 {
+        return Ok(SpendPlan {
+            inputs: selection.indices,
+            swap: None,
+            input_fee,
+        });
+    }
+
+    // swap the smallest selected candidate which is able to cover the excess
+    let mut swappable = selection
+        .indices
+        .iter()
+        .copied()
+        .filter(|i| candidates[*i].amount > excess)
+        .collect::<Vec<_>>();
+    swappable.sort_by_key(|i| candidates[*i].amount);
+
+    for swapped in swappable {
+        let others = selection
+            .indices
+            .iter()
+            .copied()
+            .filter(|i| *i != swapped)
+            .collect::<Vec<_>>();
+        let sum = |value: fn(&Candidate) -> u64| {
+            others
+                .iter()
+                .try_fold(0u64, |acc, i| acc.checked_add(value(&candidates[*i])))
+                .ok_or_else(|| anyhow!("Amount overflow"))
+        };
+        let others_amount = sum(|c| c.amount)?;
+        let others_fee_ppk = sum(|c| c.fee_ppk)?;
+        let spend_with_fee = |input_fee: u64| {
+            target
+                .saturating_add(input_fee)
+                .saturating_sub(others_amount)
+        };
+
+        // fee depends on number of swap outputs and they depend on the fee, a few rounds settle it
+        let mut input_fee = 0;
+        if pay_input_fee {
+            let mut settled = false;
+            for _ in 0..MAX_FEE_ROUNDS {
+                let outputs =
+                    Wallet::split_amount(spend_with_fee(input_fee), denominations)?.len() as u64;
+                let fee = outputs
+                    .checked_mul(active_fee_ppk)
+                    .and_then(|fee_ppk| fee_ppk.checked_add(others_fee_ppk))
+                    .ok_or_else(|| anyhow!("Input fee overflow"))?
+                    .div_ceil(1000);
+                if fee == input_fee {
+                    settled = true;
+                    break;
+                }
+                input_fee = fee;
+            }
+            if !settled {
+                return Err(anyhow!(
+                    "Input fee of swap outputs for spending {} did not settle in {} rounds",
+                    target,
+                    MAX_FEE_ROUNDS
+                )
+                .into());
+            }
+        }
+        let spend = spend_with_fee(input_fee);
+
+        let candidate = &candidates[swapped];
+        let swap_fee = candidate.fee_ppk.div_ceil(1000);
+        let Some(change) = candidate
+            .amount
+            .checked_sub(spend)
+            .and_then(|change| change.checked_sub(swap_fee))
+        else {
+            continue;
+        };
+        if spend == 0 {
+            continue;
+        }
+
+        // fail before any swap if the outputs can not be created
+        Wallet::split_amount(spend, denominations)?;
+        Wallet::split_amount(change, denominations)?;
+
+        return Ok(SpendPlan {
+            inputs: others,
+            swap: Some(SwapPlan {
+                input: swapped,
+                spend,
+                change,
+                fee: swap_fee,
+            }),
+            input_fee,
+        });
+    }
+
+    Err(insufficient())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn powers_of_two() -> Vec<u64> {
+        (0..32).map(|x| 1 << x).collect()
+    }
+
+    fn candidates(amounts: &[u64], fee_ppk: u64) -> Vec<Candidate> {
+        amounts
+            .iter()
+            .map(|amount| Candidate {
+                amount: *amount,
+                fee_ppk,
+                active: true,
+            })
+            .collect()
+    }
+
+    fn select(candidates: &[Candidate], target: u64, pay_input_fee: bool) -> Option<Selection> {
+        CoinSelection::MinFee.select(candidates, target, pay_input_fee)
+    }
+
+    fn selected_amounts(candidates: &[Candidate], selection: &Selection) -> Vec<u64> {
+        let mut amounts = selection
+            .indices
+            .iter()
+            .map(|i| candidates[*i].amount)
+            .collect::<Vec<_>>();
+        amounts.sort();
+        amounts.reverse();
+        amounts
+    }
+
+    #[test]
+    fn test_select() {
+        let c = candidates(
+            &[
+                65536, 65536, 32768, 32768, 16384, 16384, 16384, 16384, 8192, 8192, 4096, 2048,
+                2048, 1024, 1024, 1024, 1024, 1024, 512, 512, 512, 512, 512, 256, 256, 256, 128,
+                128, 64, 64, 32, 32, 32, 16, 16, 16, 8, 1, 1,
+            ],
+            0,
+        );
+
+        let exact = |target| {
+            let selection = select(&c, target, false).unwrap();
+            assert_eq!(selection.total, target);
+            selected_amounts(&c, &selection)
+        };
+        assert_eq!(exact(40), vec![32, 8]);
+        assert_eq!(exact(256), vec![256]);
+        assert_eq!(exact(576), vec![512, 64]);
+        assert_eq!(exact(42), vec![32, 8, 1, 1]);
+        assert_eq!(exact(252504).iter().sum::<u64>(), 252504);
+
+        // no exact match, the smallest change
+        let selection = select(&c, 7, false).unwrap();
+        assert_eq!(selected_amounts(&c, &selection), vec![8]);
+        let selection = select(&c, 579, false).unwrap();
+        assert_eq!(selection.total, 584);
+
+        assert_eq!(select(&c, 400_000, false), None);
+    }
+
+    #[test]
+    fn test_select_with_fees() {
+        // 4 + 2 + 1 pay 1 sat fee
+        let c = candidates(&[1, 2, 4], 100);
+        let selection = select(&c, 6, true).unwrap();
+        assert_eq!((selection.total, selection.fee), (7, 1));
+        assert_eq!(select(&c, 7, true), None);
+
+        // cheap proof is preferred over an expensive one of the same amount
+        let c = vec![
+            Candidate {
+                amount: 8,
+                fee_ppk: 1000,
+                active: true,
+            },
+            Candidate {
+                amount: 8,
+                fee_ppk: 0,
+                active: true,
+            },
+        ];
+        assert_eq!(select(&c, 8, false).unwrap().indices, vec![1]);
+    }
+
+    #[test]
+    fn test_select_is_bounded() {
+        // huge amounts and many equal proofs must neither allocate by amount nor search forever
+        let c = candidates(&[u64::MAX / 2, u64::MAX / 2, 1 << 40], 0);
+        let selection = select(&c, u64::MAX - 1, false).unwrap();
+        assert_eq!(selection.total, u64::MAX - 1);
+
+        let c = candidates(&vec![3; 5000], 100);
+        let selection = select(&c, 10_000, true).unwrap();
+        assert!(selection.total >= 10_000 + selection.fee);
+    }
+
+    #[test]
+    fn test_strategies() {
+        let mut c = candidates(&[1, 1, 1, 1, 4], 0);
+        c[0].active = false;
+        c[1].active = false;
+
+        let select = |strategy: CoinSelection, target| {
+            let mut indices = strategy.select(&c, target, false).unwrap().indices;
+            indices.sort();
+            indices
+        };
+        assert_eq!(select(CoinSelection::MinFee, 3), vec![0, 1, 2]);
+        assert_eq!(select(CoinSelection::MinProofs, 3), vec![4]);
+        assert_eq!(select(CoinSelection::Oldest, 3), vec![0, 1, 2]);
+        assert_eq!(select(CoinSelection::Oldest, 5), vec![0, 1, 2, 3, 4]);
+        // inactive proofs are spent even if it costs change
+        assert_eq!(select(CoinSelection::PreferInactive, 1), vec![0]);
+        assert_eq!(select(CoinSelection::PreferInactive, 3), vec![0, 1, 2]);
+        assert_eq!(select(CoinSelection::PreferInactive, 5), vec![0, 4]);
+
+        for strategy in CoinSelection::ALL {
+            assert_eq!(
+                strategy.to_string().parse::<CoinSelection>().unwrap(),
+                strategy
+            );
+            assert_eq!(strategy.select(&c, 9, false), None);
+        }
+        assert!("largest".parse::<CoinSelection>().is_err());
+    }
+
+    #[test]
+    fn test_plan_spend() {
+        // exact match
+        let c = candidates(&[1, 2, 4], 0);
+        let plan = plan_spend(&CoinSelection::MinFee, &c, 3, 0, &powers_of_two(), true).unwrap();
+        assert_eq!(plan.swap, None);
+        assert_eq!(plan.inputs.len(), 2);
+
+        // 4 is swapped for 3 to spend and 1 of change
+        let c = candidates(&[4, 8], 0);
+        let plan = plan_spend(&CoinSelection::MinFee, &c, 3, 0, &powers_of_two(), false).unwrap();
+        assert!(plan.inputs.is_empty());
+        assert_eq!(
+            plan.swap,
+            Some(SwapPlan {
+                input: 0,
+                spend: 3,
+                change: 1,
+                fee: 0
+            })
+        );
+
+        // 8 is swapped (1 sat fee) for 5 = 4 + 1 outputs paying 1 sat fee of spending
+        let c = vec![
+            Candidate {
+                amount: 2,
+                fee_ppk: 100,
+                active: true,
+            },
+            Candidate {
+                amount: 8,
+                fee_ppk: 900,
+                active: true,
+            },
+        ];
+        let plan = plan_spend(&CoinSelection::MinFee, &c, 4, 100, &powers_of_two(), true).unwrap();
+        assert_eq!(plan.input_fee, 1);
+        assert_eq!(
+            plan.swap,
+            Some(SwapPlan {
+                input: 1,
+                spend: 5,
+                change: 2,
+                fee: 1
+            })
+        );
+
+        assert!(matches!(
+            plan_spend(
+                &CoinSelection::MinFee,
+                &candidates(&[1, 2, 4], 100),
+                7,
+                100,
+                &powers_of_two(),
+                true
+            ),
+            Err(WalletError::InsufficientFunds {
+                requested,
+                available
+            }) if requested == 8 && available == 7
+        ));
+
+        // 1 sat fee per output: outputs of 6 + fee never number exactly the fee
+        let c = candidates(&[8], 0);
+        assert!(matches!(
+            plan_spend(&CoinSelection::MinFee, &c, 6, 1000, &powers_of_two(), true),
+            Err(WalletError::Other(_))
+        ));
+
+        // selection not covering the target is rejected
+        struct Short;
+        impl CoinSelector for Short {
+            fn select(&self, _: &[Candidate], _: u64, _: bool) -> Option<Selection> {
+                Some(Selection {
+                    indices: vec![0],
+                    total: 1,
+                    fee: 1,
+                })
+            }
+        }
+        assert!(matches!(
+            plan_spend(
+                &Short,
+                &candidates(&[1, 2], 1000),
+                2,
+                0,
+                &powers_of_two(),
+                true
+            ),
+            Err(WalletError::InsufficientFunds { .. })
+        ));
+
+        // keyset without 1 sat proofs can not create change of 1
+        let c = candidates(&[4], 0);
+        assert!(plan_spend(&CoinSelection::MinFee, &c, 2, 0, &[2, 4, 8], false).is_ok());
+        assert!(plan_spend(&CoinSelection::MinFee, &c, 3, 0, &[2, 4, 8], false).is_err());
+    }
+}
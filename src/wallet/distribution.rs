@@ -0,0 +1,190 @@
+//! Target distribution of proof denominations, so spending rarely needs a swap for change
+
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+
+use super::{
+    Wallet,
+    coin_selection::{Candidate, MAX_FEE_ROUNDS},
+    input_fee,
+};
+
+/// Splits `amount` into `denominations` so that a wallet holding proofs of `held` amounts gets
+/// closer to `per_denomination` proofs of each denomination. Missing smaller denominations are
+/// filled first, the rest is split into as few proofs as possible. Returned amounts are in ascending order.
+pub fn split_toward_target(
+    amount: u64,
+    held: &[u64],
+    per_denomination: u32,
+    denominations: &[u64],
+) -> anyhow::Result<Vec<u64>> {
+    let mut counts = BTreeMap::<u64, u32>::new();
+    for amount in held {
+        *counts.entry(*amount).or_default() += 1;
+    }
+
+    let mut amounts = vec![];
+    let mut remaining = amount;
+    let mut ascending = denominations.to_vec();
+    ascending.sort();
+    for denomination in ascending.into_iter().filter(|d| *d > 0) {
+        if denomination > remaining {
+            break;
+        }
+        let missing =
+            per_denomination.saturating_sub(counts.get(&denomination).copied().unwrap_or(0));
+        let count = (missing as u64).min(remaining / denomination);
+        amounts.extend(std::iter::repeat_n(denomination, count as usize));
+        remaining -= count * denomination;
+    }
+    match Wallet::split_amount(remaining, denominations) {
+        Ok(rest) => amounts.extend(rest),
+        // filled denominations may leave a remainder which can not be composed, skip the target then
+        Err(_) => amounts = Wallet::split_amount(amount, denominations)?,
+    }
+
+    amounts.sort();
+    Ok(amounts)
+}
+
+/// Returns indices of `candidates` to swap so that their total amount, less the input fee of the swap,
+/// ends up in the target distribution. Proofs already matching the target are kept, proofs from
+/// inactive keysets are always swapped.
+pub fn rebalance_inputs(
+    candidates: &[Candidate],
+    per_denomination: u32,
+    denominations: &[u64],
+) -> anyhow::Result<Vec<usize>> {
+    let total = candidates
+        .iter()
+        .try_fold(0u64, |acc, c| acc.checked_add(c.amount))
+        .context("Amount overflow")?;
+
+    // the fee depends on the swapped proofs and changes the target, settle it in a few rounds
+    let mut fee = 0;
+    let mut inputs = vec![];
+    for _ in 0..MAX_FEE_ROUNDS {
+        let Some(target_total) = total.checked_sub(fee) else {
+            break;
+        };
+        inputs = inputs_off_target(
+            candidates,
+            &split_toward_target(target_total, &[], per_denomination, denominations)?,
+        );
+
+        let swap_fee = input_fee(inputs.iter().map(|i| candidates[*i].fee_ppk))?.value();
+        if swap_fee == fee {
+            break;
+        }
+        fee = swap_fee;
+    }
+
+    Ok(inputs)
+}
+
+/// Returns indices of `candidates` not matching `target` amounts
+fn inputs_off_target(candidates: &[Candidate], target: &[u64]) -> Vec<usize> {
+    let mut wanted = BTreeMap::<u64, u32>::new();
+    for amount in target {
+        *wanted.entry(*amount).or_default() += 1;
+    }
+
+    let mut inputs = vec![];
+    for (i, c) in candidates.iter().enumerate() {
+        match wanted.get_mut(&c.amount) {
+            Some(count) if *count > 0 && c.active => *count -= 1,
+            _ => inputs.push(i),
+        }
+    }
+    inputs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn powers_of_two() -> Vec<u64> {
+        (0..32).map(|x| 1 << x).collect()
+    }
+
+    #[test]
+    fn test_split_toward_target() {
+        // no target keeps as few proofs as possible
+        assert_eq!(
+            split_toward_target(11, &[], 0, &powers_of_two()).unwrap(),
+            vec![1, 2, 8]
+        );
+
+        assert_eq!(
+            split_toward_target(11, &[], 2, &powers_of_two()).unwrap(),
+            vec![1, 1, 1, 2, 2, 4]
+        );
+        assert_eq!(
+            split_toward_target(100, &[], 3, &powers_of_two()).unwrap(),
+            vec![1, 1, 1, 1, 2, 2, 2, 2, 4, 4, 4, 4, 8, 8, 8, 16, 16, 16]
+        );
+
+        // only missing denominations are filled
+        assert_eq!(
+            split_toward_target(11, &[1, 1, 2], 2, &powers_of_two()).unwrap(),
+            vec![1, 2, 4, 4]
+        );
+        assert_eq!(
+            split_toward_target(3, &[1, 1, 2, 2], 2, &powers_of_two()).unwrap(),
+            vec![1, 2]
+        );
+
+        // remainder of other denominations is split even if the target can not be reached
+        assert_eq!(
+            split_toward_target(9, &[], 2, &[3, 5]).unwrap(),
+            vec![3, 3, 3]
+        );
+        assert!(split_toward_target(7, &[], 2, &[3, 5]).is_err());
+
+        for amount in [0, 1, 7, 1000, 123_456, 1 << 40] {
+            let split = split_toward_target(amount, &[2], 5, &powers_of_two()).unwrap();
+            assert_eq!(split.iter().sum::<u64>(), amount);
+        }
+    }
+
+    #[test]
+    fn test_rebalance_inputs() {
+        let candidates = |amounts: &[u64]| {
+            amounts
+                .iter()
+                .map(|amount| Candidate {
+                    amount: *amount,
+                    fee_ppk: 0,
+                    active: true,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // 11 = 1 + 1 + 1 + 2 + 2 + 4 with 2 proofs per denomination, only 8 is swapped
+        assert_eq!(
+            rebalance_inputs(&candidates(&[1, 2, 8]), 2, &powers_of_two()).unwrap(),
+            vec![2]
+        );
+        assert!(
+            rebalance_inputs(&candidates(&[2, 1, 4, 1, 2]), 2, &powers_of_two())
+                .unwrap()
+                .is_empty()
+        );
+
+        // consolidation into as few proofs as possible
+        assert_eq!(
+            rebalance_inputs(&candidates(&[1, 1, 1, 8]), 0, &powers_of_two()).unwrap(),
+            vec![1, 2]
+        );
+
+        let mut c = candidates(&[1, 2]);
+        c[1].active = false;
+        assert_eq!(rebalance_inputs(&c, 0, &powers_of_two()).unwrap(), vec![1]);
+
+        // with 1 sat fee per input 8 - 1 = 4 + 2 + 1, so only one of the proofs is swapped
+        let mut c = candidates(&[4, 4]);
+        c.iter_mut().for_each(|c| c.fee_ppk = 1000);
+        assert_eq!(rebalance_inputs(&c, 0, &powers_of_two()).unwrap(), vec![1]);
+    }
+}
@@ -0,0 +1,132 @@
+use crate::{cashu::Amount, mint::MintError};
+
+/// Error returned by [`super::Wallet`] operations
+#[derive(Debug)]
+pub enum WalletError {
+    /// Wallet does not hold enough funds (including fees) for the operation
+    InsufficientFunds {
+        requested: Amount,
+        available: Amount,
+    },
+    /// Quote was not paid yet
+    QuoteNotPaid(String),
+    /// Quote expired before it was paid
+    QuoteExpired(String),
+    /// Lightning invoice could not be decoded, has expired or has no amount
+    InvalidInvoice(String),
+    /// Amount quoted by mint does not match amount of the invoice
+    QuoteMismatch { invoice: Amount, quote: Amount },
+    /// Mint rejected the request or could not be reached
+    Mint(MintError),
+    /// DLEQ proof provided by mint or in received token is not valid
+    Dleq(anyhow::Error),
+    /// Wallet data could not be loaded or saved
+    Storage(anyhow::Error),
+    /// Cryptographic operation failed
+    Crypto(anyhow::Error),
+    /// Password does not match the wallet
+    InvalidPassword,
+    /// Any other failure
+    Other(anyhow::Error),
+}
+
+impl std::fmt::Display for WalletError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InsufficientFunds {
+                requested,
+                available,
+            } => write!(
+                f,
+                "Insufficient funds, requested: {} including fees; available: {}",
+                requested, available
+            ),
+            Self::QuoteNotPaid(quote_id) => write!(f, "Quote {} is not paid", quote_id),
+            Self::QuoteExpired(quote_id) => write!(f, "Quote {} has expired", quote_id),
+            Self::InvalidInvoice(reason) => write!(f, "Invalid invoice: {}", reason),
+            Self::QuoteMismatch { invoice, quote } => write!(
+                f,
+                "Quote amount {} does not match invoice amount {}",
+                quote, invoice
+            ),
+            Self::Mint(e) => write!(f, "Mint error: {}", e),
+            Self::Dleq(e) => write!(f, "DLEQ validation failed: {}", e),
+            Self::Storage(e) => write!(f, "Wallet storage error: {}", e),
+            Self::Crypto(e) => write!(f, "Cryptographic operation failed: {}", e),
+            Self::InvalidPassword => write!(f, "Invalid password!"),
+            Self::Other(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for WalletError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Mint(e) => e.source(),
+            Self::Dleq(e) | Self::Storage(e) | Self::Crypto(e) | Self::Other(e) => e.source(),
+            _ => None,
+        }
+    }
+}
+
+impl From<MintError> for WalletError {
+    fn from(e: MintError) -> Self {
+        Self::Mint(e)
+    }
+}
+
+impl From<anyhow::Error> for WalletError {
+    /// Recovers typed errors wrapped in `anyhow::Error` by internal wallet code
+    fn from(err: anyhow::Error) -> Self {
+        let err = match err.downcast::<WalletError>() {
+            Ok(e) => return e,
+            Err(err) => err,
+        };
+
+        match err.downcast::<MintError>() {
+            Ok(e) => Self::Mint(e),
+            Err(err) => Self::Other(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_anyhow_recovers_typed_errors() {
+        let err: anyhow::Error = WalletError::InsufficientFunds {
+            requested: 10.into(),
+            available: 5.into(),
+        }
+        .into();
+        assert!(matches!(
+            WalletError::from(err.context("melt")),
+            WalletError::InsufficientFunds {
+                requested,
+                available
+            } if requested == 10 && available == 5
+        ));
+
+        let err = anyhow::Error::from(MintError::TokenAlreadySpent).context("swap proofs");
+        assert!(matches!(
+            WalletError::from(err),
+            WalletError::Mint(MintError::TokenAlreadySpent)
+        ));
+
+        let err = anyhow::anyhow!("something else");
+        assert!(matches!(WalletError::from(err), WalletError::Other(_)));
+    }
+
+    #[test]
+    fn test_display_includes_cause() {
+        let err = WalletError::Storage(anyhow::anyhow!("disk full").context("save proofs"));
+        assert_eq!(err.to_string(), "Wallet storage error: save proofs");
+        // the rest of the cause follows in the chain of sources, without repeating
+        assert_eq!(
+            format!("{:#}", anyhow::Error::from(err)),
+            "Wallet storage error: save proofs: disk full"
+        );
+    }
+}
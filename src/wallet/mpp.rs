@@ -0,0 +1,170 @@
+//! Multi-path payments (NUT-15): one invoice paid by partial melts from wallets at several mints
+
+use anyhow::{Context, anyhow};
+use futures_util::future::join_all;
+
+use super::{Result, Wallet, WalletError};
+use crate::cashu::{
+    Amount, CurrencyUnit,
+    types::{MeltOptions, MeltQuote},
+};
+
+impl Wallet {
+    /// Pays `invoice` by partial melts from all `wallets` running concurrently.
+    /// Invoice amount is split proportionally to sat balances of wallets, wallets without funds are skipped.
+    ///
+    /// Returns result of melt for each participating wallet name. Partial failures do not lose inputs:
+    /// inputs of failed melts are returned to their wallets, melts that did not finish
+    /// stay pending and can be resolved by [`Wallet::melt_status`] of their wallet.
+    pub async fn melt_mpp(
+        wallets: &mut [&mut Wallet],
+        invoice: &str,
+    ) -> Result<Vec<(String, Result<MeltQuote>)>> {
+        let quotes = Self::create_mpp_quotes(wallets, invoice).await?;
+
+        let melts =
+            wallets
+                .iter_mut()
+                .zip(quotes)
+                .filter_map(|(wallet, quote)| quote.map(|quote| (wallet, quote)))
+                .map(|(wallet, quote)| async move {
+                    (wallet.name.clone(), wallet.melt_quote(quote).await)
+                });
+
+        Ok(join_all(melts).await)
+    }
+
+    /// Requests partial melt quotes for all `wallets`, `None` for wallets not taking part in payment.
+    /// Fails before any proofs are spent if some wallet can not pay its part.
+    pub async fn create_mpp_quotes(
+        wallets: &mut [&mut Wallet],
+        invoice: &str,
+    ) -> Result<Vec<Option<MeltQuote>>> {
+        let invoice_msat = Self::validate_invoice(invoice, None)?
+            .amount_msat
+            .unwrap_or_default();
+
+        let balances = wallets
+            .iter()
+            .map(|wallet| wallet.balance(&CurrencyUnit::Sat))
+            .collect::<Result<Vec<_>>>()?;
+        let parts = Self::split_mpp(invoice_msat, &balances)?;
+
+        let mut quotes = vec![];
+        for (wallet, part) in wallets.iter_mut().zip(parts) {
+            let quote = match part {
+                0 => None,
+                part => Some(wallet.create_mpp_quote(invoice, part).await?),
+            };
+            quotes.push(quote);
+        }
+
+        Ok(quotes)
+    }
+
+    /// Requests quote for paying `amount_msat` part of `invoice` (NUT-15)
+    pub async fn create_mpp_quote(&mut self, invoice: &str, amount_msat: u64) -> Result<MeltQuote> {
+        Self::validate_invoice(invoice, None)?;
+
+        if !self.mint_info().await?.supports_mpp(&CurrencyUnit::Sat) {
+            return Err(WalletError::Other(anyhow!(
+                "Mint {} does not support multi-path payments",
+                self.mint_url()
+            )));
+        }
+
+        let options = MeltOptions::Mpp {
+            amount: amount_msat,
+        };
+        let quote = self
+            .mint
+            .create_melt_quote(invoice, &CurrencyUnit::Sat, Some(options))
+            .await
+            .context("create_melt_quote")?;
+
+        Self::check_quote_amount(&quote, amount_msat)?;
+
+        let available = self.balance(&CurrencyUnit::Sat)?;
+        let requested = quote.amount.try_add(quote.fee_reserve)?;
+        if available < requested {
+            return Err(WalletError::InsufficientFunds {
+                requested,
+                available,
+            });
+        }
+
+        Ok(quote)
+    }
+
+    /// Pays previously created melt quote
+    pub async fn melt_quote(&mut self, quote: MeltQuote) -> Result<MeltQuote> {
+        let result = self.pay_melt_quote(quote).await;
+        self.release_reserved_proofs();
+        result
+    }
+
+    /// Splits `amount_msat` proportionally to `balances` (in sats), so all wallets keep the same
+    /// share of their balance for fees. Parts are in whole sats except the largest one.
+    pub fn split_mpp(amount_msat: u64, balances: &[Amount]) -> Result<Vec<u64>> {
+        let balances = balances.iter().map(|b| b.value()).collect::<Vec<_>>();
+        let total = balances.iter().map(|b| *b as u128).sum::<u128>();
+        if total * 1000 < amount_msat as u128 {
+            return Err(WalletError::InsufficientFunds {
+                requested: amount_msat.div_ceil(1000).into(),
+                available: (total as u64).into(),
+            });
+        }
+
+        let mut parts = balances
+            .iter()
+            .map(|b| (amount_msat as u128 * *b as u128 / total) as u64 / 1000 * 1000)
+            .collect::<Vec<_>>();
+
+        // rounding remainder goes to the largest balances
+        let mut remainder = amount_msat - parts.iter().sum::<u64>();
+        let mut order = (0..balances.len()).collect::<Vec<_>>();
+        order.sort_by_key(|i| std::cmp::Reverse(balances[*i]));
+        for i in order {
+            let add = remainder.min(balances[i].saturating_mul(1000) - parts[i]);
+            parts[i] += add;
+            remainder -= add;
+        }
+
+        Ok(parts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sats(balances: &[u64]) -> Vec<Amount> {
+        balances.iter().copied().map(Amount::from).collect()
+    }
+
+    #[test]
+    fn test_split_mpp() {
+        let parts = Wallet::split_mpp(100_000, &sats(&[100, 300])).unwrap();
+        assert_eq!(parts, vec![25_000, 75_000]);
+
+        let parts = Wallet::split_mpp(100_500, &sats(&[50, 0, 100])).unwrap();
+        assert_eq!(parts, vec![33_000, 0, 67_500]);
+        assert_eq!(parts.iter().sum::<u64>(), 100_500);
+
+        // balances summing over u64 do not overflow
+        let parts = Wallet::split_mpp(4000, &sats(&[u64::MAX, u64::MAX])).unwrap();
+        assert_eq!(parts, vec![2000, 2000]);
+
+        // everything must be used
+        let parts = Wallet::split_mpp(30_000, &sats(&[10, 20])).unwrap();
+        assert_eq!(parts, vec![10_000, 20_000]);
+
+        assert!(matches!(
+            Wallet::split_mpp(30_001, &sats(&[10, 20])),
+            Err(WalletError::InsufficientFunds {
+                requested,
+                available
+            }) if requested == 31 && available == 30
+        ));
+    }
+}